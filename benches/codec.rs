@@ -107,6 +107,17 @@ fn criterion_benchmark(c: &mut Criterion) {
     });
     encode.finish();
 
+    let single_content = mimi_content::MimiContent::simple_markdown_message(
+        "Hi everyone, we just shipped release 2.0. __Good  work__!".to_owned(),
+        [0x5e; 16],
+    );
+
+    let mut encode_single = c.benchmark_group("encode-single");
+    encode_single.bench_function("minicbor-encode", |b| {
+        b.iter(|| black_box(single_content.serialize().unwrap()));
+    });
+    encode_single.finish();
+
     let large_content = large_mimi_content();
     let large_content_bytes = large_content.serialize().unwrap();
 
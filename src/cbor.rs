@@ -4,6 +4,9 @@
 
 use std::{borrow::Cow, collections::BTreeMap};
 
+/// Maximum nesting depth of arrays and maps accepted when decoding a [`Value`].
+pub const MAX_VALUE_DEPTH: usize = 32;
+
 /// A sum type covering the CBOR values you actually need.
 /// Inspired from ciborium::Value but rewritten for minicbor.
 #[derive(Debug, Clone, PartialEq)]
@@ -55,9 +58,27 @@ impl<'b, C> minicbor::Decode<'b, C> for Value {
     fn decode(
         d: &mut minicbor::Decoder<'b>,
         _ctx: &mut C,
+    ) -> Result<Self, minicbor::decode::Error> {
+        Value::decode_at_depth(d, 0)
+    }
+}
+
+impl Value {
+    fn decode_at_depth(
+        d: &mut minicbor::Decoder<'_>,
+        depth: usize,
     ) -> Result<Self, minicbor::decode::Error> {
         use minicbor::data::Type;
-        match d.datatype()? {
+        let datatype = d.datatype()?;
+        if depth >= MAX_VALUE_DEPTH
+            && matches!(
+                datatype,
+                Type::Array | Type::ArrayIndef | Type::Map | Type::MapIndef
+            )
+        {
+            return Err(minicbor::decode::Error::message("value nested too deeply"));
+        }
+        match datatype {
             Type::Bool => Ok(Value::Bool(d.bool()?)),
             Type::U8 | Type::U16 | Type::U32 | Type::U64 => Ok(Value::Int(d.u64()? as i64)),
             Type::I8 | Type::I16 | Type::I32 | Type::I64 => Ok(Value::Int(d.i64()?)),
@@ -71,15 +92,21 @@ impl<'b, C> minicbor::Decode<'b, C> for Value {
             }
             Type::Array | Type::ArrayIndef => {
                 let len = d.array()?;
-                let mut arr = Vec::with_capacity(len.unwrap_or(0) as usize);
-                loop {
-                    if d.datatype()? == Type::Break {
-                        d.skip()?;
-                        break;
+                // Every element takes at least one byte, which bounds the
+                // capacity for declared lengths exceeding the input.
+                let remaining = d.input().len() - d.position();
+                let mut arr = Vec::with_capacity(len.unwrap_or(0).min(remaining as u64) as usize);
+                match len {
+                    Some(len) => {
+                        for _ in 0..len {
+                            arr.push(Value::decode_at_depth(d, depth + 1)?);
+                        }
                     }
-                    arr.push(Value::decode(d, _ctx)?);
-                    if len.is_some() && arr.len() == len.unwrap() as usize {
-                        break;
+                    None => {
+                        while d.datatype()? != Type::Break {
+                            arr.push(Value::decode_at_depth(d, depth + 1)?);
+                        }
+                        d.skip()?;
                     }
                 }
                 Ok(Value::Array(arr))
@@ -87,16 +114,23 @@ impl<'b, C> minicbor::Decode<'b, C> for Value {
             Type::Map | Type::MapIndef => {
                 let len = d.map()?;
                 let mut map = BTreeMap::new();
-                loop {
-                    if d.datatype()? == Type::Break {
-                        d.skip()?;
-                        break;
-                    }
+                let mut entry = |d: &mut minicbor::Decoder<'_>| {
                     let k = d.str()?.to_string();
-                    let v = Value::decode(d, _ctx)?;
+                    let v = Value::decode_at_depth(d, depth + 1)?;
                     map.insert(k, v);
-                    if len.is_some() && map.len() == len.unwrap() as usize {
-                        break;
+                    Ok::<_, minicbor::decode::Error>(())
+                };
+                match len {
+                    Some(len) => {
+                        for _ in 0..len {
+                            entry(d)?;
+                        }
+                    }
+                    None => {
+                        while d.datatype()? != Type::Break {
+                            entry(d)?;
+                        }
+                        d.skip()?;
                     }
                 }
                 Ok(Value::Map(map))
@@ -106,6 +140,48 @@ impl<'b, C> minicbor::Decode<'b, C> for Value {
     }
 }
 
+/// Skips one data item like [`minicbor::Decoder::skip`], but without
+/// allocating, and failing for arrays and maps nested deeper than
+/// [`MAX_VALUE_DEPTH`].
+pub(crate) fn skip_value(d: &mut minicbor::Decoder<'_>) -> Result<(), minicbor::decode::Error> {
+    skip_at_depth(d, 0)
+}
+
+fn skip_at_depth(
+    d: &mut minicbor::Decoder<'_>,
+    depth: usize,
+) -> Result<(), minicbor::decode::Error> {
+    use minicbor::data::Type;
+    while d.datatype()? == Type::Tag {
+        d.tag()?;
+    }
+    let datatype = d.datatype()?;
+    let len = match datatype {
+        Type::Array | Type::ArrayIndef | Type::Map | Type::MapIndef if depth >= MAX_VALUE_DEPTH => {
+            return Err(minicbor::decode::Error::message("value nested too deeply"));
+        }
+        Type::Array | Type::ArrayIndef => d.array()?,
+        Type::Map | Type::MapIndef => d.map()?.map(|len| len.saturating_mul(2)),
+        Type::Break => return Err(minicbor::decode::Error::type_mismatch(datatype)),
+        // Scalars and strings, which `skip` steps over without allocating
+        _ => return d.skip(),
+    };
+    match len {
+        Some(len) => {
+            for _ in 0..len {
+                skip_at_depth(d, depth + 1)?;
+            }
+        }
+        None => {
+            while d.datatype()? != Type::Break {
+                skip_at_depth(d, depth + 1)?;
+            }
+            d.skip()?;
+        }
+    }
+    Ok(())
+}
+
 impl From<bool> for Value {
     fn from(v: bool) -> Self {
         Value::Bool(v)
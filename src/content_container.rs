@@ -2,15 +2,25 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-use minicbor::bytes::ByteVec;
 use num_enum::{FromPrimitive, IntoPrimitive};
-use sha2::{Digest, Sha256};
-use std::{collections::BTreeMap, convert::Infallible};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    convert::Infallible,
+    io::{self, Read},
+    ops::Deref,
+};
 
 use crate::{
-    cbor, impl_encode_decode_num_enum, MessageStatus, MessageStatusReport, PerMessageStatus,
+    cbor, impl_encode_decode_num_enum, MediaType, MessageStatus, MessageStatusReport, PartRouter,
+    PerMessageStatus,
 };
 
+/// Byte string type of [`MimiContentV1::last_seen`], re-exported so callers
+/// need not depend on `minicbor` directly.
+pub use minicbor::bytes::ByteVec;
+
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum Error {
@@ -18,14 +28,342 @@ pub enum Error {
     UnsupportedContentType,
     #[error("not UTF-8")]
     NotUtf8,
-    #[error("encoding failed: {0}")]
-    Encode(minicbor::encode::Error<Infallible>),
-    #[error("decoding failed: {0}")]
-    Decode(minicbor::decode::Error),
+    #[error("encoding failed")]
+    Encode(#[source] minicbor::encode::Error<Infallible>),
+    #[error("decoding failed")]
+    Decode(#[source] minicbor::decode::Error),
+    #[error("relative expiration with zero time")]
+    InvalidExpiration,
+    #[error("malformed message id")]
+    MalformedMessageId,
+    #[error("unknown {enum_name} value {value}")]
+    UnknownDiscriminant { enum_name: &'static str, value: u64 },
+    #[error("invalid message id at index {index}")]
+    InvalidMessageId { index: usize },
+    #[error("unsupported hash algorithm")]
+    UnsupportedHashAlgorithm,
+    #[error("not an external part")]
+    NotExternalPart,
+    #[error("reading input failed")]
+    Io(#[source] std::io::Error),
+    #[error("input too large")]
+    InputTooLarge,
+    #[error("total size of external parts too large")]
+    TotalSizeTooLarge,
+    #[error("invalid salt length")]
+    InvalidSalt,
+    #[error("multipart disposition must be unspecified or render")]
+    InvalidMultiPartDisposition,
+    #[error("unknown trailing fields")]
+    TrailingFields,
+    #[error("extension value too large")]
+    ExtensionTooLarge,
+    #[error("extension value of a disallowed type")]
+    DisallowedExtensionValue,
+    #[error("topic id too large")]
+    TopicTooLarge,
+    #[error("topic id has too many characters")]
+    TopicTooManyChars,
+    #[error("extension keys {0} and \"{0}\" are both present")]
+    AmbiguousExtensionKeys(u64),
+    /// `part_path` holds the indices into the `parts` of each enclosing
+    /// multipart, as passed by [`NestedPart::walk`], and is empty for the
+    /// top-level part.
+    #[error("content{} does not match its charset", display_part_path(.part_path))]
+    CharsetMismatch { part_path: Vec<usize> },
+    /// `part_path` is as for [`Error::CharsetMismatch`].
+    #[error("unsupported charset{}", display_part_path(.part_path))]
+    UnsupportedCharset { part_path: Vec<usize> },
+    #[error("key and nonce reused")]
+    NonceReuse,
+    #[error("sender or room differs from the senderUri or roomUri extension")]
+    SenderRoomMismatch,
+    #[error("patch does not apply to this content")]
+    PatchMismatch,
+    #[error("unsupported content version {0}")]
+    UnsupportedVersion(u8),
+    /// `part_path` is as for [`Error::CharsetMismatch`].
+    #[error("markdown{} outside the GFM-MIMI subset", display_part_path(.part_path))]
+    InvalidMarkdownVariant { part_path: Vec<usize> },
+    #[error("declared size of external part too large")]
+    SizeTooLarge,
+    #[cfg(feature = "postcard")]
+    #[error("storage encoding failed")]
+    Storage(#[source] ::postcard::Error),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Maximum number of bytes read by [`MimiContent::deserialize_from`].
+pub const MAX_INPUT_LEN: u64 = 16 * 1024 * 1024;
+
+/// Reads one CBOR data item from `reader` into `buf`, without reading past
+/// its end.
+///
+/// Fails with [`Error::InputTooLarge`] if the item would exceed
+/// [`MAX_INPUT_LEN`] bytes, and with [`Error::Decode`] if it is nested deeper
+/// than content can be.
+fn read_cbor_item<R: io::Read>(reader: &mut R, buf: &mut Vec<u8>) -> Result<()> {
+    fn read_into<R: io::Read>(reader: &mut R, buf: &mut Vec<u8>, len: u64) -> Result<()> {
+        if (buf.len() as u64).saturating_add(len) > MAX_INPUT_LEN {
+            return Err(Error::InputTooLarge);
+        }
+        let read = reader.take(len).read_to_end(buf).map_err(Error::Io)?;
+        if (read as u64) < len {
+            return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        Ok(())
+    }
+    let malformed = |msg| Error::Decode(minicbor::decode::Error::message(msg));
+    // The top level, the content array and its extension map or, for each
+    // multipart, the part and its array of parts. Deeper input would be
+    // rejected when decoding anyway.
+    let max_depth = 4 + 2 * MAX_PART_DEPTH.max(cbor::MAX_VALUE_DEPTH);
+
+    // Items still to be read in each enclosing container, `None` for
+    // indefinite-length containers and strings, which end with a break
+    let mut pending = vec![Some(1u64)];
+    while let Some(remaining) = pending.last_mut() {
+        match remaining {
+            Some(0) => {
+                pending.pop();
+                continue;
+            }
+            Some(count) => *count -= 1,
+            None => {}
+        }
+        let indefinite = remaining.is_none();
+
+        let start = buf.len();
+        read_into(reader, buf, 1)?;
+        let initial = buf[start];
+        if initial == 0xff {
+            if !indefinite {
+                return Err(malformed("unexpected break"));
+            }
+            pending.pop();
+            continue;
+        }
+        let argument = match initial & 0x1f {
+            info @ 0..=23 => Some(u64::from(info)),
+            info @ 24..=27 => {
+                let len = 1 << (info - 24);
+                read_into(reader, buf, len)?;
+                let mut bytes = [0; 8];
+                bytes[8 - len as usize..].copy_from_slice(&buf[start + 1..]);
+                Some(u64::from_be_bytes(bytes))
+            }
+            31 => None,
+            _ => return Err(malformed("reserved additional information")),
+        };
+        match (initial >> 5, argument) {
+            (0 | 1 | 7, Some(_)) => {}
+            (2 | 3, Some(len)) => read_into(reader, buf, len)?,
+            (4, Some(len)) => pending.push(Some(len)),
+            (5, Some(len)) => pending.push(Some(len.saturating_mul(2))),
+            (2..=5, None) => pending.push(None),
+            (6, Some(_)) => pending.push(Some(1)),
+            _ => return Err(malformed("invalid indefinite length")),
+        }
+        if pending.len() > max_depth {
+            return Err(malformed("nested too deeply"));
+        }
+    }
+    Ok(())
+}
+
+/// Limits enforced by [`MimiContent::validate_limits`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum sum of the declared sizes of all external parts
+    pub max_total_external_size: u64,
+    /// Maximum declared size of a single external part, also enforced by
+    /// [`NestedPart::verify_content_hash`]
+    pub max_external_size: u64,
+    /// Maximum encoded size of a single extension value, see
+    /// [`extension_encoded_len`]
+    pub max_extension_size: usize,
+    /// Maximum size of the topic ID in bytes
+    pub max_topic_size: usize,
+    /// Maximum number of characters of a topic ID that is valid UTF-8
+    pub max_topic_chars: usize,
+    /// Whether to reject floats in extension values, whose encoding is not
+    /// deterministic across implementations
+    pub reject_float_extensions: bool,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_total_external_size: 1 << 40,
+            max_external_size: 1 << 36,
+            max_extension_size: 64 * 1024,
+            max_topic_size: 1024,
+            max_topic_chars: 256,
+            reject_float_extensions: false,
+        }
+    }
+}
+
+/// Returns the length of the CBOR encoding of an extension value.
+pub fn extension_encoded_len(value: &cbor::Value) -> usize {
+    encoded_len(value)
+}
+
+/// Returns the length of the CBOR encoding of `value` without allocating.
+fn encoded_len<T: minicbor::Encode<()>>(value: &T) -> usize {
+    struct LenCounter(usize);
+
+    impl minicbor::encode::Write for LenCounter {
+        type Error = Infallible;
+
+        fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+            self.0 += buf.len();
+            Ok(())
+        }
+    }
+
+    let mut counter = LenCounter(0);
+    // Counting cannot fail and none of the types used here produce custom
+    // errors.
+    let _ = minicbor::encode(value, &mut counter);
+    counter.0
+}
+
+/// Maximum nesting depth of multiparts accepted when decoding.
+pub const MAX_PART_DEPTH: usize = 32;
+
+fn contains_float(value: &cbor::Value) -> bool {
+    match value {
+        cbor::Value::Float(_) => true,
+        cbor::Value::Array(values) => values.iter().any(contains_float),
+        cbor::Value::Map(values) => values.values().any(contains_float),
+        _ => false,
+    }
+}
+
+/// Version byte prepended to every message ID.
+///
+/// The version identifies the hash algorithm, see
+/// [`HashAlgorithm::id_prefix`].
+pub const MESSAGE_ID_VERSION: u8 = 0x01;
+
+/// Length of a message ID in bytes, including the version byte.
+pub const MESSAGE_ID_LEN: usize = 32;
+
+/// Version of the layout of [`MimiContent`]. Version 1 is the layout of
+/// [`MimiContentV1`].
+pub const CONTENT_VERSION: u8 = 2;
+
+/// Length of the salt in bytes.
+pub const SALT_LEN: usize = 16;
+
+/// A message ID as computed by [`MimiContent::message_id`].
+///
+/// Consists of [`MESSAGE_ID_VERSION`] followed by the truncated hash, for a
+/// total of [`MESSAGE_ID_LEN`] bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MessageId([u8; MESSAGE_ID_LEN]);
+
+impl MessageId {
+    pub fn as_bytes(&self) -> &[u8; MESSAGE_ID_LEN] {
+        &self.0
+    }
+}
+
+impl TryFrom<&[u8]> for MessageId {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        let bytes: [u8; MESSAGE_ID_LEN] =
+            value.try_into().map_err(|_| Error::MalformedMessageId)?;
+        if bytes[0] != MESSAGE_ID_VERSION {
+            return Err(Error::MalformedMessageId);
+        }
+        Ok(Self(bytes))
+    }
+}
+
+impl TryFrom<Vec<u8>> for MessageId {
+    type Error = Error;
+
+    fn try_from(value: Vec<u8>) -> Result<Self> {
+        value.as_slice().try_into()
+    }
+}
+
+impl From<MessageId> for Vec<u8> {
+    fn from(value: MessageId) -> Self {
+        value.0.to_vec()
+    }
+}
+
+impl AsRef<[u8]> for MessageId {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Deref for MessageId {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Random salt making the message ID of otherwise identical messages unique.
+///
+/// Implements `AsRef<[u8]>`, so it can be passed to hashing APIs directly:
+///
+/// ```
+/// use mimi_content::content_container::Salt;
+/// use sha2::{Digest, Sha256};
+///
+/// let salt = Salt::from([7; 16]);
+/// let digest = Sha256::digest(salt);
+/// assert_eq!(digest.as_slice(), Sha256::digest([7; 16]).as_slice());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Salt([u8; SALT_LEN]);
+
+impl Salt {
+    /// Copies a salt, which must be [`SALT_LEN`] bytes long.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        bytes.try_into().map(Self).map_err(|_| Error::InvalidSalt)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; SALT_LEN] {
+        &self.0
+    }
+}
+
+impl From<[u8; SALT_LEN]> for Salt {
+    fn from(value: [u8; SALT_LEN]) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Salt> for Vec<u8> {
+    fn from(value: Salt) -> Self {
+        value.0.to_vec()
+    }
+}
+
+impl AsRef<[u8]> for Salt {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Deref for Salt {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 #[derive(minicbor_derive::Encode, minicbor_derive::Decode, PartialEq, Debug, Clone)]
 #[cbor(array)]
 pub struct MimiContentV1 {
@@ -66,9 +404,16 @@ impl Default for MimiContentV1 {
 }
 
 impl MimiContentV1 {
+    /// Converts version 1 content to the current layout.
+    ///
+    /// Version 1 has no salt, so the upgraded content gets an all-zero salt
+    /// of [`SALT_LEN`] bytes. This adds no predictability that the original
+    /// message did not already have, since it was sent unsalted. Content
+    /// that is sent again as a new message should get a random salt with
+    /// [`MimiContent::clone_with_new_salt`].
     pub fn upgrade(self) -> MimiContent {
         MimiContent {
-            salt: [0; 16].to_vec(),
+            salt: vec![0; SALT_LEN],
             replaces: self.replaces,
             topic_id: self.topic_id,
             expires: self.expires,
@@ -104,18 +449,94 @@ pub struct MimiContent {
 
 impl MimiContent {
     pub fn message_id(&self, sender: &[u8], room: &[u8]) -> Result<Vec<u8>> {
-        let mut hasher = Sha256::new();
+        self.message_id_with(HashAlgorithm::Sha256, sender, room)
+    }
+
+    /// Computes the message ID with `hash_alg`, prefixed with the version
+    /// byte of the algorithm.
+    ///
+    /// Returns [`Error::UnsupportedHashAlgorithm`] for algorithms without a
+    /// version byte, which currently are all but SHA-256.
+    pub fn message_id_with(
+        &self,
+        hash_alg: HashAlgorithm,
+        sender: &[u8],
+        room: &[u8],
+    ) -> Result<Vec<u8>> {
+        let prefix = hash_alg
+            .id_prefix()
+            .ok_or(Error::UnsupportedHashAlgorithm)?;
+        let mut hasher = hash_alg.hasher().ok_or(Error::UnsupportedHashAlgorithm)?;
         hasher.update(sender);
         hasher.update(room);
-        hasher.update(self.serialize()?);
-        hasher.update(self.salt.as_slice());
+        minicbor::encode(self, &mut hasher).map_err(Error::Encode)?;
+        hasher.update(&self.salt);
         let hash = hasher.finalize();
 
-        let mut result = vec![0x01];
-        result.extend(&hash[0..31]);
+        let mut result = Vec::with_capacity(MESSAGE_ID_LEN);
+        result.push(prefix);
+        result.extend(
+            hash.get(..MESSAGE_ID_LEN - 1)
+                .ok_or(Error::UnsupportedHashAlgorithm)?,
+        );
         Ok(result)
     }
 
+    /// Computes the message ID of encoded content.
+    ///
+    /// The content is decoded and encoded again before hashing, so the ID is
+    /// the one a compliant peer computes, even if `input` is not canonical,
+    /// e.g. because its extensions are out of order.
+    pub fn canonical_message_id(input: &[u8], sender: &[u8], room: &[u8]) -> Result<MessageId> {
+        let content = Self::deserialize(input)?;
+        MessageId::try_from(content.message_id(sender, room)?)
+    }
+
+    /// Computes the message ID from the sender and room URIs in the
+    /// `senderUri` (1) and `roomUri` (2) extensions.
+    ///
+    /// Returns `None` if either extension is missing or not text.
+    pub fn message_id_from_extensions(&self) -> Option<MessageId> {
+        let uri = |key| match self.extensions.get(&ExtensionName::Number(key)) {
+            Some(cbor::Value::Text(uri)) => Some(uri.as_bytes()),
+            _ => None,
+        };
+        let id = self.message_id(uri(1)?, uri(2)?).ok()?;
+        MessageId::try_from(id).ok()
+    }
+
+    /// Checks that `sender` and `room`, e.g. as passed to
+    /// [`MimiContent::message_id`], are the UTF-8 encodings of the URIs in
+    /// the `senderUri` (1) and `roomUri` (2) extensions.
+    ///
+    /// Missing extensions are not checked. An extension that is present but
+    /// not text never matches.
+    pub fn check_sender_room(&self, sender: &[u8], room: &[u8]) -> Result<()> {
+        let matches = |key, expected: &[u8]| match self.extensions.get(&ExtensionName::Number(key))
+        {
+            None => true,
+            Some(cbor::Value::Text(uri)) => uri.as_bytes() == expected,
+            Some(_) => false,
+        };
+        if !matches(1, sender) || !matches(2, room) {
+            return Err(Error::SenderRoomMismatch);
+        }
+        Ok(())
+    }
+
+    /// Hashes the body of the message, i.e. its nested part, to recognize
+    /// the same body sent in different messages.
+    ///
+    /// Unlike [`MimiContent::message_id`], the fingerprint does not depend on
+    /// the sender, room, salt, extensions or any other envelope field, so it
+    /// must not be used to identify a message.
+    pub fn body_fingerprint(&self) -> [u8; 32] {
+        let mut buf = Vec::new();
+        // Parts produce no custom errors and writing to a `Vec` cannot fail.
+        let _ = minicbor::encode(&self.nested_part, &mut buf);
+        Sha256::digest(&buf).into()
+    }
+
     pub fn is_status_update(&self) -> bool {
         if let NestedPart::SinglePart { content_type, .. } = &self.nested_part {
             content_type == "application/mimi-message-status"
@@ -124,7 +545,7 @@ impl MimiContent {
         }
     }
 
-    pub fn simple_markdown_message(markdown: String, random_salt: [u8; 16]) -> Self {
+    pub fn simple_markdown_message(markdown: String, random_salt: [u8; SALT_LEN]) -> Self {
         Self {
             salt: random_salt.into(),
             replaces: None,
@@ -141,9 +562,18 @@ impl MimiContent {
         }
     }
 
+    /// Creates a message with a single `text/markdown;variant=GFM-MIMI` part.
+    pub fn from_markdown(markdown: &str, salt: Salt) -> Self {
+        Self {
+            salt: salt.into(),
+            nested_part: markdown.into(),
+            ..Default::default()
+        }
+    }
+
     pub fn simple_receipt(
         targets: &[&[u8]],
-        random_salt: [u8; 16],
+        random_salt: [u8; SALT_LEN],
         status: MessageStatus,
     ) -> Result<(MessageStatusReport, Self)> {
         let report = MessageStatusReport {
@@ -175,6 +605,143 @@ impl MimiContent {
         ))
     }
 
+    /// Creates a status message reporting a status per message.
+    pub fn status_receipt(entries: &[(MessageId, MessageStatus)], salt: Salt) -> Result<Self> {
+        let report: MessageStatusReport = entries
+            .iter()
+            .map(|(mimi_id, status)| PerMessageStatus {
+                mimi_id: (*mimi_id).into(),
+                status: *status,
+            })
+            .collect();
+
+        Ok(Self {
+            salt: salt.into(),
+            nested_part: NestedPart::SinglePart {
+                disposition: Disposition::Unspecified,
+                language: "".to_owned(),
+                content_type: "application/mimi-message-status".to_owned(),
+                content: report.serialize()?,
+            },
+            ..Default::default()
+        })
+    }
+
+    /// Creates a message deleting the message `replaces`.
+    pub fn tombstone(replaces: MessageId, salt: Salt) -> Self {
+        Self {
+            salt: salt.into(),
+            replaces: Some(replaces.into()),
+            topic_id: b"".to_vec(),
+            expires: None,
+            in_reply_to: None,
+            extensions: BTreeMap::new(),
+            nested_part: NestedPart::NullPart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+            },
+        }
+    }
+
+    /// Returns whether this message deletes the message it replaces.
+    ///
+    /// This includes reaction removals.
+    pub fn is_tombstone(&self) -> bool {
+        self.replaces.is_some() && self.nested_part.is_null_part()
+    }
+
+    /// Creates a message withdrawing the reaction `replaces`.
+    ///
+    /// Like a [`MimiContent::tombstone`], but the null part keeps the
+    /// `Reaction` disposition, so the removal can be told apart from the
+    /// deletion of a message.
+    pub fn reaction_removal(replaces: MessageId, salt: Salt) -> Self {
+        Self {
+            nested_part: NestedPart::NullPart {
+                disposition: Disposition::Reaction,
+                language: "".to_owned(),
+            },
+            ..Self::tombstone(replaces, salt)
+        }
+    }
+
+    /// Returns whether this message withdraws the reaction it replaces.
+    pub fn is_reaction_removal(&self) -> bool {
+        self.is_tombstone() && self.nested_part.disposition() == Disposition::Reaction
+    }
+
+    /// Returns whether the top-level part is a null part, as in a deletion.
+    pub fn is_null_body(&self) -> bool {
+        self.nested_part.is_null_part()
+    }
+
+    /// Returns whether the top-level part is a single part with empty content.
+    ///
+    /// Unlike a null part, such a part still has a content type and is
+    /// rendered, just without content.
+    pub fn has_empty_content(&self) -> bool {
+        matches!(&self.nested_part, NestedPart::SinglePart { content, .. } if content.is_empty())
+    }
+
+    /// Returns whether the message carries an expiration other than the
+    /// "never expires" sentinel.
+    pub fn is_ephemeral(&self) -> bool {
+        self.expires
+            .as_ref()
+            .is_some_and(|expires| expires.relative || expires.time != 0)
+    }
+
+    /// Returns the absolute Unix time at which the message expires.
+    ///
+    /// A relative expiration is counted from `message_time`, the time the
+    /// message was sent. Returns `None` if the message never expires.
+    pub fn expires_at(&self, message_time: u64) -> Option<u64> {
+        self.expires.as_ref()?.resolve(message_time)
+    }
+
+    /// Sets a relative expiration of `seconds` after the message is sent.
+    ///
+    /// Like any change to the content, this changes the message ID. A
+    /// relative expiration of zero is rejected by [`Expiration::validate`].
+    pub fn set_ttl(&mut self, seconds: u32) {
+        self.expires = Some(Expiration {
+            relative: true,
+            time: seconds,
+        });
+    }
+
+    /// Shortens the expiration so the message expires at most
+    /// `max_ttl_seconds` after `now`, the time the message was sent.
+    ///
+    /// A message without expiration, or with the "never expires" sentinel,
+    /// gets a relative expiration of `max_ttl_seconds`. An absolute
+    /// expiration stays absolute and is lowered to `now + max_ttl_seconds`
+    /// if it is later. If that sum does not fit in the 32-bit time, any
+    /// absolute expiration is earlier and is left unchanged. A limit of zero
+    /// is treated as one second, since a relative expiration of zero is
+    /// invalid.
+    ///
+    /// The message ID changes if the expiration does, so this must be
+    /// applied before the ID is computed, e.g. by the sender's server.
+    pub fn clamp_expiration(&mut self, max_ttl_seconds: u32, now: u64) {
+        let max_ttl = max_ttl_seconds.max(1);
+        match &mut self.expires {
+            Some(Expiration {
+                relative: true,
+                time,
+            }) => *time = (*time).min(max_ttl),
+            Some(Expiration {
+                relative: false,
+                time,
+            }) if *time != 0 => {
+                if let Ok(limit) = u32::try_from(now.saturating_add(u64::from(max_ttl))) {
+                    *time = (*time).min(limit);
+                }
+            }
+            _ => self.set_ttl(max_ttl),
+        }
+    }
+
     pub fn string_rendering(&self) -> Result<String> {
         // For now, we only support SingleParts that contain markdown messages.
         match &self.nested_part {
@@ -190,1290 +757,6183 @@ impl MimiContent {
         }
     }
 
-    pub fn serialize(&self) -> Result<Vec<u8>> {
-        let mut buf = Vec::new();
-        minicbor::encode(self, &mut buf).map_err(Error::Encode)?;
-        Ok(buf)
+    /// Sets the extension with the numeric key `key` and returns the content.
+    ///
+    /// An existing value under the same key is replaced.
+    pub fn with_extension_number(mut self, key: u64, value: impl Into<cbor::Value>) -> Self {
+        self.set_extension_number(key, value);
+        self
     }
 
-    pub fn deserialize(input: &[u8]) -> Result<Self> {
-        minicbor::decode(input).map_err(Error::Decode)
+    /// Returns whether both messages are equal apart from their salt.
+    ///
+    /// Unlike `==`, this deliberately ignores the salt, so the same body sent
+    /// twice compares equal even though the message IDs differ.
+    pub fn content_eq(&self, other: &MimiContent) -> bool {
+        let Self {
+            salt: _,
+            replaces,
+            topic_id,
+            expires,
+            in_reply_to,
+            extensions,
+            nested_part,
+        } = self;
+        *replaces == other.replaces
+            && *topic_id == other.topic_id
+            && *expires == other.expires
+            && *in_reply_to == other.in_reply_to
+            && *extensions == other.extensions
+            && *nested_part == other.nested_part
     }
-}
 
-#[derive(PartialEq, Eq, Debug, Clone, PartialOrd, Ord)]
-pub enum ExtensionName {
-    Text(String),
-    Number(u64),
-}
+    /// Compares the message to `original` field by field, e.g. to show what an
+    /// edit changed.
+    ///
+    /// The salt is not compared, as it differs between any two messages.
+    pub fn diff(&self, original: &MimiContent) -> ContentDiff {
+        let mut diff = ContentDiff {
+            replaces: self.replaces != original.replaces,
+            topic_id: self.topic_id != original.topic_id,
+            expires: self.expires != original.expires,
+            in_reply_to: self.in_reply_to != original.in_reply_to,
+            nested_part: self.nested_part != original.nested_part,
+            ..Default::default()
+        };
+        for (name, value) in &self.extensions {
+            match original.extensions.get(name) {
+                None => diff.extensions_added.push(name.clone()),
+                Some(original_value) if original_value != value => {
+                    diff.extensions_changed.push(name.clone())
+                }
+                Some(_) => {}
+            }
+        }
+        diff.extensions_removed = original
+            .extensions
+            .keys()
+            .filter(|name| !self.extensions.contains_key(*name))
+            .cloned()
+            .collect();
+        diff
+    }
 
-impl<C> minicbor::Encode<C> for ExtensionName {
-    fn encode<W: minicbor::encode::Write>(
-        &self,
-        e: &mut minicbor::Encoder<W>,
-        _ctx: &mut C,
-    ) -> Result<(), minicbor::encode::Error<W::Error>> {
-        match self {
-            ExtensionName::Text(text) => e.str(text),
-            ExtensionName::Number(integer) => e.u64(*integer),
-        }?;
-        Ok(())
+    /// Sets the topic ID to the UTF-8 encoding of `topic` and returns the
+    /// content.
+    pub fn with_topic(mut self, topic: &str) -> Self {
+        self.topic_id = topic.as_bytes().to_vec();
+        self
     }
-}
 
-impl<C> minicbor::Decode<'_, C> for ExtensionName {
-    fn decode(
-        d: &mut minicbor::Decoder<'_>,
-        _ctx: &mut C,
-    ) -> Result<Self, minicbor::decode::Error> {
-        if let Ok(numerical_value) = d.u64() {
-            Ok(Self::Number(numerical_value))
-        } else {
-            let text_value = d.str()?;
-            Ok(Self::Text(text_value.to_owned()))
-        }
+    /// Returns the topic ID, which need not be text.
+    pub fn topic_id_bytes(&self) -> &[u8] {
+        &self.topic_id
     }
-}
 
-#[derive(minicbor_derive::Encode, minicbor_derive::Decode, PartialEq, Eq, Debug, Clone)]
-#[cbor(array)]
-pub struct Expiration {
-    #[cbor(n(0))]
-    pub relative: bool,
-    #[cbor(n(1))]
-    pub time: u32,
-}
+    /// Sets the topic ID to an opaque binary identifier.
+    pub fn set_topic_bytes(&mut self, topic_id: &[u8]) {
+        self.topic_id = topic_id.to_vec();
+    }
 
-/// Content Hashing Algorithm
-///
-/// See [Named Information Hash Algorithm Registry].
-///
-/// [Named Information Hash Algorithm Registry]: https://www.iana.org/assignments/named-information/named-information.xhtml
-#[derive(Debug, Clone, Copy, Eq, PartialEq, IntoPrimitive, FromPrimitive)]
-#[repr(u8)]
-#[non_exhaustive]
-#[allow(non_camel_case_types)]
-pub enum HashAlgorithm {
-    Unspecified = 0,
-    /// [RFC6920](https://www.rfc-editor.org/rfc/rfc6920.html)
-    Sha256 = 1,
-    /// [RFC6920](https://www.rfc-editor.org/rfc/rfc6920.html)
-    Sha256_128 = 2,
-    /// [RFC6920](https://www.rfc-editor.org/rfc/rfc6920.html)
-    Sha256_120 = 3,
-    /// [RFC6920](https://www.rfc-editor.org/rfc/rfc6920.html)
-    Sha256_96 = 4,
-    /// [RFC6920](https://www.rfc-editor.org/rfc/rfc6920.html)
-    Sha256_64 = 5,
-    /// [RFC6920](https://www.rfc-editor.org/rfc/rfc6920.html)
-    Sha256_32 = 6,
-    /// [FIPS 180-4](https://nvlpubs.nist.gov/nistpubs/FIPS/NIST.FIPS.180-4.pdf)
-    Sha384 = 7,
-    /// [FIPS 180-4](https://nvlpubs.nist.gov/nistpubs/FIPS/NIST.FIPS.180-4.pdf)
-    Sha512 = 8,
-    /// [FIPS 202](https://nvlpubs.nist.gov/nistpubs/FIPS/NIST.FIPS.202.pdf)
-    Sha3_224 = 9,
-    /// [FIPS 202](https://nvlpubs.nist.gov/nistpubs/FIPS/NIST.FIPS.202.pdf)
-    Sha3_256 = 10,
-    /// [FIPS 202](https://nvlpubs.nist.gov/nistpubs/FIPS/NIST.FIPS.202.pdf)
-    Sha3_384 = 11,
-    /// [FIPS 202](https://nvlpubs.nist.gov/nistpubs/FIPS/NIST.FIPS.202.pdf)
-    Sha3_512 = 12,
-    /// Custom hash algorithm
-    #[num_enum(catch_all)]
-    Custom(u8),
-}
+    /// Returns the topic ID as text, if it is valid UTF-8.
+    pub fn topic_id_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.topic_id).ok()
+    }
 
-impl_encode_decode_num_enum!(HashAlgorithm, u8);
+    pub fn is_valid_utf8_topic(&self) -> bool {
+        self.topic_id_str().is_some()
+    }
 
-#[allow(clippy::derivable_impls)]
-impl Default for HashAlgorithm {
-    fn default() -> Self {
-        Self::Unspecified
+    /// Returns the topic ID for display.
+    ///
+    /// A topic ID that is not valid UTF-8 is shown as lowercase hex prefixed
+    /// with `0x`, so it stands out from a text topic made of hex digits. Use
+    /// [`MimiContent::topic_id_str`] to tell the two apart reliably.
+    pub fn display_topic(&self) -> Cow<'_, str> {
+        match self.topic_id_str() {
+            Some(topic) => Cow::Borrowed(topic),
+            None => Cow::Owned(
+                std::iter::once("0x".to_owned())
+                    .chain(self.topic_id.iter().map(|byte| format!("{byte:02x}")))
+                    .collect(),
+            ),
+        }
     }
-}
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum NestedPart {
-    NullPart {
-        disposition: Disposition,
-        language: String, // TODO: Parse as Vec<LanguageTag> ?
-    },
-    SinglePart {
-        disposition: Disposition,
-        language: String, // TODO: Parse as Vec<LanguageTag> ?
-        content_type: String,
-        content: Vec<u8>,
-    },
-    ExternalPart {
-        disposition: Disposition,
-        language: String, // TODO: Parse as Vec<LanguageTag> ?
-        content_type: String,
-        url: String,
-        expires: u32,
-        size: u64,
-        enc_alg: EncryptionAlgorithm,
-        key: Vec<u8>,
-        nonce: Vec<u8>,
-        aad: Vec<u8>,
-        hash_alg: HashAlgorithm,
-        content_hash: Vec<u8>,
-        description: String,
-        filename: String,
-    },
-    MultiPart {
-        disposition: Disposition,
-        language: String, // TODO: Parse as Vec<LanguageTag> ?
-        part_semantics: PartSemantics,
-        parts: Vec<NestedPart>,
-    },
-}
+    /// Returns whether the topic ID equals the UTF-8 encoding of `topic`.
+    pub fn topic_matches(&self, topic: &str) -> bool {
+        self.topic_id == topic.as_bytes()
+    }
 
-impl Default for NestedPart {
-    fn default() -> Self {
-        Self::NullPart {
-            disposition: Disposition::Unspecified,
-            language: String::new(),
+    /// Sets the extension with the text key `key` and returns the content.
+    ///
+    /// An existing value under the same key is replaced.
+    pub fn with_extension_text(mut self, key: &str, value: impl Into<cbor::Value>) -> Self {
+        self.set_extension_text(key, value);
+        self
+    }
+
+    /// Sets the extension with the numeric key `key`.
+    ///
+    /// Returns the previous value under the same key, if any.
+    pub fn set_extension_number(
+        &mut self,
+        key: u64,
+        value: impl Into<cbor::Value>,
+    ) -> Option<cbor::Value> {
+        self.extensions
+            .insert(ExtensionName::Number(key), value.into())
+    }
+
+    /// Sets the extension with the text key `key`.
+    ///
+    /// Returns the previous value under the same key, if any.
+    pub fn set_extension_text(
+        &mut self,
+        key: &str,
+        value: impl Into<cbor::Value>,
+    ) -> Option<cbor::Value> {
+        self.extensions
+            .insert(ExtensionName::Text(key.to_owned()), value.into())
+    }
+
+    /// Returns the value of the extension `key`, if present.
+    pub fn get_extension(&self, key: &ExtensionName) -> Option<&cbor::Value> {
+        self.extensions.get(key)
+    }
+
+    /// Returns whether the extension `key` is present.
+    pub fn has_extension(&self, key: &ExtensionName) -> bool {
+        self.extensions.contains_key(key)
+    }
+
+    /// Removes the extension `key` and returns its value, if present.
+    pub fn remove_extension(&mut self, key: &ExtensionName) -> Option<cbor::Value> {
+        self.extensions.remove(key)
+    }
+
+    /// Returns a copy of this message with `salt` replacing the salt.
+    ///
+    /// The copy has the same body but a different message ID.
+    pub fn clone_with_new_salt(&self, salt: Salt) -> Self {
+        Self {
+            salt: salt.into(),
+            ..self.clone()
         }
     }
-}
 
-impl NestedPart {
-    pub fn disposition(&self) -> Disposition {
-        match self {
-            NestedPart::NullPart { disposition, .. } => *disposition,
-            NestedPart::SinglePart { disposition, .. } => *disposition,
-            NestedPart::ExternalPart { disposition, .. } => *disposition,
-            NestedPart::MultiPart { disposition, .. } => *disposition,
+    /// Replaces the nested part, returning the previous one.
+    pub fn replace_nested_part(&mut self, new: NestedPart) -> NestedPart {
+        std::mem::replace(&mut self.nested_part, new)
+    }
+
+    /// Canonicalizes the casing of every language tag in the message.
+    ///
+    /// Follows the BCP 47 conventions: lowercase language, titlecase script,
+    /// and uppercase region subtags. This changes the serialized content and
+    /// therefore the message ID, so it should be applied by the sender before
+    /// computing the ID.
+    pub fn normalize_languages(&mut self) {
+        self.nested_part.normalize_languages();
+    }
+
+    /// Returns the content types of all single and external parts, as
+    /// lowercase `type/subtype` without parameters.
+    pub fn content_types(&self) -> BTreeSet<String> {
+        let mut content_types = BTreeSet::new();
+        self.nested_part.walk(|_, part| {
+            if let Some(content_type) = part.content_type() {
+                let media_type = MediaType::parse(content_type);
+                content_types.insert(
+                    format!("{}/{}", media_type.type_(), media_type.subtype()).to_ascii_lowercase(),
+                );
+            }
+        });
+        content_types
+    }
+
+    /// Returns whether a client supporting the content types in `supported`,
+    /// e.g. `text/markdown`, can render the message.
+    ///
+    /// Parameters of content types are ignored. A `ChooseOne` multipart
+    /// needs one renderable part, any other multipart needs all of its parts
+    /// to be renderable. Null parts are always renderable.
+    pub fn renderable_with(&self, supported: &BTreeSet<String>) -> bool {
+        self.nested_part.renderable_with(supported)
+    }
+
+    /// Returns every language tag used by a part of the message, e.g. for a
+    /// language picker.
+    ///
+    /// Tags are split at commas and normalized as in
+    /// [`MimiContent::normalize_languages`]. Empty tags are skipped.
+    pub fn available_languages(&self) -> BTreeSet<String> {
+        let mut languages = BTreeSet::new();
+        self.nested_part.walk(|_, part| {
+            languages.extend(
+                part.language()
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(normalize_language_tag),
+            );
+        });
+        languages
+    }
+
+    pub fn extension_count(&self) -> usize {
+        self.extensions.len()
+    }
+
+    /// Returns the length of the encoded extensions map, including keys.
+    pub fn extensions_byte_size(&self) -> usize {
+        encoded_len(&self.extensions)
+    }
+
+    /// Returns the extension keys in canonical order.
+    pub fn extension_keys(&self) -> impl Iterator<Item = &ExtensionName> {
+        self.extensions.keys()
+    }
+
+    /// Returns the extensions in canonical order.
+    pub fn extensions_iter(&self) -> impl Iterator<Item = (&ExtensionName, &cbor::Value)> {
+        self.extensions.iter()
+    }
+
+    /// Merges `other` into the extensions of this message.
+    ///
+    /// Keys present in both keep their existing value unless `overwrite` is
+    /// set. Like any change to the content, this changes the message ID.
+    pub fn merge_extensions(
+        &mut self,
+        other: BTreeMap<ExtensionName, cbor::Value>,
+        overwrite: bool,
+    ) {
+        for (key, value) in other {
+            if overwrite {
+                self.extensions.insert(key, value);
+            } else {
+                self.extensions.entry(key).or_insert(value);
+            }
         }
     }
 
-    pub fn is_null_part(&self) -> bool {
-        matches!(self, NestedPart::NullPart { .. })
+    /// Returns a copy of this message without extensions, e.g. to forward it
+    /// without revealing the original sender and room.
+    ///
+    /// The copy has a different message ID.
+    pub fn without_extensions(&self) -> Self {
+        Self {
+            extensions: BTreeMap::new(),
+            ..self.clone()
+        }
     }
 
-    pub fn is_single_part(&self) -> bool {
-        matches!(self, NestedPart::SinglePart { .. })
+    /// Removes all extensions except those in `keep`.
+    ///
+    /// Like any change to the content, this changes the message ID.
+    pub fn retain_extensions(&mut self, keep: &[ExtensionName]) {
+        self.extensions.retain(|key, _| keep.contains(key));
     }
 
-    pub fn is_external_part(&self) -> bool {
-        matches!(self, NestedPart::ExternalPart { .. })
+    /// Collects all `Custom` enum values in the message.
+    ///
+    /// Each value is returned with the path of the part it appeared in, as
+    /// passed by [`NestedPart::walk`]. Custom values signal that the message
+    /// uses features this implementation does not know about.
+    pub fn custom_values(&self) -> CustomValues {
+        let mut values = Vec::new();
+        self.nested_part.walk(|path, part| {
+            if let Disposition::Custom(value) = part.disposition() {
+                values.push((path.to_vec(), CustomValue::Disposition(value)));
+            }
+            match part {
+                NestedPart::ExternalPart {
+                    enc_alg, hash_alg, ..
+                } => {
+                    if let EncryptionAlgorithm::Custom(value) = enc_alg {
+                        values.push((path.to_vec(), CustomValue::EncryptionAlgorithm(*value)));
+                    }
+                    if let HashAlgorithm::Custom(value) = hash_alg {
+                        values.push((path.to_vec(), CustomValue::HashAlgorithm(*value)));
+                    }
+                }
+                NestedPart::MultiPart {
+                    part_semantics: PartSemantics::Custom(value),
+                    ..
+                } => {
+                    values.push((path.to_vec(), CustomValue::PartSemantics(*value)));
+                }
+                _ => {}
+            }
+        });
+        values
     }
 
-    pub fn is_multi_part(&self) -> bool {
-        matches!(self, NestedPart::MultiPart { .. })
+    /// Returns the sum of the declared sizes of all external parts.
+    ///
+    /// Returns `None` if the sum overflows.
+    pub fn total_external_size(&self) -> Option<u64> {
+        let mut total = Some(0u64);
+        self.nested_part.walk(|_, part| {
+            if let NestedPart::ExternalPart { size, .. } = part {
+                total = total.and_then(|total| total.checked_add(*size));
+            }
+        });
+        total
     }
-}
 
-impl<C> minicbor::Encode<C> for NestedPart {
-    fn encode<W: minicbor::encode::Write>(
-        &self,
-        e: &mut minicbor::Encoder<W>,
-        _ctx: &mut C,
-    ) -> Result<(), minicbor::encode::Error<W::Error>> {
-        match self {
-            NestedPart::NullPart {
-                disposition,
-                language,
-            } => {
-                e.array(3)?.encode(disposition)?.str(language)?.u8(0)?;
+    /// Checks the message against `limits`.
+    ///
+    /// Sizes whose sum overflows are treated as exceeding the limits.
+    pub fn validate_limits(&self, limits: &Limits) -> Result<()> {
+        let mut errors = Vec::new();
+        self.collect_limit_violations(limits, &mut errors);
+        errors.into_iter().next().map_or(Ok(()), Err)
+    }
+
+    fn collect_limit_violations(&self, limits: &Limits, errors: &mut Vec<Error>) {
+        match self.total_external_size() {
+            Some(total) if total <= limits.max_total_external_size => {}
+            _ => errors.push(Error::TotalSizeTooLarge),
+        }
+        let mut size_too_large = false;
+        self.nested_part.walk(|_, part| {
+            size_too_large |= part
+                .external_size()
+                .is_some_and(|size| size > limits.max_external_size);
+        });
+        if size_too_large {
+            errors.push(Error::SizeTooLarge);
+        }
+        if self
+            .extensions
+            .values()
+            .any(|value| extension_encoded_len(value) > limits.max_extension_size)
+        {
+            errors.push(Error::ExtensionTooLarge);
+        }
+        if limits.reject_float_extensions && self.extensions.values().any(contains_float) {
+            errors.push(Error::DisallowedExtensionValue);
+        }
+        if self.topic_id.len() > limits.max_topic_size {
+            errors.push(Error::TopicTooLarge);
+        } else if std::str::from_utf8(&self.topic_id)
+            .is_ok_and(|topic| topic.chars().count() > limits.max_topic_chars)
+        {
+            errors.push(Error::TopicTooManyChars);
+        }
+    }
+
+    /// Checks that no text extension key spells out a numeric key that is
+    /// also present, e.g. `"1"` next to `1`.
+    ///
+    /// Such keys are valid but confusing, so this check is not part of
+    /// [`MimiContent::validate`].
+    pub fn validate_extension_keys(&self) -> Result<()> {
+        for key in self.extensions.keys() {
+            let ExtensionName::Text(text) = key else {
+                continue;
+            };
+            if let Ok(number) = text.parse() {
+                if self.extensions.contains_key(&ExtensionName::Number(number)) {
+                    return Err(Error::AmbiguousExtensionKeys(number));
+                }
             }
-            NestedPart::SinglePart {
-                disposition,
-                language,
+        }
+        Ok(())
+    }
+
+    /// Checks that `text/markdown;variant=GFM-MIMI` parts stay within the
+    /// subset of the variant: no raw HTML, and autolinks only to `http`,
+    /// `https`, `mailto` and `mimi` URIs or to email addresses.
+    ///
+    /// The markdown is parsed as CommonMark with the GFM tables and
+    /// strikethrough extensions, so HTML inside code spans and code blocks is
+    /// not flagged. Only the first offending part is reported. The check is
+    /// not part of [`MimiContent::validate`].
+    #[cfg(feature = "markdown")]
+    pub fn validate_markdown_variant(&self) -> Result<()> {
+        let mut invalid_part = None;
+        self.nested_part.walk(|path, part| {
+            if invalid_part.is_some() {
+                return;
+            }
+            if let NestedPart::SinglePart {
                 content_type,
                 content,
-            } => {
-                e.array(5)?
-                    .encode(disposition)?
-                    .str(language)?
-                    .u8(1)?
-                    .str(content_type)?
-                    .bytes(content)?;
+                ..
+            } = part
+            {
+                let media_type = MediaType::parse(content_type);
+                let gfm_mimi = media_type.is("text/markdown")
+                    && media_type
+                        .parameter("variant")
+                        .is_some_and(|variant| variant.eq_ignore_ascii_case("GFM-MIMI"));
+                if gfm_mimi && uses_disallowed_markdown(content) {
+                    invalid_part = Some(path.to_vec());
+                }
             }
-            NestedPart::ExternalPart {
-                disposition,
-                language,
+        });
+        match invalid_part {
+            Some(part_path) => Err(Error::InvalidMarkdownVariant { part_path }),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks the semantic rules for the parts of the message.
+    ///
+    /// A multipart must have the disposition `Unspecified` or `Render`.
+    pub fn validate_semantics(&self) -> Result<()> {
+        let mut errors = Vec::new();
+        self.collect_semantic_violations(&mut errors);
+        errors.into_iter().next().map_or(Ok(()), Err)
+    }
+
+    fn collect_semantic_violations(&self, errors: &mut Vec<Error>) {
+        self.nested_part.walk(|path, part| match part {
+            NestedPart::MultiPart { disposition, .. }
+                if !matches!(disposition, Disposition::Unspecified | Disposition::Render) =>
+            {
+                errors.push(Error::InvalidMultiPartDisposition)
+            }
+            NestedPart::SinglePart {
                 content_type,
-                url,
-                expires,
-                size,
-                enc_alg,
-                key,
-                nonce,
-                aad,
-                hash_alg,
-                content_hash,
-                description,
-                filename,
+                content,
+                ..
             } => {
-                e.array(15)?
-                    .encode(disposition)?
-                    .str(language)?
-                    .u8(2)?
-                    .str(content_type)?
-                    .str(url)?
-                    .u32(*expires)?
-                    .u64(*size)?
-                    .encode(enc_alg)?
-                    .bytes(key)?
-                    .bytes(nonce)?
-                    .bytes(aad)?
-                    .encode(hash_alg)?
-                    .bytes(content_hash)?
-                    .str(description)?
-                    .str(filename)?;
+                if let Err(error) = check_charset(content_type, content, path) {
+                    errors.push(error);
+                }
+            }
+            _ => {}
+        });
+    }
+
+    /// Runs all structural checks, the semantic checks, and the checks against
+    /// `limits`.
+    ///
+    /// Returns every violation found rather than stopping at the first one.
+    pub fn validate(&self, limits: &Limits) -> Result<(), Vec<Error>> {
+        let mut errors = Vec::new();
+        if self.salt.len() != SALT_LEN {
+            errors.push(Error::InvalidSalt);
+        }
+        for id in [&self.replaces, &self.in_reply_to].into_iter().flatten() {
+            if let Err(error) = MessageId::try_from(id.as_slice()) {
+                errors.push(error);
             }
+        }
+        if let Some(Err(error)) = self.expires.as_ref().map(Expiration::validate) {
+            errors.push(error);
+        }
+        self.collect_semantic_violations(&mut errors);
+        self.collect_limit_violations(limits, &mut errors);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Like [`MimiContent::validate`], but returns only the first violation.
+    pub fn validate_first(&self, limits: &Limits) -> Result<()> {
+        self.validate(limits)
+            .map_err(|errors| errors.into_iter().next().expect("at least one error"))
+    }
+
+    /// Returns the content type and content of a top-level single part.
+    ///
+    /// Unlike [`MimiContent::string_rendering`], no assumption is made about
+    /// the encoding of the content.
+    pub fn primary_content(&self) -> Option<(&str, &[u8])> {
+        match &self.nested_part {
+            NestedPart::SinglePart {
+                content_type,
+                content,
+                ..
+            } => Some((content_type.as_str(), content.as_slice())),
+            _ => None,
+        }
+    }
+
+    /// Selects the part to render.
+    ///
+    /// For a top-level `ChooseOne` multipart, the alternatives are expected in
+    /// order of increasing preference, so the last part whose content type is
+    /// one of `supported` (e.g. `text/markdown`) is returned. If there is none,
+    /// the first part for which [`NestedPart::is_renderable`] holds is
+    /// returned. Any other nested part is returned as is.
+    pub fn select_part(&self, supported: &[&str]) -> Option<&NestedPart> {
+        match &self.nested_part {
             NestedPart::MultiPart {
-                disposition,
-                language,
-                part_semantics,
+                part_semantics: PartSemantics::ChooseOne,
                 parts,
-            } => {
-                e.array(5)?
-                    .encode(disposition)?
-                    .str(language)?
-                    .u8(3)?
-                    .encode(part_semantics)?
-                    .encode(parts)?;
+                ..
+            } => parts
+                .iter()
+                .rev()
+                .find(|part| {
+                    part.content_type().is_some_and(|content_type| {
+                        let media_type = MediaType::parse(content_type);
+                        supported.iter().any(|essence| media_type.is(essence))
+                    })
+                })
+                .or_else(|| parts.iter().find(|part| part.is_renderable())),
+            nested_part => Some(nested_part),
+        }
+    }
+
+    /// Returns all parts with the given disposition, in depth-first order.
+    pub fn parts_with_disposition(
+        &self,
+        disposition: Disposition,
+    ) -> impl Iterator<Item = &NestedPart> {
+        let mut parts = Vec::new();
+        self.nested_part.walk(|_, part| {
+            if part.disposition() == disposition {
+                parts.push(part);
             }
-        };
+        });
+        parts.into_iter()
+    }
 
-        Ok(())
+    /// Returns all preview parts, e.g. link previews, which are rendered
+    /// separately from the primary content.
+    pub fn previews(&self) -> impl Iterator<Item = &NestedPart> {
+        self.parts_with_disposition(Disposition::Preview)
     }
-}
 
-impl<C> minicbor::Decode<'_, C> for NestedPart {
-    fn decode(
-        d: &mut minicbor::Decoder<'_>,
-        _ctx: &mut C,
-    ) -> Result<Self, minicbor::decode::Error> {
-        let array = d.array()?.ok_or(minicbor::decode::Error::message(
-            "invalid array length for NestedPart",
-        ))?;
-        let disposition = d.decode()?;
-        let language = d.str()?.to_owned();
-        let discriminant = d.u8()?;
-        match (array, discriminant) {
-            (3, 0) => Ok(NestedPart::NullPart {
-                disposition,
-                language,
-            }),
-            (5, 1) => Ok(NestedPart::SinglePart {
-                disposition,
-                language,
-                content_type: d.str()?.to_owned(),
-                content: d.bytes()?.to_vec(),
-            }),
-            (15, 2) => Ok(NestedPart::ExternalPart {
-                disposition,
-                language,
-                content_type: d.str()?.to_owned(),
-                url: d.str()?.to_owned(),
-                expires: d.u32()?,
-                size: d.u64()?,
-                enc_alg: d.decode()?,
-                key: d.bytes()?.to_vec(),
-                nonce: d.bytes()?.to_vec(),
-                aad: d.bytes()?.to_vec(),
-                hash_alg: d.decode()?,
-                content_hash: d.bytes()?.to_vec(),
-                description: d.str()?.to_owned(),
-                filename: d.str()?.to_owned(),
-            }),
-            (5, 3) => {
-                let part_semantics = d.decode()?;
-                let parts = d.decode()?;
-                Ok(NestedPart::MultiPart {
-                    disposition,
-                    language,
-                    part_semantics,
-                    parts,
-                })
+    /// Walks all parts and passes each single and external part to the
+    /// matching handler of `router`.
+    pub fn route_parts(&self, router: &PartRouter) {
+        self.nested_part.walk(|path, part| {
+            if let Some(content_type) = part.content_type() {
+                router.dispatch(content_type, path, part);
             }
-            _ => Err(minicbor::decode::Error::message(
-                "invalid discriminant for NestedPart",
-            )),
+        });
+    }
+
+    /// Returns the message embedded with [`NestedPart::embedded_mimi`].
+    ///
+    /// Returns `None` unless the message is a single
+    /// `application/mimi-content` part. Only one level is decoded; a message
+    /// embedded in the returned message has to be extracted separately, so
+    /// nesting cannot cause unbounded recursion.
+    pub fn as_embedded(&self) -> Option<Result<MimiContent>> {
+        let (content_type, content) = self.primary_content()?;
+        if !MediaType::parse(content_type).is("application/mimi-content") {
+            return None;
         }
+        Some(MimiContent::deserialize(content))
     }
-}
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, IntoPrimitive, FromPrimitive)]
-#[repr(u8)]
-pub enum Disposition {
-    Unspecified = 0,
-    Render = 1,
-    Reaction = 2,
-    Profile = 3,
-    Inline = 4,
-    Icon = 5,
-    Attachment = 6,
-    Session = 7,
-    Preview = 8,
-    #[num_enum(catch_all)]
-    Custom(u8),
+    /// Returns the emoji of a reaction.
+    ///
+    /// Returns `None` unless the message is a single `text/plain` part with
+    /// the `Reaction` disposition.
+    pub fn reaction_emoji(&self) -> Option<Result<String>> {
+        if self.nested_part.disposition() != Disposition::Reaction {
+            return None;
+        }
+        let (content_type, content) = self.primary_content()?;
+        if !MediaType::parse(content_type).is("text/plain") {
+            return None;
+        }
+        Some(String::from_utf8(content.to_vec()).map_err(|_| Error::NotUtf8))
+    }
+
+    /// Returns the markdown variant and text of a top-level markdown part.
+    ///
+    /// Returns `None` if the message is not a single `text/markdown` part.
+    pub fn render_markdown(&self) -> Option<Result<(MarkdownVariant, String)>> {
+        let (content_type, content) = self.primary_content()?;
+        let media_type = MediaType::parse(content_type);
+        if !media_type.is("text/markdown") {
+            return None;
+        }
+        let variant = match media_type.parameter("variant") {
+            None => MarkdownVariant::Unspecified,
+            Some(variant) if variant.eq_ignore_ascii_case("GFM-MIMI") => MarkdownVariant::GfmMimi,
+            Some(variant) => MarkdownVariant::Other(variant.to_owned()),
+        };
+        Some(
+            String::from_utf8(content.to_vec())
+                .map(|text| (variant, text))
+                .map_err(|_| Error::NotUtf8),
+        )
+    }
+
+    /// Serializes the content.
+    ///
+    /// Unless the body is a multipart, the buffer is sized up front to the
+    /// exact length instead of growing while encoding, which is where the
+    /// time goes for short messages. For multiparts, counting the length
+    /// takes longer than growing the buffer. See the `encode-single` and
+    /// `encode-large` groups in `benches/codec.rs`.
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let mut buf = match self.nested_part {
+            NestedPart::MultiPart { .. } => Vec::new(),
+            _ => Vec::with_capacity(encoded_len(self)),
+        };
+        minicbor::encode(self, &mut buf).map_err(Error::Encode)?;
+        Ok(buf)
+    }
+
+    /// Deserializes content.
+    ///
+    /// Malformed input, including lengths exceeding the input and nesting
+    /// deeper than [`MAX_PART_DEPTH`] or [`cbor::MAX_VALUE_DEPTH`], results in
+    /// an error and never in a panic.
+    pub fn deserialize(input: &[u8]) -> Result<Self> {
+        minicbor::decode(input).map_err(Error::Decode)
+    }
+
+    /// Serializes several messages as a single CBOR array, as found in sync
+    /// responses.
+    pub fn serialize_batch(contents: &[MimiContent]) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        minicbor::encode(contents, &mut buf).map_err(Error::Encode)?;
+        Ok(buf)
+    }
+
+    /// Deserializes a CBOR array of messages as written by
+    /// [`MimiContent::serialize_batch`].
+    pub fn deserialize_batch(input: &[u8]) -> Result<Vec<Self>> {
+        minicbor::decode(input).map_err(Error::Decode)
+    }
+
+    /// Deserializes content encoded with the layout of `version`.
+    ///
+    /// Version 1 content is decoded as [`MimiContentV1`] and upgraded.
+    /// Version [`CONTENT_VERSION`] is the layout decoded by
+    /// [`MimiContent::deserialize`].
+    pub fn deserialize_version(input: &[u8], version: u8) -> Result<Self> {
+        match version {
+            1 => minicbor::decode(input)
+                .map(MimiContentV1::upgrade)
+                .map_err(Error::Decode),
+            CONTENT_VERSION => Self::deserialize(input),
+            _ => Err(Error::UnsupportedVersion(version)),
+        }
+    }
+
+    /// Checks whether `input` has the shape of content, as a cheap filter
+    /// before decoding.
+    ///
+    /// The input must be an array of at least seven fields, starting with a
+    /// salt of [`SALT_LEN`] bytes, whose fields have the expected types and
+    /// whose nested part is well-formed. The array may have an indefinite
+    /// length, as accepted by [`MimiContent::deserialize`]. Values nested
+    /// deeper than [`cbor::MAX_VALUE_DEPTH`] are rejected. It does not validate
+    /// semantics; see [`MimiContent::validate`].
+    pub fn is_well_formed(input: &[u8]) -> bool {
+        use minicbor::data::Type;
+
+        let check = |d: &mut minicbor::Decoder<'_>| -> Result<bool, minicbor::decode::Error> {
+            if matches!(d.array()?, Some(len) if len < MIMI_CONTENT_FIELDS) {
+                return Ok(false);
+            }
+            if d.bytes()?.len() != SALT_LEN {
+                return Ok(false);
+            }
+            for expected in [
+                [Type::Null, Type::Bytes],
+                [Type::Bytes, Type::Bytes],
+                [Type::Null, Type::Array],
+                [Type::Null, Type::Bytes],
+                [Type::Map, Type::Map],
+            ] {
+                let datatype = d.datatype()?;
+                if !expected.contains(&datatype) {
+                    return Ok(false);
+                }
+                if datatype == Type::Map {
+                    // Extension values start at depth zero, as when decoding
+                    let len = d.map()?.unwrap_or(0);
+                    for _ in 0..len.saturating_mul(2) {
+                        cbor::skip_value(d)?;
+                    }
+                } else {
+                    cbor::skip_value(d)?;
+                }
+            }
+            check_part_shape(d, 0, None)?;
+            Ok(true)
+        };
+        check(&mut minicbor::Decoder::new(input)).unwrap_or(false)
+    }
+
+    /// Serializes the content prefixed with its length as a 4-byte big-endian
+    /// integer, for embedding in a framed stream.
+    pub fn to_length_prefixed(&self) -> Result<Vec<u8>> {
+        let content = self.serialize()?;
+        let len = u32::try_from(content.len()).map_err(|_| {
+            Error::Encode(minicbor::encode::Error::message(
+                "content too large for length prefix",
+            ))
+        })?;
+        let mut buf = Vec::with_capacity(4 + content.len());
+        buf.extend_from_slice(&len.to_be_bytes());
+        buf.extend_from_slice(&content);
+        Ok(buf)
+    }
+
+    /// Deserializes as many fields as possible, e.g. to show the envelope of
+    /// a message whose body is corrupt.
+    ///
+    /// Returns the decoded fields and the error that stopped decoding, if
+    /// any.
+    pub fn deserialize_partial(input: &[u8]) -> (PartialContent, Option<Error>) {
+        let mut partial = PartialContent::default();
+        let error = partial
+            .decode_fields(&mut minicbor::Decoder::new(input))
+            .err()
+            .map(Error::Decode);
+        (partial, error)
+    }
+
+    /// Like [`MimiContent::deserialize`], but reports the field and the
+    /// approximate byte offset at which decoding failed, e.g. to debug
+    /// interoperability issues.
+    ///
+    /// On failure, the input is decoded a second time to locate the error.
+    pub fn deserialize_debug(input: &[u8]) -> Result<Self, DecodeError> {
+        const FIELDS: [&str; MIMI_CONTENT_FIELDS as usize] = [
+            "salt",
+            "replaces",
+            "topic_id",
+            "expires",
+            "in_reply_to",
+            "extensions",
+            "nested_part",
+        ];
+
+        // Locates the error within the nested part
+        let part_path = || {
+            let mut path = Vec::new();
+            let mut d = minicbor::Decoder::new(input);
+            let _ = d.array().and_then(|_| {
+                for _ in 1..MIMI_CONTENT_FIELDS {
+                    cbor::skip_value(&mut d)?;
+                }
+                check_part_shape(&mut d, 0, Some(&mut path))
+            });
+            path
+        };
+
+        minicbor::decode(input).map_err(|source| {
+            let mut d = minicbor::Decoder::new(input);
+            let valid_header = match minicbor::Decoder::new(input).array() {
+                Ok(Some(len)) => len >= MIMI_CONTENT_FIELDS,
+                Ok(None) => true,
+                Err(_) => false,
+            };
+            let field = if valid_header {
+                let mut partial = PartialContent::default();
+                let _ = partial.decode_fields(&mut d);
+                FIELDS
+                    .get(partial.decoded_fields)
+                    .copied()
+                    .unwrap_or("trailing fields")
+            } else {
+                "header"
+            };
+            DecodeError {
+                field,
+                part_path: if field == "nested_part" {
+                    part_path()
+                } else {
+                    Vec::new()
+                },
+                offset: d.position(),
+                source,
+            }
+        })
+    }
+
+    /// Deserializes length-prefixed content as written by
+    /// [`MimiContent::to_length_prefixed`].
+    ///
+    /// Returns the content and the number of bytes consumed from `input`.
+    /// Bytes within the frame after the content are rejected, as they point
+    /// to a corrupt length prefix.
+    pub fn from_length_prefixed(input: &[u8]) -> Result<(Self, usize)> {
+        let end_of_input = || Error::Decode(minicbor::decode::Error::end_of_input());
+        let (prefix, rest) = input.split_first_chunk::<4>().ok_or_else(end_of_input)?;
+        let len = u32::from_be_bytes(*prefix) as usize;
+        let mut d = minicbor::Decoder::new(rest.get(..len).ok_or_else(end_of_input)?);
+        let content = d.decode().map_err(Error::Decode)?;
+        if d.position() != len {
+            return Err(Error::Decode(minicbor::decode::Error::message(
+                "trailing bytes in frame",
+            )));
+        }
+        Ok((content, 4 + len))
+    }
+
+    /// Deserializes content read from `reader`.
+    ///
+    /// Exactly one CBOR data item is read, following the lengths in its
+    /// headers, so the reader need not reach the end of its input, as with a
+    /// socket, and bytes after the message stay in the reader. A declared
+    /// length that would take the message past [`MAX_INPUT_LEN`] bytes is
+    /// rejected before anything is read for it. The decoded content owns all
+    /// of its data, so the bytes of the item are held until decoding ends.
+    ///
+    /// Headers are read a byte at a time so as not to read past the message,
+    /// which makes a system call per byte on an unbuffered reader such as a
+    /// `TcpStream`. Wrap such readers in a [`io::BufReader`], which keeps the
+    /// bytes after the message for the next read.
+    pub fn deserialize_from<R: io::Read>(mut reader: R) -> Result<Self> {
+        let mut input = Vec::new();
+        read_cbor_item(&mut reader, &mut input)?;
+        Self::deserialize(&input)
+    }
+
+    /// Like [`MimiContent::deserialize`], but rejects `Custom` dispositions
+    /// and part semantics anywhere in the message, as well as trailing fields.
+    pub fn deserialize_strict(input: &[u8]) -> Result<Self> {
+        let (content, unknown_tail) = Self::deserialize_with_unknown_tail(input)?;
+        if !unknown_tail.is_empty() {
+            return Err(Error::TrailingFields);
+        }
+        content.nested_part.ensure_known_discriminants()?;
+        Ok(content)
+    }
+
+    /// Deserializes content that may have been encoded by a newer version
+    /// with additional fields appended.
+    ///
+    /// Returns the known fields and any trailing fields. Note that
+    /// [`MimiContent::deserialize`] skips trailing fields as well.
+    pub fn deserialize_with_unknown_tail(input: &[u8]) -> Result<(Self, Vec<cbor::Value>)> {
+        let content = Self::deserialize(input)?;
+        let unknown_tail = decode_unknown_tail(input).map_err(Error::Decode)?;
+        Ok((content, unknown_tail))
+    }
 }
 
-impl_encode_decode_num_enum!(Disposition, u8);
+/// A decoding error with its location, as returned by
+/// [`MimiContent::deserialize_debug`].
+#[derive(Debug, thiserror::Error)]
+#[error("decoding {field}{} failed near byte {offset}", display_part_path(.part_path))]
+pub struct DecodeError {
+    /// The field being decoded, `header` for the array header of the message
+    /// or `trailing fields` for fields after the known ones
+    pub field: &'static str,
+    /// For errors in `nested_part`, the indices into the `parts` of each
+    /// enclosing multipart of the part that failed, as passed by
+    /// [`NestedPart::walk`]; empty otherwise
+    pub part_path: Vec<usize>,
+    /// Approximate offset into the input at which decoding failed
+    pub offset: usize,
+    #[source]
+    pub source: minicbor::decode::Error,
+}
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, IntoPrimitive, FromPrimitive)]
-#[repr(u16)]
-pub enum EncryptionAlgorithm {
-    None = 0,
-    /// Reference: [RFC5116](https://www.rfc-editor.org/rfc/rfc5116.html)
-    Aes128Gcm = 1,
-    /// Reference: [RFC5116](https://www.rfc-editor.org/rfc/rfc5116.html)
-    Aes256Gcm = 2,
-    /// Reference: [RFC5116](https://www.rfc-editor.org/rfc/rfc5116.html)
-    Aes128Ccm = 3,
-    /// Reference: [RFC5116](https://www.rfc-editor.org/rfc/rfc5116.html)
-    Aes256Ccm = 4,
-    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
-    Aes128Gcm8 = 5,
-    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
-    Aes256Gcm8 = 6,
-    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
-    Aes128Gcm12 = 7,
-    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
-    Aes256Gcm12 = 8,
-    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
-    Aes128CcmShort = 9,
-    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
-    Aes256CcmShort = 10,
-    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
-    Aes128CcmShort8 = 11,
-    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
-    Aes256CcmShort8 = 12,
-    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
-    Aes128CcmShort12 = 13,
-    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
-    Aes256CcmShort12 = 14,
-    /// Reference: [RFC5297](https://www.rfc-editor.org/rfc/rfc5297.html)
-    AesSivCmac256 = 15,
-    /// Reference: [RFC5297](https://www.rfc-editor.org/rfc/rfc5297.html)
-    AesSivCmac384 = 16,
-    /// Reference: [RFC5297](https://www.rfc-editor.org/rfc/rfc5297.html)
-    AesSivCmac512 = 17,
-    /// Reference: [RFC6655](https://www.rfc-editor.org/rfc/rfc6655.html)
-    Aes128Ccm8 = 18,
-    /// Reference: [RFC6655](https://www.rfc-editor.org/rfc/rfc6655.html)
-    Aes256Ccm8 = 19,
-    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
-    Aes128OcbTaglen128 = 20,
-    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
-    Aes128OcbTaglen96 = 21,
-    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
-    Aes128OcbTaglen64 = 22,
-    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
-    Aes192OcbTaglen128 = 23,
-    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
-    Aes192OcbTaglen96 = 24,
-    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
-    Aes192OcbTaglen64 = 25,
-    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
-    Aes256OcbTaglen128 = 26,
-    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
-    Aes256OcbTaglen96 = 27,
-    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
-    Aes256OcbTaglen64 = 28,
-    /// Reference: [RFC8439](https://www.rfc-editor.org/rfc/rfc8439.html)
-    Chacha20Poly1305 = 29,
-    /// Reference: [RFC8452](https://www.rfc-editor.org/rfc/rfc8452.html)
-    Aes128GcmSiv = 30,
-    /// Reference: [RFC8452](https://www.rfc-editor.org/rfc/rfc8452.html)
-    Aes256GcmSiv = 31,
-    /// Reference: [draft-irtf-cfrg-aegis-aead-08](https://datatracker.ietf.org/doc/draft-irtf-cfrg-aegis-aead/08/)
-    Aegis128L = 32,
-    /// Reference: [draft-irtf-cfrg-aegis-aead-08](https://datatracker.ietf.org/doc/draft-irtf-cfrg-aegis-aead/08/)
-    Aegis256 = 33,
-    /// Unknown algorithm
-    #[num_enum(catch_all)]
-    Custom(u16),
+fn display_part_path(path: &[usize]) -> String {
+    if path.is_empty() {
+        return String::new();
+    }
+    let indices: Vec<_> = path.iter().map(usize::to_string).collect();
+    format!(" at part {}", indices.join("."))
 }
 
-impl_encode_decode_num_enum!(EncryptionAlgorithm, u16);
+/// The fields of a message decoded before an error, as returned by
+/// [`MimiContent::deserialize_partial`].
+///
+/// Fields are decoded in order, so `decoded_fields` tells whether a `None`
+/// field was absent in the message or not decoded at all.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PartialContent {
+    pub decoded_fields: usize,
+    pub salt: Option<Vec<u8>>,
+    pub replaces: Option<Vec<u8>>,
+    pub topic_id: Option<Vec<u8>>,
+    pub expires: Option<Expiration>,
+    pub in_reply_to: Option<Vec<u8>>,
+    pub extensions: Option<BTreeMap<ExtensionName, cbor::Value>>,
+    pub nested_part: Option<NestedPart>,
+}
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, FromPrimitive)]
-#[repr(u8)]
-pub enum PartSemantics {
-    ChooseOne = 0,
-    SingleUnit = 1,
-    ProcessAll = 2,
-    #[num_enum(catch_all)]
-    Custom(u8),
+impl PartialContent {
+    fn decode_fields(
+        &mut self,
+        d: &mut minicbor::Decoder<'_>,
+    ) -> Result<(), minicbor::decode::Error> {
+        if matches!(d.array()?, Some(len) if len < MIMI_CONTENT_FIELDS) {
+            return Err(minicbor::decode::Error::message(
+                "invalid array length for MimiContent",
+            ));
+        }
+        self.salt = Some(d.bytes()?.to_vec());
+        self.decoded_fields += 1;
+        self.replaces = minicbor::bytes::decode(d, &mut ())?;
+        self.decoded_fields += 1;
+        self.topic_id = Some(d.bytes()?.to_vec());
+        self.decoded_fields += 1;
+        self.expires = d.decode()?;
+        self.decoded_fields += 1;
+        self.in_reply_to = minicbor::bytes::decode(d, &mut ())?;
+        self.decoded_fields += 1;
+        self.extensions = Some(d.decode()?);
+        self.decoded_fields += 1;
+        self.nested_part = Some(d.decode()?);
+        self.decoded_fields += 1;
+        Ok(())
+    }
+}
+
+/// A message together with its message ID.
+///
+/// The ID is derived from the content, so the content can only be changed
+/// through [`IdentifiedContent::set`], which recomputes the ID.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdentifiedContent {
+    content: MimiContent,
+    sender: Vec<u8>,
+    room: Vec<u8>,
+    id: MessageId,
+}
+
+impl IdentifiedContent {
+    /// Computes the ID of `content` sent by `sender` in `room`.
+    pub fn new(content: MimiContent, sender: &[u8], room: &[u8]) -> Result<Self> {
+        let id = MessageId::try_from(content.message_id(sender, room)?)?;
+        Ok(Self {
+            content,
+            sender: sender.to_vec(),
+            room: room.to_vec(),
+            id,
+        })
+    }
+
+    pub fn content(&self) -> &MimiContent {
+        &self.content
+    }
+
+    pub fn id(&self) -> &MessageId {
+        &self.id
+    }
+
+    /// Changes the content through `f` and recomputes the ID.
+    pub fn set(&mut self, f: impl FnOnce(&mut MimiContent)) -> Result<()> {
+        f(&mut self.content);
+        self.id = MessageId::try_from(self.content.message_id(&self.sender, &self.room)?)?;
+        Ok(())
+    }
+
+    pub fn into_parts(self) -> (MimiContent, MessageId) {
+        (self.content, self.id)
+    }
+}
+
+/// Fields that differ between two messages, as returned by
+/// [`MimiContent::diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContentDiff {
+    pub replaces: bool,
+    pub topic_id: bool,
+    pub expires: bool,
+    pub in_reply_to: bool,
+    /// Whether the body differs
+    pub nested_part: bool,
+    pub extensions_added: Vec<ExtensionName>,
+    pub extensions_removed: Vec<ExtensionName>,
+    pub extensions_changed: Vec<ExtensionName>,
+}
+
+impl ContentDiff {
+    /// Returns whether no field differs.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The `variant` parameter of a `text/markdown` content type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MarkdownVariant {
+    /// No variant parameter given
+    Unspecified,
+    /// GitHub Flavored Markdown as profiled by MIMI
+    GfmMimi,
+    Other(String),
+}
+
+/// A `Custom` enum value found by [`MimiContent::custom_values`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CustomValue {
+    Disposition(u8),
+    PartSemantics(u8),
+    HashAlgorithm(u8),
+    EncryptionAlgorithm(u16),
+}
+
+/// Custom values together with the path of the part they appeared in.
+pub type CustomValues = Vec<(Vec<usize>, CustomValue)>;
+
+/// Number of fields of the [`MimiContent`] array
+const MIMI_CONTENT_FIELDS: u64 = 7;
+
+fn decode_unknown_tail(input: &[u8]) -> Result<Vec<cbor::Value>, minicbor::decode::Error> {
+    let mut d = minicbor::Decoder::new(input);
+    let len = d.array()?;
+    for _ in 0..MIMI_CONTENT_FIELDS {
+        d.skip()?;
+    }
+    let mut tail = Vec::new();
+    match len {
+        Some(len) => {
+            for _ in MIMI_CONTENT_FIELDS..len {
+                tail.push(d.decode()?);
+            }
+        }
+        None => {
+            while d.datatype()? != minicbor::data::Type::Break {
+                tail.push(d.decode()?);
+            }
+        }
+    }
+    Ok(tail)
+}
+
+/// The key of an extension.
+///
+/// `Number(1)` and `Text("1")` are distinct keys. Keys are ordered by the
+/// derived `Ord`, so all text keys sort before all numeric keys. Extension
+/// maps are encoded in this order, which differs from the deterministic
+/// encoding of RFC 8949, Section 4.2.1, where integer keys come first.
+#[derive(PartialEq, Eq, Debug, Clone, PartialOrd, Ord)]
+pub enum ExtensionName {
+    Text(String),
+    Number(u64),
+}
+
+impl ExtensionName {
+    /// Returns the registered name of a well-known numeric extension.
+    ///
+    /// This is display metadata only and does not affect the wire format.
+    pub fn well_known_name(&self) -> Option<&'static str> {
+        match self {
+            ExtensionName::Number(1) => Some("senderUri"),
+            ExtensionName::Number(2) => Some("roomUri"),
+            _ => None,
+        }
+    }
+}
+
+impl<C> minicbor::Encode<C> for ExtensionName {
+    fn encode<W: minicbor::encode::Write>(
+        &self,
+        e: &mut minicbor::Encoder<W>,
+        _ctx: &mut C,
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        match self {
+            ExtensionName::Text(text) => e.str(text),
+            ExtensionName::Number(integer) => e.u64(*integer),
+        }?;
+        Ok(())
+    }
 }
 
-impl_encode_decode_num_enum!(PartSemantics, u8);
+impl<C> minicbor::Decode<'_, C> for ExtensionName {
+    fn decode(
+        d: &mut minicbor::Decoder<'_>,
+        _ctx: &mut C,
+    ) -> Result<Self, minicbor::decode::Error> {
+        use minicbor::data::Type;
+
+        match d.datatype()? {
+            Type::U8 | Type::U16 | Type::U32 | Type::U64 => Ok(Self::Number(d.u64()?)),
+            Type::String => Ok(Self::Text(d.str()?.to_owned())),
+            _ => Err(minicbor::decode::Error::message(
+                "extension name must be an unsigned integer or text",
+            )),
+        }
+    }
+}
+
+/// Application data stored in several extensions of a message.
+///
+/// Implementors map each field to an extension key of their choice. For a
+/// lossless round trip, [`MimiExtensions::from_extensions`] must accept every
+/// map produced by [`MimiExtensions::to_extensions`] and return an equal
+/// value.
+pub trait MimiExtensions: Sized {
+    /// Returns the extensions representing `self`.
+    fn to_extensions(&self) -> BTreeMap<ExtensionName, cbor::Value>;
+
+    /// Reads the value from `extensions`, which may contain unrelated keys.
+    ///
+    /// Returns `None` if a required extension is missing or malformed.
+    fn from_extensions(extensions: &BTreeMap<ExtensionName, cbor::Value>) -> Option<Self>;
+
+    /// Stores `self` in the extensions of `content`, replacing existing
+    /// values under the same keys.
+    fn apply_to(&self, content: &mut MimiContent) {
+        content.merge_extensions(self.to_extensions(), true);
+    }
+
+    /// Reads the value from the extensions of `content`.
+    fn extract_from(content: &MimiContent) -> Option<Self> {
+        Self::from_extensions(&content.extensions)
+    }
+}
+
+#[derive(minicbor_derive::Encode, minicbor_derive::Decode, PartialEq, Eq, Debug, Clone)]
+#[cbor(array)]
+pub struct Expiration {
+    #[cbor(n(0))]
+    pub relative: bool,
+    #[cbor(n(1))]
+    pub time: u32,
+}
+
+impl Expiration {
+    /// Checks the expiration for encoder mistakes.
+    ///
+    /// An absolute expiration with `time == 0` is the sentinel for "never
+    /// expires" and is valid. A relative expiration with `time == 0` would
+    /// expire immediately, which is rejected.
+    pub fn validate(&self) -> Result<()> {
+        if self.relative && self.time == 0 {
+            return Err(Error::InvalidExpiration);
+        }
+        Ok(())
+    }
+
+    /// Returns the absolute Unix time of the expiration.
+    ///
+    /// A relative expiration is counted from `message_time`, the time the
+    /// message was sent, saturating at `u64::MAX`. Returns `None` for the
+    /// "never expires" sentinel.
+    pub fn resolve(&self, message_time: u64) -> Option<u64> {
+        match (self.relative, self.time) {
+            (false, 0) => None,
+            (false, time) => Some(u64::from(time)),
+            (true, time) => Some(message_time.saturating_add(u64::from(time))),
+        }
+    }
+}
+
+/// Content Hashing Algorithm
+///
+/// See [Named Information Hash Algorithm Registry].
+///
+/// [Named Information Hash Algorithm Registry]: https://www.iana.org/assignments/named-information/named-information.xhtml
+#[derive(Debug, Clone, Copy, Eq, PartialEq, IntoPrimitive, FromPrimitive)]
+#[repr(u8)]
+#[non_exhaustive]
+#[allow(non_camel_case_types)]
+pub enum HashAlgorithm {
+    Unspecified = 0,
+    /// [RFC6920](https://www.rfc-editor.org/rfc/rfc6920.html)
+    Sha256 = 1,
+    /// [RFC6920](https://www.rfc-editor.org/rfc/rfc6920.html)
+    Sha256_128 = 2,
+    /// [RFC6920](https://www.rfc-editor.org/rfc/rfc6920.html)
+    Sha256_120 = 3,
+    /// [RFC6920](https://www.rfc-editor.org/rfc/rfc6920.html)
+    Sha256_96 = 4,
+    /// [RFC6920](https://www.rfc-editor.org/rfc/rfc6920.html)
+    Sha256_64 = 5,
+    /// [RFC6920](https://www.rfc-editor.org/rfc/rfc6920.html)
+    Sha256_32 = 6,
+    /// [FIPS 180-4](https://nvlpubs.nist.gov/nistpubs/FIPS/NIST.FIPS.180-4.pdf)
+    Sha384 = 7,
+    /// [FIPS 180-4](https://nvlpubs.nist.gov/nistpubs/FIPS/NIST.FIPS.180-4.pdf)
+    Sha512 = 8,
+    /// [FIPS 202](https://nvlpubs.nist.gov/nistpubs/FIPS/NIST.FIPS.202.pdf)
+    Sha3_224 = 9,
+    /// [FIPS 202](https://nvlpubs.nist.gov/nistpubs/FIPS/NIST.FIPS.202.pdf)
+    Sha3_256 = 10,
+    /// [FIPS 202](https://nvlpubs.nist.gov/nistpubs/FIPS/NIST.FIPS.202.pdf)
+    Sha3_384 = 11,
+    /// [FIPS 202](https://nvlpubs.nist.gov/nistpubs/FIPS/NIST.FIPS.202.pdf)
+    Sha3_512 = 12,
+    /// Custom hash algorithm
+    #[num_enum(catch_all)]
+    Custom(u8),
+}
+
+impl_encode_decode_num_enum!(HashAlgorithm, u8);
+
+impl HashAlgorithm {
+    /// Returns the version byte of message IDs computed with this algorithm.
+    ///
+    /// | Algorithm | Version byte |
+    /// |-----------|--------------|
+    /// | `Sha256`  | `0x01`       |
+    ///
+    /// Returns `None` for every other algorithm.
+    pub fn id_prefix(&self) -> Option<u8> {
+        match self {
+            HashAlgorithm::Sha256 => Some(MESSAGE_ID_VERSION),
+            _ => None,
+        }
+    }
+
+    /// Computes the (possibly truncated) digest of `data`.
+    ///
+    /// Returns `None` for `Unspecified`, `Custom`, and the SHA-3 family, which
+    /// are not supported.
+    pub fn digest(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let mut hasher = self.hasher()?;
+        hasher.update(data);
+        Some(hasher.finalize())
+    }
+
+    /// Returns a [`Hasher`] to compute a digest from data given in pieces.
+    ///
+    /// Returns `None` for the same algorithms as [`HashAlgorithm::digest`].
+    pub fn hasher(&self) -> Option<Hasher> {
+        let sha256 = |len: usize| Some(Hasher(HasherState::Sha256(Sha256::new(), len)));
+        match self {
+            HashAlgorithm::Sha256 => sha256(32),
+            HashAlgorithm::Sha256_128 => sha256(16),
+            HashAlgorithm::Sha256_120 => sha256(15),
+            HashAlgorithm::Sha256_96 => sha256(12),
+            HashAlgorithm::Sha256_64 => sha256(8),
+            HashAlgorithm::Sha256_32 => sha256(4),
+            HashAlgorithm::Sha384 => Some(Hasher(HasherState::Sha384(Sha384::new()))),
+            HashAlgorithm::Sha512 => Some(Hasher(HasherState::Sha512(Sha512::new()))),
+            _ => None,
+        }
+    }
+
+    /// Computes the digest of `data` for each of `algs`.
+    ///
+    /// Unsupported algorithms are skipped.
+    pub fn digest_all(algs: &[HashAlgorithm], data: &[u8]) -> Vec<(HashAlgorithm, Vec<u8>)> {
+        algs.iter()
+            .filter_map(|alg| Some((*alg, alg.digest(data)?)))
+            .collect()
+    }
+}
+
+/// Incremental digest computation of a [`HashAlgorithm`].
+///
+/// Feeding data with [`Hasher::update`] in several calls results in the same
+/// digest as [`HashAlgorithm::digest`] of the concatenated data. CBOR can be
+/// encoded into a hasher directly, as it implements
+/// [`minicbor::encode::Write`].
+#[derive(Debug, Clone)]
+pub struct Hasher(HasherState);
+
+#[derive(Debug, Clone)]
+enum HasherState {
+    Sha256(Sha256, usize),
+    Sha384(Sha384),
+    Sha512(Sha512),
+}
+
+impl Hasher {
+    /// Appends `data` to the hashed input.
+    pub fn update(&mut self, data: &[u8]) {
+        match &mut self.0 {
+            HasherState::Sha256(hasher, _) => hasher.update(data),
+            HasherState::Sha384(hasher) => hasher.update(data),
+            HasherState::Sha512(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Returns the (possibly truncated) digest of the input.
+    pub fn finalize(self) -> Vec<u8> {
+        match self.0 {
+            HasherState::Sha256(hasher, len) => hasher.finalize()[..len].to_vec(),
+            HasherState::Sha384(hasher) => hasher.finalize().to_vec(),
+            HasherState::Sha512(hasher) => hasher.finalize().to_vec(),
+        }
+    }
+}
+
+impl minicbor::encode::Write for Hasher {
+    type Error = Infallible;
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.update(buf);
+        Ok(())
+    }
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for HashAlgorithm {
+    fn default() -> Self {
+        Self::Unspecified
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NestedPart {
+    NullPart {
+        disposition: Disposition,
+        language: String, // TODO: Parse as Vec<LanguageTag> ?
+    },
+    SinglePart {
+        disposition: Disposition,
+        language: String, // TODO: Parse as Vec<LanguageTag> ?
+        content_type: String,
+        content: Vec<u8>,
+    },
+    ExternalPart {
+        disposition: Disposition,
+        language: String, // TODO: Parse as Vec<LanguageTag> ?
+        content_type: String,
+        url: String,
+        expires: u32,
+        size: u64,
+        enc_alg: EncryptionAlgorithm,
+        key: Vec<u8>,
+        nonce: Vec<u8>,
+        /// Additional authenticated data for `enc_alg`.
+        ///
+        /// There is no distinction between absent and empty AAD: an empty
+        /// value means zero-length associated data.
+        aad: Vec<u8>,
+        hash_alg: HashAlgorithm,
+        content_hash: Vec<u8>,
+        description: String,
+        filename: String,
+    },
+    /// A group of parts.
+    ///
+    /// The disposition of the multipart itself must be `Unspecified` or
+    /// `Render`; how each child is presented is determined solely by the
+    /// child's own disposition. See [`MimiContent::validate_semantics`].
+    ///
+    /// The order of `parts` is significant, e.g. for `ProcessAll`, and is
+    /// preserved exactly by encoding and decoding. Nothing in this crate
+    /// reorders or deduplicates parts.
+    MultiPart {
+        disposition: Disposition,
+        language: String, // TODO: Parse as Vec<LanguageTag> ?
+        part_semantics: PartSemantics,
+        parts: Vec<NestedPart>,
+    },
+}
+
+impl Default for NestedPart {
+    fn default() -> Self {
+        Self::NullPart {
+            disposition: Disposition::Unspecified,
+            language: String::new(),
+        }
+    }
+}
+
+/// Creates a single `text/markdown;variant=GFM-MIMI` part.
+impl From<&str> for NestedPart {
+    fn from(markdown: &str) -> Self {
+        Self::SinglePart {
+            disposition: Disposition::Render,
+            language: "".to_owned(),
+            content_type: "text/markdown;variant=GFM-MIMI".to_owned(),
+            content: markdown.as_bytes().to_vec(),
+        }
+    }
+}
+
+impl NestedPart {
+    /// Creates a single part carrying a profile picture or similar profile
+    /// data of the sender.
+    pub fn profile(content_type: impl Into<String>, content: impl Into<Vec<u8>>) -> Self {
+        Self::SinglePart {
+            disposition: Disposition::Profile,
+            language: "".to_owned(),
+            content_type: content_type.into(),
+            content: content.into(),
+        }
+    }
+
+    /// Creates a single part carrying a preview, e.g. a thumbnail of a linked
+    /// page or attachment.
+    pub fn preview(content_type: impl Into<String>, content: impl Into<Vec<u8>>) -> Self {
+        Self::SinglePart {
+            disposition: Disposition::Preview,
+            language: "".to_owned(),
+            content_type: content_type.into(),
+            content: content.into(),
+        }
+    }
+
+    /// Creates a single part carrying an icon, e.g. of a room.
+    pub fn icon(content_type: impl Into<String>, content: impl Into<Vec<u8>>) -> Self {
+        Self::SinglePart {
+            disposition: Disposition::Icon,
+            language: "".to_owned(),
+            content_type: content_type.into(),
+            content: content.into(),
+        }
+    }
+
+    pub fn disposition(&self) -> Disposition {
+        match self {
+            NestedPart::NullPart { disposition, .. } => *disposition,
+            NestedPart::SinglePart { disposition, .. } => *disposition,
+            NestedPart::ExternalPart { disposition, .. } => *disposition,
+            NestedPart::MultiPart { disposition, .. } => *disposition,
+        }
+    }
+
+    /// Creates a single part embedding `content`, e.g. to forward a message.
+    pub fn embedded_mimi(content: &MimiContent) -> Result<Self> {
+        Ok(Self::SinglePart {
+            disposition: Disposition::Render,
+            language: "".to_owned(),
+            content_type: "application/mimi-content".to_owned(),
+            content: content.serialize()?,
+        })
+    }
+
+    /// Returns the content type of a single or external part.
+    pub fn content_type(&self) -> Option<&str> {
+        match self {
+            NestedPart::SinglePart { content_type, .. }
+            | NestedPart::ExternalPart { content_type, .. } => Some(content_type.as_str()),
+            NestedPart::NullPart { .. } | NestedPart::MultiPart { .. } => None,
+        }
+    }
+
+    fn renderable_with(&self, supported: &BTreeSet<String>) -> bool {
+        match self {
+            NestedPart::NullPart { .. } => true,
+            NestedPart::SinglePart { content_type, .. }
+            | NestedPart::ExternalPart { content_type, .. } => {
+                let media_type = MediaType::parse(content_type);
+                supported.iter().any(|essence| media_type.is(essence))
+            }
+            NestedPart::MultiPart {
+                part_semantics: PartSemantics::ChooseOne,
+                parts,
+                ..
+            } => parts.iter().any(|part| part.renderable_with(supported)),
+            NestedPart::MultiPart { parts, .. } => {
+                parts.iter().all(|part| part.renderable_with(supported))
+            }
+        }
+    }
+
+    /// Returns the URL of an external part.
+    pub fn external_url(&self) -> Option<&str> {
+        match self {
+            NestedPart::ExternalPart { url, .. } => Some(url),
+            _ => None,
+        }
+    }
+
+    /// Returns the size of an external part.
+    ///
+    /// The size is declared by the sender and not checked against any limit,
+    /// see [`MimiContent::validate_limits`] before trusting it, e.g. to
+    /// preallocate a buffer for the download.
+    pub fn external_size(&self) -> Option<u64> {
+        match self {
+            NestedPart::ExternalPart { size, .. } => Some(*size),
+            _ => None,
+        }
+    }
+
+    /// Compares two parts, optionally ignoring the `language` of all parts,
+    /// e.g. to match a translated alternative against the original.
+    pub fn semantic_eq(&self, other: &NestedPart, ignore_language: bool) -> bool {
+        if !ignore_language {
+            return self == other;
+        }
+        match (self, other) {
+            (
+                NestedPart::MultiPart {
+                    disposition,
+                    part_semantics,
+                    parts,
+                    ..
+                },
+                NestedPart::MultiPart {
+                    disposition: other_disposition,
+                    part_semantics: other_part_semantics,
+                    parts: other_parts,
+                    ..
+                },
+            ) => {
+                disposition == other_disposition
+                    && part_semantics == other_part_semantics
+                    && parts.len() == other_parts.len()
+                    && std::iter::zip(parts, other_parts)
+                        .all(|(part, other_part)| part.semantic_eq(other_part, true))
+            }
+            (NestedPart::MultiPart { .. }, _) | (_, NestedPart::MultiPart { .. }) => false,
+            _ => {
+                // Borrowed parts compare the content without copying it
+                let mut other = other.as_part_ref();
+                if let NestedPartRef::NullPart { language, .. }
+                | NestedPartRef::SinglePart { language, .. }
+                | NestedPartRef::ExternalPart { language, .. } = &mut other
+                {
+                    *language = self.language();
+                }
+                self.as_part_ref() == other
+            }
+        }
+    }
+
+    pub fn language(&self) -> &str {
+        match self {
+            NestedPart::NullPart { language, .. }
+            | NestedPart::SinglePart { language, .. }
+            | NestedPart::ExternalPart { language, .. }
+            | NestedPart::MultiPart { language, .. } => language,
+        }
+    }
+
+    /// Returns whether the part can be rendered as text.
+    ///
+    /// This holds for single parts of type `text/markdown` or `text/plain`
+    /// and for multiparts with at least one such part.
+    pub fn is_renderable(&self) -> bool {
+        match self {
+            NestedPart::SinglePart { content_type, .. } => {
+                let media_type = MediaType::parse(content_type);
+                media_type.is("text/markdown") || media_type.is("text/plain")
+            }
+            NestedPart::MultiPart { parts, .. } => parts.iter().any(NestedPart::is_renderable),
+            NestedPart::NullPart { .. } | NestedPart::ExternalPart { .. } => false,
+        }
+    }
+
+    pub fn is_null_part(&self) -> bool {
+        matches!(self, NestedPart::NullPart { .. })
+    }
+
+    pub fn is_single_part(&self) -> bool {
+        matches!(self, NestedPart::SinglePart { .. })
+    }
+
+    pub fn is_external_part(&self) -> bool {
+        matches!(self, NestedPart::ExternalPart { .. })
+    }
+
+    pub fn is_multi_part(&self) -> bool {
+        matches!(self, NestedPart::MultiPart { .. })
+    }
+
+    /// Sets the AAD of an external part to the CBOR encoding of `value`.
+    pub fn set_aad(&mut self, value: &cbor::Value) -> Result<()> {
+        let NestedPart::ExternalPart { aad, .. } = self else {
+            return Err(Error::NotExternalPart);
+        };
+        let mut buf = Vec::new();
+        minicbor::encode(value, &mut buf).map_err(Error::Encode)?;
+        *aad = buf;
+        Ok(())
+    }
+
+    /// Checks the downloaded and decrypted `data` against the content hash of
+    /// an external part.
+    ///
+    /// Returns [`Error::SizeTooLarge`] without hashing if the declared size or
+    /// the length of `data` exceeds `limits.max_external_size`,
+    /// [`Error::NotExternalPart`] for other parts and
+    /// [`Error::UnsupportedHashAlgorithm`] if the hash algorithm is not
+    /// supported.
+    pub fn verify_content_hash(&self, data: &[u8], limits: &Limits) -> Result<bool> {
+        let NestedPart::ExternalPart {
+            size,
+            hash_alg,
+            content_hash,
+            ..
+        } = self
+        else {
+            return Err(Error::NotExternalPart);
+        };
+        if *size > limits.max_external_size || data.len() as u64 > limits.max_external_size {
+            return Err(Error::SizeTooLarge);
+        }
+        let digest = hash_alg
+            .digest(data)
+            .ok_or(Error::UnsupportedHashAlgorithm)?;
+        Ok(digest == *content_hash)
+    }
+
+    /// Sets `size` and `content_hash` of an external part from `plaintext`.
+    ///
+    /// With `HashAlgorithm::Unspecified`, `content_hash` is left empty.
+    pub fn finalize(&mut self, plaintext: &[u8]) -> Result<()> {
+        let NestedPart::ExternalPart {
+            size,
+            hash_alg,
+            content_hash,
+            ..
+        } = self
+        else {
+            return Err(Error::NotExternalPart);
+        };
+        *content_hash = match hash_alg {
+            HashAlgorithm::Unspecified => Vec::new(),
+            _ => hash_alg
+                .digest(plaintext)
+                .ok_or(Error::UnsupportedHashAlgorithm)?,
+        };
+        *size = plaintext.len() as u64;
+        Ok(())
+    }
+
+    /// Calls `f` with every part in the tree, depth first and starting with
+    /// this part.
+    ///
+    /// The path passed to `f` holds the indices into the `parts` of each
+    /// enclosing multipart, and is empty for this part.
+    pub fn walk<'a>(&'a self, mut f: impl FnMut(&[usize], &'a NestedPart)) {
+        self.walk_inner(&mut Vec::new(), &mut f);
+    }
+
+    fn walk_inner<'a>(
+        &'a self,
+        path: &mut Vec<usize>,
+        f: &mut impl FnMut(&[usize], &'a NestedPart),
+    ) {
+        f(path, self);
+        if let NestedPart::MultiPart { parts, .. } = self {
+            for (index, part) in parts.iter().enumerate() {
+                path.push(index);
+                part.walk_inner(path, f);
+                path.pop();
+            }
+        }
+    }
+
+    fn normalize_languages(&mut self) {
+        let language = match self {
+            NestedPart::NullPart { language, .. }
+            | NestedPart::SinglePart { language, .. }
+            | NestedPart::ExternalPart { language, .. }
+            | NestedPart::MultiPart { language, .. } => language,
+        };
+        *language = language
+            .split(',')
+            .map(|tag| normalize_language_tag(tag.trim()))
+            .collect::<Vec<_>>()
+            .join(",");
+        if let NestedPart::MultiPart { parts, .. } = self {
+            for part in parts {
+                part.normalize_languages();
+            }
+        }
+    }
+
+    fn ensure_known_discriminants(&self) -> Result<()> {
+        if let Disposition::Custom(value) = self.disposition() {
+            return Err(Error::UnknownDiscriminant {
+                enum_name: "Disposition",
+                value: value.into(),
+            });
+        }
+        if let NestedPart::MultiPart {
+            part_semantics,
+            parts,
+            ..
+        } = self
+        {
+            if let PartSemantics::Custom(value) = part_semantics {
+                return Err(Error::UnknownDiscriminant {
+                    enum_name: "PartSemantics",
+                    value: (*value).into(),
+                });
+            }
+            for part in parts {
+                part.ensure_known_discriminants()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks `content` against the `charset` parameter of `content_type`, if
+/// any. Only UTF-8 and US-ASCII are supported.
+fn check_charset(content_type: &str, content: &[u8], part_path: &[usize]) -> Result<()> {
+    let Some(charset) = MediaType::parse(content_type).parameter("charset") else {
+        return Ok(());
+    };
+    let valid = if charset.eq_ignore_ascii_case("utf-8") {
+        std::str::from_utf8(content).is_ok()
+    } else if charset.eq_ignore_ascii_case("us-ascii") {
+        content.is_ascii()
+    } else {
+        return Err(Error::UnsupportedCharset {
+            part_path: part_path.to_vec(),
+        });
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::CharsetMismatch {
+            part_path: part_path.to_vec(),
+        })
+    }
+}
+
+/// Returns whether `markdown` contains raw HTML or a disallowed autolink.
+/// See [`MimiContent::validate_markdown_variant`].
+///
+/// Content that is not UTF-8 is checked after replacing invalid sequences.
+#[cfg(feature = "markdown")]
+fn uses_disallowed_markdown(markdown: &[u8]) -> bool {
+    use pulldown_cmark::{Event, LinkType, Tag};
+
+    let markdown = String::from_utf8_lossy(markdown);
+    crate::markdown::parser(&markdown).any(|event| match event {
+        Event::Html(_) | Event::InlineHtml(_) => true,
+        Event::Start(Tag::Link {
+            link_type: LinkType::Autolink,
+            dest_url,
+            ..
+        }) => {
+            let scheme = dest_url.split(':').next().unwrap_or_default();
+            !crate::markdown::LINK_SCHEMES
+                .iter()
+                .any(|allowed| scheme.eq_ignore_ascii_case(allowed))
+        }
+        _ => false,
+    })
+}
+
+fn normalize_language_tag(tag: &str) -> String {
+    // Subtags after a singleton (extensions and private use) are lowercase
+    let mut after_singleton = false;
+    tag.split('-')
+        .enumerate()
+        .map(|(index, subtag)| {
+            let normalized = if index == 0 || after_singleton {
+                subtag.to_ascii_lowercase()
+            } else if subtag.len() == 2 {
+                subtag.to_ascii_uppercase()
+            } else if subtag.len() == 4 {
+                let (first, rest) = subtag.split_at(1);
+                first.to_ascii_uppercase() + &rest.to_ascii_lowercase()
+            } else {
+                subtag.to_ascii_lowercase()
+            };
+            after_singleton |= subtag.len() == 1;
+            normalized
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+impl<C> minicbor::Encode<C> for NestedPart {
+    fn encode<W: minicbor::encode::Write>(
+        &self,
+        e: &mut minicbor::Encoder<W>,
+        ctx: &mut C,
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        self.as_part_ref().encode(e, ctx)
+    }
+}
+
+impl<C> minicbor::Decode<'_, C> for NestedPart {
+    fn decode(d: &mut minicbor::Decoder<'_>, ctx: &mut C) -> Result<Self, minicbor::decode::Error> {
+        NestedPartRef::decode(d, ctx).map(NestedPartRef::into_owned)
+    }
+}
+
+/// Decodes the parts of a multipart at `depth` with `decode_part`.
+///
+/// Fails if the parts would be nested deeper than [`MAX_PART_DEPTH`].
+fn decode_parts<'b, T>(
+    d: &mut minicbor::Decoder<'b>,
+    depth: usize,
+    mut decode_part: impl FnMut(&mut minicbor::Decoder<'b>, usize) -> Result<T, minicbor::decode::Error>,
+) -> Result<Vec<T>, minicbor::decode::Error> {
+    if depth >= MAX_PART_DEPTH {
+        return Err(minicbor::decode::Error::message(
+            "multiparts nested too deeply",
+        ));
+    }
+    let mut parts = Vec::new();
+    match d.array()? {
+        Some(len) => {
+            for _ in 0..len {
+                parts.push(decode_part(d, depth + 1)?);
+            }
+        }
+        None => {
+            while d.datatype()? != minicbor::data::Type::Break {
+                parts.push(decode_part(d, depth + 1)?);
+            }
+            d.skip()?;
+        }
+    }
+    Ok(parts)
+}
+
+/// Checks that a part has the shape of a [`NestedPart`] without
+/// materializing it.
+///
+/// Parts of a multipart are collected into a `Vec<()>`, which does not
+/// allocate. On failure, `path`, if given, holds the indices into the `parts` of each
+/// enclosing multipart of the part that failed, as passed by
+/// [`NestedPart::walk`].
+fn check_part_shape(
+    d: &mut minicbor::Decoder<'_>,
+    depth: usize,
+    mut path: Option<&mut Vec<usize>>,
+) -> Result<(), minicbor::decode::Error> {
+    let array = d.array()?;
+    d.decode::<Disposition>()?;
+    d.str()?;
+    match (array, d.u8()?) {
+        (Some(3), 0) => {}
+        (Some(5), 1) => {
+            d.str()?;
+            d.bytes()?;
+        }
+        (Some(15), 2) => {
+            d.str()?;
+            d.str()?;
+            d.u32()?;
+            d.u64()?;
+            d.decode::<EncryptionAlgorithm>()?;
+            d.bytes()?;
+            d.bytes()?;
+            d.bytes()?;
+            d.decode::<HashAlgorithm>()?;
+            d.bytes()?;
+            d.str()?;
+            d.str()?;
+        }
+        (Some(5), 3) => {
+            d.decode::<PartSemantics>()?;
+            let mut index = 0;
+            decode_parts(d, depth, |d, depth| {
+                if let Some(path) = path.as_deref_mut() {
+                    path.push(index);
+                }
+                check_part_shape(d, depth, path.as_deref_mut())?;
+                if let Some(path) = path.as_deref_mut() {
+                    path.pop();
+                }
+                index += 1;
+                Ok(())
+            })?;
+        }
+        _ => {
+            return Err(minicbor::decode::Error::message(
+                "invalid discriminant for NestedPart",
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Borrowed counterpart of [`NestedPart`].
+///
+/// Strings and byte strings are borrowed instead of owned, so large inline
+/// payloads can be encoded without copying them into a [`NestedPart`] first.
+/// [`NestedPart`] is encoded and decoded through this type, so both have the
+/// same layout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NestedPartRef<'a> {
+    NullPart {
+        disposition: Disposition,
+        language: &'a str,
+    },
+    SinglePart {
+        disposition: Disposition,
+        language: &'a str,
+        content_type: &'a str,
+        content: &'a [u8],
+    },
+    ExternalPart {
+        disposition: Disposition,
+        language: &'a str,
+        content_type: &'a str,
+        url: &'a str,
+        expires: u32,
+        size: u64,
+        enc_alg: EncryptionAlgorithm,
+        key: &'a [u8],
+        nonce: &'a [u8],
+        aad: &'a [u8],
+        hash_alg: HashAlgorithm,
+        content_hash: &'a [u8],
+        description: &'a str,
+        filename: &'a str,
+    },
+    MultiPart {
+        disposition: Disposition,
+        language: &'a str,
+        part_semantics: PartSemantics,
+        parts: Vec<NestedPartRef<'a>>,
+    },
+}
+
+impl NestedPart {
+    /// Creates a borrowed single part without copying `content`.
+    pub fn single_borrowed<'a>(
+        disposition: Disposition,
+        content_type: &'a str,
+        content: &'a [u8],
+    ) -> NestedPartRef<'a> {
+        NestedPartRef::SinglePart {
+            disposition,
+            language: "",
+            content_type,
+            content,
+        }
+    }
+
+    pub fn as_part_ref(&self) -> NestedPartRef<'_> {
+        match self {
+            NestedPart::NullPart {
+                disposition,
+                language,
+            } => NestedPartRef::NullPart {
+                disposition: *disposition,
+                language,
+            },
+            NestedPart::SinglePart {
+                disposition,
+                language,
+                content_type,
+                content,
+            } => NestedPartRef::SinglePart {
+                disposition: *disposition,
+                language,
+                content_type,
+                content,
+            },
+            NestedPart::ExternalPart {
+                disposition,
+                language,
+                content_type,
+                url,
+                expires,
+                size,
+                enc_alg,
+                key,
+                nonce,
+                aad,
+                hash_alg,
+                content_hash,
+                description,
+                filename,
+            } => NestedPartRef::ExternalPart {
+                disposition: *disposition,
+                language,
+                content_type,
+                url,
+                expires: *expires,
+                size: *size,
+                enc_alg: *enc_alg,
+                key,
+                nonce,
+                aad,
+                hash_alg: *hash_alg,
+                content_hash,
+                description,
+                filename,
+            },
+            NestedPart::MultiPart {
+                disposition,
+                language,
+                part_semantics,
+                parts,
+            } => NestedPartRef::MultiPart {
+                disposition: *disposition,
+                language,
+                part_semantics: *part_semantics,
+                parts: parts.iter().map(NestedPart::as_part_ref).collect(),
+            },
+        }
+    }
+}
+
+impl NestedPartRef<'_> {
+    pub fn into_owned(self) -> NestedPart {
+        match self {
+            NestedPartRef::NullPart {
+                disposition,
+                language,
+            } => NestedPart::NullPart {
+                disposition,
+                language: language.to_owned(),
+            },
+            NestedPartRef::SinglePart {
+                disposition,
+                language,
+                content_type,
+                content,
+            } => NestedPart::SinglePart {
+                disposition,
+                language: language.to_owned(),
+                content_type: content_type.to_owned(),
+                content: content.to_vec(),
+            },
+            NestedPartRef::ExternalPart {
+                disposition,
+                language,
+                content_type,
+                url,
+                expires,
+                size,
+                enc_alg,
+                key,
+                nonce,
+                aad,
+                hash_alg,
+                content_hash,
+                description,
+                filename,
+            } => NestedPart::ExternalPart {
+                disposition,
+                language: language.to_owned(),
+                content_type: content_type.to_owned(),
+                url: url.to_owned(),
+                expires,
+                size,
+                enc_alg,
+                key: key.to_vec(),
+                nonce: nonce.to_vec(),
+                aad: aad.to_vec(),
+                hash_alg,
+                content_hash: content_hash.to_vec(),
+                description: description.to_owned(),
+                filename: filename.to_owned(),
+            },
+            NestedPartRef::MultiPart {
+                disposition,
+                language,
+                part_semantics,
+                parts,
+            } => NestedPart::MultiPart {
+                disposition,
+                language: language.to_owned(),
+                part_semantics,
+                parts: parts.into_iter().map(NestedPartRef::into_owned).collect(),
+            },
+        }
+    }
+}
+
+impl<C> minicbor::Encode<C> for NestedPartRef<'_> {
+    fn encode<W: minicbor::encode::Write>(
+        &self,
+        e: &mut minicbor::Encoder<W>,
+        _ctx: &mut C,
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        match self {
+            NestedPartRef::NullPart {
+                disposition,
+                language,
+            } => {
+                e.array(3)?.encode(disposition)?.str(language)?.u8(0)?;
+            }
+            NestedPartRef::SinglePart {
+                disposition,
+                language,
+                content_type,
+                content,
+            } => {
+                e.array(5)?
+                    .encode(disposition)?
+                    .str(language)?
+                    .u8(1)?
+                    .str(content_type)?
+                    .bytes(content)?;
+            }
+            NestedPartRef::ExternalPart {
+                disposition,
+                language,
+                content_type,
+                url,
+                expires,
+                size,
+                enc_alg,
+                key,
+                nonce,
+                aad,
+                hash_alg,
+                content_hash,
+                description,
+                filename,
+            } => {
+                e.array(15)?
+                    .encode(disposition)?
+                    .str(language)?
+                    .u8(2)?
+                    .str(content_type)?
+                    .str(url)?
+                    .u32(*expires)?
+                    .u64(*size)?
+                    .encode(enc_alg)?
+                    .bytes(key)?
+                    .bytes(nonce)?
+                    .bytes(aad)?
+                    .encode(hash_alg)?
+                    .bytes(content_hash)?
+                    .str(description)?
+                    .str(filename)?;
+            }
+            NestedPartRef::MultiPart {
+                disposition,
+                language,
+                part_semantics,
+                parts,
+            } => {
+                e.array(5)?
+                    .encode(disposition)?
+                    .str(language)?
+                    .u8(3)?
+                    .encode(part_semantics)?
+                    .encode(parts)?;
+            }
+        };
+
+        Ok(())
+    }
+}
+
+impl<'b, C> minicbor::Decode<'b, C> for NestedPartRef<'b> {
+    fn decode(
+        d: &mut minicbor::Decoder<'b>,
+        _ctx: &mut C,
+    ) -> Result<Self, minicbor::decode::Error> {
+        Self::decode_at_depth(d, 0)
+    }
+}
+
+impl<'b> NestedPartRef<'b> {
+    fn decode_at_depth(
+        d: &mut minicbor::Decoder<'b>,
+        depth: usize,
+    ) -> Result<Self, minicbor::decode::Error> {
+        let array = d.array()?.ok_or(minicbor::decode::Error::message(
+            "invalid array length for NestedPart",
+        ))?;
+        let disposition = d.decode()?;
+        let language = d.str()?;
+        let discriminant = d.u8()?;
+        match (array, discriminant) {
+            (3, 0) => Ok(NestedPartRef::NullPart {
+                disposition,
+                language,
+            }),
+            (5, 1) => Ok(NestedPartRef::SinglePart {
+                disposition,
+                language,
+                content_type: d.str()?,
+                content: d.bytes()?,
+            }),
+            (15, 2) => Ok(NestedPartRef::ExternalPart {
+                disposition,
+                language,
+                content_type: d.str()?,
+                url: d.str()?,
+                expires: d.u32()?,
+                size: d.u64()?,
+                enc_alg: d.decode()?,
+                key: d.bytes()?,
+                nonce: d.bytes()?,
+                aad: d.bytes()?,
+                hash_alg: d.decode()?,
+                content_hash: d.bytes()?,
+                description: d.str()?,
+                filename: d.str()?,
+            }),
+            (5, 3) => {
+                let part_semantics = d.decode()?;
+                let parts = decode_parts(d, depth, Self::decode_at_depth)?;
+                Ok(NestedPartRef::MultiPart {
+                    disposition,
+                    language,
+                    part_semantics,
+                    parts,
+                })
+            }
+            _ => Err(minicbor::decode::Error::message(
+                "invalid discriminant for NestedPart",
+            )),
+        }
+    }
+}
+
+/// Borrowed counterpart of [`MimiContent`].
+///
+/// Decoding borrows strings and byte strings from the input, so a message
+/// can be inspected without copying its payload. Extensions are borrowed from
+/// a [`MimiContent`] but owned when decoded. Encodes to exactly the same
+/// bytes as the equivalent owned content.
+#[derive(minicbor_derive::Encode, minicbor_derive::Decode, Debug, Clone, PartialEq)]
+#[cbor(array)]
+pub struct MimiContentRef<'a> {
+    #[b(0)]
+    #[cbor(with = "minicbor::bytes")]
+    pub salt: &'a [u8],
+    #[b(1)]
+    #[cbor(with = "minicbor::bytes")]
+    pub replaces: Option<&'a [u8]>,
+    #[b(2)]
+    #[cbor(with = "minicbor::bytes")]
+    pub topic_id: &'a [u8],
+    #[n(3)]
+    pub expires: Option<Expiration>,
+    #[b(4)]
+    #[cbor(with = "minicbor::bytes")]
+    pub in_reply_to: Option<&'a [u8]>,
+    #[n(5)]
+    pub extensions: Cow<'a, BTreeMap<ExtensionName, cbor::Value>>,
+    #[b(6)]
+    pub nested_part: NestedPartRef<'a>,
+}
+
+impl MimiContent {
+    pub fn as_content_ref(&self) -> MimiContentRef<'_> {
+        MimiContentRef {
+            salt: &self.salt,
+            replaces: self.replaces.as_deref(),
+            topic_id: &self.topic_id,
+            expires: self.expires.clone(),
+            in_reply_to: self.in_reply_to.as_deref(),
+            extensions: Cow::Borrowed(&self.extensions),
+            nested_part: self.nested_part.as_part_ref(),
+        }
+    }
+}
+
+impl<'a> MimiContentRef<'a> {
+    /// Deserializes content, borrowing from `input`.
+    pub fn deserialize(input: &'a [u8]) -> Result<Self> {
+        minicbor::decode(input).map_err(Error::Decode)
+    }
+
+    pub fn into_owned(self) -> MimiContent {
+        MimiContent {
+            salt: self.salt.to_vec(),
+            replaces: self.replaces.map(<[u8]>::to_vec),
+            topic_id: self.topic_id.to_vec(),
+            expires: self.expires,
+            in_reply_to: self.in_reply_to.map(<[u8]>::to_vec),
+            extensions: self.extensions.into_owned(),
+            nested_part: self.nested_part.into_owned(),
+        }
+    }
+}
+
+/// Disposition of a part.
+///
+/// Values 0 to 8 are the named variants. Every other value decodes as
+/// `Custom`, so custom dispositions occupy 9 to 255. A `Custom` holding the
+/// value of a named variant encodes to that value and therefore decodes as
+/// the named variant.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, IntoPrimitive, FromPrimitive)]
+#[repr(u8)]
+#[non_exhaustive]
+pub enum Disposition {
+    Unspecified = 0,
+    Render = 1,
+    Reaction = 2,
+    Profile = 3,
+    Inline = 4,
+    Icon = 5,
+    Attachment = 6,
+    Session = 7,
+    Preview = 8,
+    /// Custom disposition in the range 9 to 255
+    #[num_enum(catch_all)]
+    Custom(u8),
+}
+
+impl_encode_decode_num_enum!(Disposition, u8);
+
+impl Disposition {
+    /// Returns all named variants, i.e. every value except `Custom`.
+    pub fn all_known() -> &'static [Self] {
+        &[
+            Self::Unspecified,
+            Self::Render,
+            Self::Reaction,
+            Self::Profile,
+            Self::Inline,
+            Self::Icon,
+            Self::Attachment,
+            Self::Session,
+            Self::Preview,
+        ]
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, IntoPrimitive, FromPrimitive)]
+#[repr(u16)]
+#[non_exhaustive]
+pub enum EncryptionAlgorithm {
+    None = 0,
+    /// Reference: [RFC5116](https://www.rfc-editor.org/rfc/rfc5116.html)
+    Aes128Gcm = 1,
+    /// Reference: [RFC5116](https://www.rfc-editor.org/rfc/rfc5116.html)
+    Aes256Gcm = 2,
+    /// Reference: [RFC5116](https://www.rfc-editor.org/rfc/rfc5116.html)
+    Aes128Ccm = 3,
+    /// Reference: [RFC5116](https://www.rfc-editor.org/rfc/rfc5116.html)
+    Aes256Ccm = 4,
+    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
+    Aes128Gcm8 = 5,
+    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
+    Aes256Gcm8 = 6,
+    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
+    Aes128Gcm12 = 7,
+    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
+    Aes256Gcm12 = 8,
+    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
+    Aes128CcmShort = 9,
+    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
+    Aes256CcmShort = 10,
+    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
+    Aes128CcmShort8 = 11,
+    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
+    Aes256CcmShort8 = 12,
+    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
+    Aes128CcmShort12 = 13,
+    /// Reference: [RFC5282](https://www.rfc-editor.org/rfc/rfc5282.html)
+    Aes256CcmShort12 = 14,
+    /// Reference: [RFC5297](https://www.rfc-editor.org/rfc/rfc5297.html)
+    AesSivCmac256 = 15,
+    /// Reference: [RFC5297](https://www.rfc-editor.org/rfc/rfc5297.html)
+    AesSivCmac384 = 16,
+    /// Reference: [RFC5297](https://www.rfc-editor.org/rfc/rfc5297.html)
+    AesSivCmac512 = 17,
+    /// Reference: [RFC6655](https://www.rfc-editor.org/rfc/rfc6655.html)
+    Aes128Ccm8 = 18,
+    /// Reference: [RFC6655](https://www.rfc-editor.org/rfc/rfc6655.html)
+    Aes256Ccm8 = 19,
+    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
+    Aes128OcbTaglen128 = 20,
+    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
+    Aes128OcbTaglen96 = 21,
+    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
+    Aes128OcbTaglen64 = 22,
+    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
+    Aes192OcbTaglen128 = 23,
+    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
+    Aes192OcbTaglen96 = 24,
+    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
+    Aes192OcbTaglen64 = 25,
+    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
+    Aes256OcbTaglen128 = 26,
+    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
+    Aes256OcbTaglen96 = 27,
+    /// Reference: [RFC7253, Section 3.1](https://www.rfc-editor.org/rfc/rfc7253.html#section-3.1)
+    Aes256OcbTaglen64 = 28,
+    /// Reference: [RFC8439](https://www.rfc-editor.org/rfc/rfc8439.html)
+    Chacha20Poly1305 = 29,
+    /// Reference: [RFC8452](https://www.rfc-editor.org/rfc/rfc8452.html)
+    Aes128GcmSiv = 30,
+    /// Reference: [RFC8452](https://www.rfc-editor.org/rfc/rfc8452.html)
+    Aes256GcmSiv = 31,
+    /// Reference: [draft-irtf-cfrg-aegis-aead-08](https://datatracker.ietf.org/doc/draft-irtf-cfrg-aegis-aead/08/)
+    Aegis128L = 32,
+    /// Reference: [draft-irtf-cfrg-aegis-aead-08](https://datatracker.ietf.org/doc/draft-irtf-cfrg-aegis-aead/08/)
+    Aegis256 = 33,
+    /// Unknown algorithm
+    #[num_enum(catch_all)]
+    Custom(u16),
+}
+
+impl_encode_decode_num_enum!(EncryptionAlgorithm, u16);
+
+impl EncryptionAlgorithm {
+    /// Returns all named variants, i.e. every value except `Custom`.
+    pub fn all_known() -> &'static [Self] {
+        &[
+            Self::None,
+            Self::Aes128Gcm,
+            Self::Aes256Gcm,
+            Self::Aes128Ccm,
+            Self::Aes256Ccm,
+            Self::Aes128Gcm8,
+            Self::Aes256Gcm8,
+            Self::Aes128Gcm12,
+            Self::Aes256Gcm12,
+            Self::Aes128CcmShort,
+            Self::Aes256CcmShort,
+            Self::Aes128CcmShort8,
+            Self::Aes256CcmShort8,
+            Self::Aes128CcmShort12,
+            Self::Aes256CcmShort12,
+            Self::AesSivCmac256,
+            Self::AesSivCmac384,
+            Self::AesSivCmac512,
+            Self::Aes128Ccm8,
+            Self::Aes256Ccm8,
+            Self::Aes128OcbTaglen128,
+            Self::Aes128OcbTaglen96,
+            Self::Aes128OcbTaglen64,
+            Self::Aes192OcbTaglen128,
+            Self::Aes192OcbTaglen96,
+            Self::Aes192OcbTaglen64,
+            Self::Aes256OcbTaglen128,
+            Self::Aes256OcbTaglen96,
+            Self::Aes256OcbTaglen64,
+            Self::Chacha20Poly1305,
+            Self::Aes128GcmSiv,
+            Self::Aes256GcmSiv,
+            Self::Aegis128L,
+            Self::Aegis256,
+        ]
+    }
+}
+
+/// Defaults to no encryption.
+impl Default for EncryptionAlgorithm {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, FromPrimitive)]
+#[repr(u8)]
+#[non_exhaustive]
+pub enum PartSemantics {
+    ChooseOne = 0,
+    SingleUnit = 1,
+    ProcessAll = 2,
+    /// Custom part semantics in the range 3 to 255
+    #[num_enum(catch_all)]
+    Custom(u8),
+}
+
+impl_encode_decode_num_enum!(PartSemantics, u8);
+
+impl PartSemantics {
+    /// Returns all named variants, i.e. every value except `Custom`.
+    pub fn all_known() -> &'static [Self] {
+        &[Self::ChooseOne, Self::SingleUnit, Self::ProcessAll]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::{cbor, hex_decode};
+
+    use super::*;
+
+    fn extensions_alice() -> BTreeMap<ExtensionName, cbor::Value> {
+        let mut extensions = BTreeMap::new();
+        extensions.insert(
+            ExtensionName::Number(1),
+            "mimi://example.com/u/alice-smith".into(),
+        );
+        extensions.insert(
+            ExtensionName::Number(2),
+            "mimi://example.com/r/engineering_team".into(),
+        );
+
+        extensions
+    }
+
+    fn extensions_bob() -> BTreeMap<ExtensionName, cbor::Value> {
+        let mut extensions = BTreeMap::new();
+        extensions.insert(
+            ExtensionName::Number(1),
+            "mimi://example.com/u/bob-jones".into(),
+        );
+        extensions.insert(
+            ExtensionName::Number(2),
+            "mimi://example.com/r/engineering_team".into(),
+        );
+
+        extensions
+    }
+
+    fn extensions_cathy() -> BTreeMap<ExtensionName, cbor::Value> {
+        let mut extensions = BTreeMap::new();
+        extensions.insert(
+            ExtensionName::Number(1),
+            "mimi://example.com/u/cathy-washington".into(),
+        );
+        extensions.insert(
+            ExtensionName::Number(2),
+            "mimi://example.com/r/engineering_team".into(),
+        );
+
+        extensions
+    }
+
+    #[test]
+    fn original_message() {
+        let value = MimiContent {
+            salt: hex::decode("5eed9406c2545547ab6f09f20a18b003").unwrap(),
+            replaces: None,
+            topic_id: b"".to_vec(),
+            expires: None,
+            in_reply_to: None,
+            extensions: extensions_alice(),
+            nested_part: NestedPart::SinglePart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+                content_type: "text/markdown;variant=GFM-MIMI".to_owned(),
+                content: b"Hi everyone, we just shipped release 2.0. __Good  work__!".to_vec(),
+            },
+        };
+
+        assert_eq!(
+            hex::encode(
+                value
+                    .message_id(
+                        b"mimi://example.com/u/alice-smith",
+                        b"mimi://example.com/r/engineering_team"
+                    )
+                    .unwrap()
+            ),
+            "01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79"
+        );
+
+        let result = value.serialize().unwrap();
+
+        // Test deserialization
+        let value2 = MimiContent::deserialize(&result).unwrap();
+        assert_eq!(value, value2);
+
+        // TODO: Mimi draft is wrong here
+        let target = hex_decode(
+            r#"
+            87                                      # array(7)
+               50                                   # bytes(16)
+                  5eed9406c2545547ab6f09f20a18b003
+               f6                                   # primitive(22)
+               40                                   # bytes(0)
+               f6                                   # primitive(22)
+               f6                                   # primitive(22)
+               a2                                   # map(2)
+                  01                                # unsigned(1)
+                  78 20                             # text(32)
+                     6d696d693a2f2f6578616d706c652e63
+                     6f6d2f752f616c6963652d736d697468
+                     # "mimi://example.com/u/alice-smith"
+                  02                                # unsigned(2)
+                  78 25                             # text(37)
+                     6d696d693a2f2f6578616d706c652e63
+                     6f6d2f722f656e67696e656572696e67
+                     5f7465616d
+                     # "mimi://example.com/r/engineering_team"
+               85                                   # array(5)
+                  01                                # unsigned(1)
+                  60                                # text(0)
+                                                    # ""
+                  01                                # unsigned(1)
+                  78 1e                             # text(30)
+                     746578742f6d61726b646f776e3b7661
+                     7269616e743d47464d2d4d494d49
+                     # "text/markdown;variant=GFM-MIMI"
+                  58 39                             # bytes(57)
+                     48692065766572796f6e652c20776520
+                     6a75737420736869707065642072656c
+                     6561736520322e302e205f5f476f6f64
+                     2020776f726b5f5f21
+                     # "Hi everyone, we just shipped release 2.0. __Good  work__!"
+
+
+            "#,
+        );
+
+        assert_eq!(hex::encode(result), hex::encode(target));
+    }
+
+    #[test]
+    fn reply() {
+        let value = MimiContent {
+            salt: hex::decode("11a458c73b8dd2cf404db4b378b8fe4d").unwrap(),
+            replaces: None,
+            topic_id: b"".to_vec(),
+            expires: None,
+            in_reply_to: Some(
+                hex::decode("01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79")
+                    .unwrap(),
+            ),
+            extensions: extensions_bob(),
+            nested_part: NestedPart::SinglePart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+                content_type: "text/markdown;variant=GFM-MIMI".to_owned(),
+                content: b"Right on! _Congratulations_ 'all!".to_vec(),
+            },
+        };
+
+        let result = value.serialize().unwrap();
+
+        assert_eq!(
+            hex::encode(
+                value
+                    .message_id(
+                        b"mimi://example.com/u/bob-jones",
+                        b"mimi://example.com/r/engineering_team"
+                    )
+                    .unwrap()
+            ),
+            "01a419aef4e16d43cfc06c28235ecfbe9faebc740d0148e7ca20b22150930836"
+        );
+
+        // Test deserialization
+        let value2 = MimiContent::deserialize(&result).unwrap();
+        assert_eq!(value, value2);
+
+        // Taken from MIMI content format draft
+        let target = hex_decode(
+            r#"
+            87                                      # array(7)
+               50                                   # bytes(16)
+                  11a458c73b8dd2cf404db4b378b8fe4d
+               f6                                   # primitive(22)
+               40                                   # bytes(0)
+               f6                                   # primitive(22)
+               58 20                                # bytes(32)
+                  01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79
+               a2                                   # map(2)
+                  01                                # unsigned(1)
+                  78 1e                             # text(30)
+                     6d696d693a2f2f6578616d706c652e63
+                     6f6d2f752f626f622d6a6f6e6573
+                     # "mimi://example.com/u/bob-jones"
+                  02                                # unsigned(2)
+                  78 25                             # text(37)
+                     6d696d693a2f2f6578616d706c652e63
+                     6f6d2f722f656e67696e656572696e67
+                     5f7465616d
+                     # "mimi://example.com/r/engineering_team"
+               85                                   # array(5)
+                  01                                # unsigned(1)
+                  60                                # text(0)
+                                                    # ""
+                  01                                # unsigned(1)
+                  78 1e                             # text(30)
+                     746578742f6d61726b646f776e3b7661
+                     7269616e743d47464d2d4d494d49
+                     # "text/markdown;variant=GFM-MIMI"
+                  58 21                             # bytes(33)
+                     5269676874206f6e21205f436f6e6772
+                     6174756c6174696f6e735f2027616c6c
+                     21
+                     # "Right on! _Congratulations_ 'all!"
+            "#,
+        );
+
+        assert_eq!(hex::encode(result), hex::encode(target));
+    }
+
+    #[test]
+    fn reaction() {
+        let value = MimiContent {
+            salt: hex::decode("d37bc0e6a8b4f04e9e6382375f587bf6").unwrap(),
+            replaces: None,
+            topic_id: b"".to_vec(),
+            expires: None,
+            in_reply_to: Some(
+                hex::decode("01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79")
+                    .unwrap(),
+            ),
+            extensions: extensions_cathy(),
+            nested_part: NestedPart::SinglePart {
+                disposition: Disposition::Reaction,
+                language: "".to_owned(),
+                content_type: "text/plain;charset=utf-8".to_owned(),
+                content: "❤".as_bytes().to_vec(),
+            },
+        };
+
+        let result = value.serialize().unwrap();
+
+        assert_eq!(
+            hex::encode(
+                value
+                    .message_id(
+                        b"mimi://example.com/u/cathy-washington",
+                        b"mimi://example.com/r/engineering_team"
+                    )
+                    .unwrap()
+            ),
+            "01b1a14a88f4480e1336be86987854f838a3ec82944d4533d8d4088578550ed7"
+        );
+
+        // Test deserialization
+        let value2 = MimiContent::deserialize(&result).unwrap();
+        assert_eq!(value, value2);
+
+        // TODO: Draft repo has wrong message ids here
+        let target = hex_decode(
+            r#"
+            87                                      # array(7)
+               50                                   # bytes(16)
+                  d37bc0e6a8b4f04e9e6382375f587bf6
+               f6                                   # primitive(22)
+               40                                   # bytes(0)
+               f6                                   # primitive(22)
+               58 20                                # bytes(32)
+               01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79
+               a2                                   # map(2)
+                  01                                # unsigned(1)
+                  78 25                             # text(37)
+                     6d696d693a2f2f6578616d706c652e63
+                     6f6d2f752f63617468792d7761736869
+                     6e67746f6e
+                     # "mimi://example.com/u/cathy-washington"
+                  02                                # unsigned(2)
+                  78 25                             # text(37)
+                     6d696d693a2f2f6578616d706c652e63
+                     6f6d2f722f656e67696e656572696e67
+                     5f7465616d
+                     # "mimi://example.com/r/engineering_team"
+               85                                   # array(5)
+                  02                                # unsigned(2)
+                  60                                # text(0)
+                                                    # ""
+                  01                                # unsigned(1)
+                  78 18                             # text(24)
+                     746578742f706c61696e3b6368617273
+                     65743d7574662d38
+                     # "text/plain;charset=utf-8"
+                  43                                # bytes(3)
+                     e29da4                         # "❤"
+            "#,
+        );
+
+        assert_eq!(hex::encode(result), hex::encode(target));
+    }
+
+    #[test]
+    fn edit() {
+        let value = MimiContent {
+            salt: hex::decode("b8c2e6d8800ecf45df39be6c45f4c042").unwrap(),
+            replaces: Some(
+                hex::decode(b"01a419aef4e16d43cfc06c28235ecfbe9faebc740d0148e7ca20b22150930836")
+                    .unwrap(),
+            ),
+            topic_id: b"".to_vec(),
+            expires: None,
+            in_reply_to: Some(
+                hex::decode("01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79")
+                    .unwrap(),
+            ),
+            extensions: extensions_bob(),
+            nested_part: NestedPart::SinglePart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+                content_type: "text/markdown;variant=GFM-MIMI".to_owned(),
+                content: b"Right on! _Congratulations_ y'all!".to_vec(),
+            },
+        };
+
+        let result = value.serialize().unwrap();
+
+        assert_eq!(
+            hex::encode(
+                value
+                    .message_id(
+                        b"mimi://example.com/u/bob-jones",
+                        b"mimi://example.com/r/engineering_team"
+                    )
+                    .unwrap()
+            ),
+            "01fdcd2f418e4b16f6ba319800a44c12b3b0730871f29385bdc6d151b15751ad"
+        );
+
+        // Test deserialization
+        let value2 = MimiContent::deserialize(&result).unwrap();
+        assert_eq!(value, value2);
+
+        // TODO: Draft repo has wrong message ids here
+        let target = hex_decode(
+            r#"
+            87                                      # array(7)
+               50                                   # bytes(16)
+                  b8c2e6d8800ecf45df39be6c45f4c042  # "\xB8\xC2\xE6؀\u000E\xCFE\xDF9\xBElE\xF4\xC0B"
+               58 20                                # bytes(32)
+                  01a419aef4e16d43cfc06c28235ecfbe9faebc740d0148e7ca20b22150930836
+               40                                   # bytes(0)
+                                                    # ""
+               f6                                   # primitive(22)
+               58 20                                # bytes(32)
+                  01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79
+               a2                                   # map(2)
+                  01                                # unsigned(1)
+                  78 1e                             # text(30)
+                     6d696d693a2f2f6578616d706c652e636f6d2f752f626f622d6a6f6e6573 # "mimi://example.com/u/bob-jones"
+                  02                                # unsigned(2)
+                  78 25                             # text(37)
+                     6d696d693a2f2f6578616d706c652e636f6d2f722f656e67696e656572696e675f7465616d # "mimi://example.com/r/engineering_team"
+               85                                   # array(5)
+                  01                                # unsigned(1)
+                  60                                # text(0)
+                                                    # ""
+                  01                                # unsigned(1)
+                  78 1e                             # text(30)
+                     746578742f6d61726b646f776e3b76617269616e743d47464d2d4d494d49 # "text/markdown;variant=GFM-MIMI"
+                  58 22                             # bytes(34)
+                     5269676874206f6e21205f436f6e67726174756c6174696f6e735f207927616c6c21 # "Right on! _Congratulations_ y'all!"
+            "#,
+        );
+
+        assert_eq!(hex::encode(result), hex::encode(target));
+    }
+
+    #[test]
+    fn delete() {
+        let value = MimiContent {
+            salt: hex::decode("0a590d73b2c7761c39168be5ebf7f2e6").unwrap(),
+            replaces: Some(
+                hex::decode(b"01a419aef4e16d43cfc06c28235ecfbe9faebc740d0148e7ca20b22150930836")
+                    .unwrap(),
+            ),
+            topic_id: b"".to_vec(),
+            expires: None,
+            in_reply_to: Some(
+                hex::decode("01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79")
+                    .unwrap(),
+            ),
+            extensions: extensions_bob(),
+            nested_part: NestedPart::NullPart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+            },
+        };
+
+        let result = value.serialize().unwrap();
+
+        assert_eq!(
+            hex::encode(
+                value
+                    .message_id(
+                        b"mimi://example.com/u/bob-jones",
+                        b"mimi://example.com/r/engineering_team"
+                    )
+                    .unwrap()
+            ),
+            "01b85744b443e9db85de5bb826c04bcd65b625e53d17839dc8a3f21321421088"
+        );
+
+        // Test deserialization
+        let value2 = MimiContent::deserialize(&result).unwrap();
+        assert_eq!(value, value2);
+
+        // TODO: Draft repo has wrong message ids here
+        let target = hex_decode(
+            r#"
+            87                                      # array(7)
+                50                                   # bytes(16)
+                0a590d73b2c7761c39168be5ebf7f2e6  # "\nY\rs\xB2\xC7v\u001C9\u0016\x8B\xE5\xEB\xF7\xF2\xE6"
+                58 20                                # bytes(32)
+                01a419aef4e16d43cfc06c28235ecfbe9faebc740d0148e7ca20b22150930836
+                40                                   # bytes(0)
+                                                    # ""
+                f6                                   # primitive(22)
+                58 20                                # bytes(32)
+                01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79
+                a2                                   # map(2)
+                01                                # unsigned(1)
+                78 1e                             # text(30)
+                    6d696d693a2f2f6578616d706c652e636f6d2f752f626f622d6a6f6e6573 # "mimi://example.com/u/bob-jones"
+                02                                # unsigned(2)
+                78 25                             # text(37)
+                    6d696d693a2f2f6578616d706c652e636f6d2f722f656e67696e656572696e675f7465616d # "mimi://example.com/r/engineering_team"
+                83                                   # array(3)
+                01                                # unsigned(1)
+                60                                # text(0)
+                                                    # ""
+                00                                # unsigned(0)
+            "#,
+        );
+
+        assert_eq!(hex::encode(result), hex::encode(target));
+    }
+
+    #[test]
+    fn expiring() {
+        let value = MimiContent {
+            salt: hex::decode("33be993eb39f418f9295afc2ae160d2d")
+                .unwrap(),
+            replaces: None,
+            topic_id: b"".to_vec(),
+            expires: Some(Expiration { relative: false, time: 1644390004 }),
+            in_reply_to: None,
+            extensions: extensions_alice(),
+            nested_part: NestedPart::SinglePart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+                content_type: "text/markdown;variant=GFM-MIMI".to_owned(),
+                content: b"__*VPN GOING DOWN*__ I'm rebooting the VPN in ten minutes unless anyone objects.".to_vec(),
+            },
+        };
+
+        let result = value.serialize().unwrap();
+
+        assert_eq!(
+            hex::encode(
+                value
+                    .message_id(
+                        b"mimi://example.com/u/alice-smith",
+                        b"mimi://example.com/r/engineering_team"
+                    )
+                    .unwrap()
+            ),
+            "0106308e2c03346eba95b24abdfa9fe643aa247debfb7192feae647155316920"
+        );
+
+        // Test deserialization
+        let value2 = MimiContent::deserialize(&result).unwrap();
+        assert_eq!(value, value2);
+
+        // Taken from MIMI content format draft
+        let target = hex_decode(
+            r#"
+            87                                      # array(7)
+               50                                   # bytes(16)
+                  33be993eb39f418f9295afc2ae160d2d
+               f6                                   # primitive(22)
+               40                                   # bytes(0)
+                                                    # ""
+               82                                   # array(2)
+                  f4                                # primitive(20)
+                  1a 62036674                       # unsigned(1644390004)
+               f6                                   # primitive(22)
+               a2                                   # map(2)
+                  01                                # unsigned(1)
+                  78 20                             # text(32)
+                     6d696d693a2f2f6578616d706c652e63
+                     6f6d2f752f616c6963652d736d697468
+                     # "mimi://example.com/u/alice-smith"
+                  02                                # unsigned(2)
+                  78 25                             # text(37)
+                     6d696d693a2f2f6578616d706c652e63
+                     6f6d2f722f656e67696e656572696e67
+                     5f7465616d
+                     # "mimi://example.com/r/engineering_team"
+               85                                   # array(5)
+                  01                                # unsigned(1)
+                  60                                # text(0)
+                                                    # ""
+                  01                                # unsigned(1)
+                  78 1e                             # text(30)
+                     746578742f6d61726b646f776e3b7661
+                     7269616e743d47464d2d4d494d49
+                     # "text/markdown;variant=GFM-MIMI"
+                  58 50                             # bytes(80)
+                     5f5f2a56504e20474f494e4720444f57
+                     4e2a5f5f2049276d207265626f6f7469
+                     6e67207468652056504e20696e207465
+                     6e206d696e7574657320756e6c657373
+                     20616e796f6e65206f626a656374732e
+                     # "__*VPN GOING DOWN*__ I'm rebooting the VPN in" +
+                     # " ten minutes unless anyone objects."
+            "#,
+        );
+
+        assert_eq!(hex::encode(result), hex::encode(target));
+    }
+
+    #[test]
+    fn attachments() {
+        let value = MimiContent {
+            salt: hex::decode("18fac6371e4e53f1aeaf8a013155c166").unwrap(),
+            replaces: None,
+            topic_id: b"".to_vec(),
+            expires: None,
+            in_reply_to: None,
+            extensions: extensions_bob(),
+            nested_part: NestedPart::ExternalPart {
+                disposition: Disposition::Attachment,
+                language: "en".to_owned(),
+                content_type: "video/mp4".to_owned(),
+                url: "https://example.com/storage/8ksB4bSrrRE.mp4".to_owned(),
+                expires: 0,
+                size: 708234961,
+                enc_alg: EncryptionAlgorithm::Aes128Gcm,
+                key: hex::decode("21399320958a6f4c745dde670d95e0d8").unwrap(),
+                nonce: hex::decode("c86cf2c33f21527d1dd76f5b").unwrap(),
+                aad: b"".to_vec(),
+                hash_alg: HashAlgorithm::Sha256,
+                content_hash: hex::decode(
+                    "9ab17a8cf0890baaae7ee016c7312fcc080ba46498389458ee44f0276e783163",
+                )
+                .unwrap(),
+                description: "2 hours of key signing video".to_owned(),
+                filename: "bigfile.mp4".to_owned(),
+            },
+        };
+
+        let result = value.serialize().unwrap();
+
+        assert_eq!(
+            hex::encode(
+                value
+                    .message_id(
+                        b"mimi://example.com/u/bob-jones",
+                        b"mimi://example.com/r/engineering_team"
+                    )
+                    .unwrap()
+            ),
+            "01ad825f6116adeb437a7b1f95a9d9acbcc708f83f5df505d32af9c2826e8b5f"
+        );
+
+        // Test deserialization
+        let value2 = MimiContent::deserialize(&result).unwrap();
+        assert_eq!(value, value2);
+
+        // Taken from MIMI content format draft
+        let target = hex_decode(
+            r#"
+            87                                      # array(7)
+               50                                   # bytes(16)
+                  18fac6371e4e53f1aeaf8a013155c166
+               f6                                   # primitive(22)
+               40                                   # bytes(0)
+                                                    # ""
+               f6                                   # primitive(22)
+               f6                                   # primitive(22)
+               a2                                   # map(2)
+                  01                                # unsigned(1)
+                  78 1e                             # text(30)
+                     6d696d693a2f2f6578616d706c652e63
+                     6f6d2f752f626f622d6a6f6e6573
+                     # "mimi://example.com/u/bob-jones"
+                  02                                # unsigned(2)
+                  78 25                             # text(37)
+                     6d696d693a2f2f6578616d706c652e63
+                     6f6d2f722f656e67696e656572696e67
+                     5f7465616d
+                     # "mimi://example.com/r/engineering_team"
+               8f                                   # array(15)
+                  06                                # unsigned(6)
+                  62                                # text(2)
+                     656e                           # "en"
+                  02                                # unsigned(2)
+                  69                                # text(9)
+                     766964656f2f6d7034             # "video/mp4"
+                  78 2b                             # text(43)
+                     68747470733a2f2f6578616d706c652e
+                     636f6d2f73746f726167652f386b7342
+                     346253727252452e6d7034
+                     # "https://example.com/storage/8ksB4bSrrRE.mp4"
+                  00                                # unsigned(0)
+                  1a 2a36ced1                       # unsigned(708234961)
+                  01                                # unsigned(1)
+                  50                                # bytes(16)
+                     21399320958a6f4c745dde670d95e0d8
+                  4c                                # bytes(12)
+                     c86cf2c33f21527d1dd76f5b
+                  40                                # bytes(0)
+                  01                                # unsigned(1)
+                  58 20                             # bytes(32)
+                     9ab17a8cf0890baaae7ee016c7312fcc
+                     080ba46498389458ee44f0276e783163
+                  78 1c                             # text(28)
+                     3220686f757273206f66206b65792073
+                     69676e696e6720766964656f
+                     # "2 hours of key signing video"
+                  6b                                # text(11)
+                     62696766696c652e6d7034         # "bigfile.mp4"
+            "#,
+        );
+
+        assert_eq!(hex::encode(result), hex::encode(target));
+    }
+
+    #[test]
+    fn conferencing() {
+        let value = MimiContent {
+            salt: hex::decode("678ac6cd54de049c3e9665cd212470fa").unwrap(),
+            replaces: None,
+            topic_id: b"Foo 118".to_vec(),
+            expires: None,
+            in_reply_to: None,
+            extensions: extensions_alice(),
+            nested_part: NestedPart::ExternalPart {
+                disposition: Disposition::Session,
+                language: "".to_owned(),
+                content_type: "".to_owned(),
+                url: "https://example.com/join/12345".to_owned(),
+                expires: 0,
+                size: 0,
+                enc_alg: EncryptionAlgorithm::None,
+                key: Vec::new(),
+                nonce: Vec::new(),
+                aad: Vec::new(),
+                hash_alg: HashAlgorithm::Unspecified,
+                content_hash: b"".to_vec(),
+                description: "Join the Foo 118 conference".to_owned(),
+                filename: "".to_owned(),
+            },
+        };
+
+        let result = value.serialize().unwrap();
+
+        assert_eq!(
+            hex::encode(
+                value
+                    .message_id(
+                        b"mimi://example.com/u/alice-smith",
+                        b"mimi://example.com/r/engineering_team"
+                    )
+                    .unwrap()
+            ),
+            "01d8dab2e22b75dee4f5e52bb181d2d732008a235b80375113803e36b32a5f06"
+        );
+
+        // Test deserialization
+        let value2 = MimiContent::deserialize(&result).unwrap();
+        assert_eq!(value, value2);
+
+        // Taken from MIMI content format draft
+        let target = hex_decode(
+            r#"
+            87                                      # array(7)
+               50                                   # bytes(16)
+                  678ac6cd54de049c3e9665cd212470fa
+               f6                                   # primitive(22)
+               47                                   # bytes(7)
+                  466f6f20313138                    # "Foo 118"
+               f6                                   # primitive(22)
+               f6                                   # primitive(22)
+               a2                                   # map(2)
+                  01                                # unsigned(1)
+                  78 20                             # text(32)
+                     6d696d693a2f2f6578616d706c652e63
+                     6f6d2f752f616c6963652d736d697468
+                     # "mimi://example.com/u/alice-smith"
+                  02                                # unsigned(2)
+                  78 25                             # text(37)
+                     6d696d693a2f2f6578616d706c652e63
+                     6f6d2f722f656e67696e656572696e67
+                     5f7465616d
+                     # "mimi://example.com/r/engineering_team"
+               8f                                   # array(15)
+                  07                                # unsigned(7)
+                  60                                # text(0)
+                                                    # ""
+                  02                                # unsigned(2)
+                  60                                # text(0)
+                                                    # ""
+                  78 1e                             # text(30)
+                     68747470733a2f2f6578616d706c652e
+                     636f6d2f6a6f696e2f3132333435
+                     # "https://example.com/join/12345"
+                  00                                # unsigned(0)
+                  00                                # unsigned(0)
+                  00                                # unsigned(0)
+                  40                                # bytes(0)
+                                                    # ""
+                  40                                # bytes(0)
+                                                    # ""
+                  40                                # bytes(0)
+                                                    # ""
+                  00                                # unsigned(0)
+                  40                                # bytes(0)
+                                                    # ""
+                  78 1b                             # text(27)
+                     4a6f696e2074686520466f6f20313138
+                     20636f6e666572656e6365
+                     # "Join the Foo 118 conference"
+                  60                                # text(0)
+            "#,
+        );
+
+        assert_eq!(hex::encode(result), hex::encode(target));
+    }
+
+    #[test]
+    fn multipart() {
+        let value = MimiContent {
+            salt: hex::decode("261c953e178af653fe3d42641b91d814").unwrap(),
+            replaces: None,
+            topic_id: b"".to_vec(),
+            expires: None,
+            in_reply_to: None,
+            extensions: extensions_alice(),
+            nested_part: NestedPart::MultiPart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+                part_semantics: PartSemantics::ChooseOne,
+                parts: vec![
+                    NestedPart::SinglePart {
+                        disposition: Disposition::Render,
+                        language: "".to_owned(),
+                        content_type: "text/markdown;variant=GFM-MIMI".to_owned(),
+                        content: b"# Welcome!".to_vec(),
+                    },
+                    NestedPart::SinglePart {
+                        disposition: Disposition::Render,
+                        language: "".to_owned(),
+                        content_type: "application/vnd.examplevendor-fancy-im-message".to_owned(),
+                        content: hex::decode("dc861ebaa718fd7c3ca159f71a2001").unwrap(),
+                    },
+                ],
+            },
+        };
+
+        let result = value.serialize().unwrap();
+
+        assert_eq!(
+            hex::encode(
+                value
+                    .message_id(
+                        b"mimi://example.com/u/alice-smith",
+                        b"mimi://example.com/r/engineering_team"
+                    )
+                    .unwrap()
+            ),
+            "015c0469c52da0938c27cfa16702e27735a4729746be5f64bc5838f754828464"
+        );
+
+        // Test deserialization
+        let value2 = MimiContent::deserialize(&result).unwrap();
+        assert_eq!(value, value2);
+
+        // Taken from MIMI content format draft
+        let target = hex_decode(
+            r##"
+            87                                      # array(7)
+                50                                   # bytes(16)
+                    261c953e178af653fe3d42641b91d814  # "&\u001C\x95>\u0017\x8A\xF6S\xFE=Bd\e\x91\xD8\u0014"
+                f6                                   # primitive(22)
+                40                                   # bytes(0)
+                                                    # ""
+                f6                                   # primitive(22)
+                f6                                   # primitive(22)
+                a2                                   # map(2)
+                    01                                # unsigned(1)
+                    78 20                             # text(32)
+                        6d696d693a2f2f6578616d706c652e636f6d2f752f616c6963652d736d697468 # "mimi://example.com/u/alice-smith"
+                    02                                # unsigned(2)
+                    78 25                             # text(37)
+                        6d696d693a2f2f6578616d706c652e636f6d2f722f656e67696e656572696e675f7465616d # "mimi://example.com/r/engineering_team"
+                85                                   # array(5)
+                    01                                # unsigned(1)
+                    60                                # text(0)
+                                                    # ""
+                    03                                # unsigned(3)
+                    00                                # unsigned(0)
+                    82                                # array(2)
+                        85                             # array(5)
+                        01                          # unsigned(1)
+                        60                          # text(0)
+                                                    # ""
+                        01                          # unsigned(1)
+                        78 1e                       # text(30)
+                            746578742f6d61726b646f776e3b76617269616e743d47464d2d4d494d49 # "text/markdown;variant=GFM-MIMI"
+                        4a                          # bytes(10)
+                            232057656c636f6d6521     # "# Welcome!"
+                        85                             # array(5)
+                        01                          # unsigned(1)
+                        60                          # text(0)
+                                                    # ""
+                        01                          # unsigned(1)
+                        78 2e                       # text(46)
+                            6170706c69636174696f6e2f766e642e6578616d706c6576656e646f722d66616e63792d696d2d6d657373616765 # "application/vnd.examplevendor-fancy-im-message"
+                        4f                          # bytes(15)
+                            dc861ebaa718fd7c3ca159f71a2001 # "܆\u001E\xBA\xA7\u0018\xFD|<\xA1Y\xF7\u001A \u0001"
+            "##,
+        );
+
+        assert_eq!(hex::encode(result), hex::encode(target));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn multipart_minicbor_serde_compat() {
+        let value = MimiContent {
+            salt: hex::decode("261c953e178af653fe3d42641b91d814").unwrap(),
+            replaces: None,
+            topic_id: b"".to_vec(),
+            expires: None,
+            in_reply_to: None,
+            extensions: extensions_alice(),
+            nested_part: NestedPart::MultiPart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+                part_semantics: PartSemantics::ChooseOne,
+                parts: vec![
+                    NestedPart::SinglePart {
+                        disposition: Disposition::Render,
+                        language: "".to_owned(),
+                        content_type: "text/markdown;variant=GFM-MIMI".to_owned(),
+                        content: b"# Welcome!".to_vec(),
+                    },
+                    NestedPart::SinglePart {
+                        disposition: Disposition::Render,
+                        language: "".to_owned(),
+                        content_type: "application/vnd.examplevendor-fancy-im-message".to_owned(),
+                        content: hex::decode("dc861ebaa718fd7c3ca159f71a2001").unwrap(),
+                    },
+                ],
+            },
+        };
+
+        let minicbor_bytes = value.serialize().unwrap();
+        let minicbor_serde_bytes = minicbor_serde::to_vec(&value).unwrap();
+
+        assert_eq!(
+            hex::encode(&minicbor_bytes),
+            hex::encode(&minicbor_serde_bytes)
+        );
+
+        let minicbor_content: MimiContent = MimiContent::deserialize(&minicbor_bytes).unwrap();
+        let minicbor_serde_content: MimiContent =
+            minicbor_serde::from_slice(&minicbor_serde_bytes).unwrap();
+        assert_eq!(minicbor_content, minicbor_serde_content);
+    }
+
+    #[test]
+    fn extension_setters() {
+        let value = MimiContent::default()
+            .with_extension_number(2, "mimi://example.com/r/engineering_team")
+            .with_extension_number(1, "mimi://example.com/u/alice-smith");
+        assert_eq!(value.extensions, extensions_alice());
+
+        let mut value = value.with_extension_text("x-vendor", 7u8);
+        let previous = value.set_extension_number(1, "mimi://example.com/u/bob-jones");
+        assert_eq!(previous, Some("mimi://example.com/u/alice-smith".into()));
+        assert_eq!(value.set_extension_text("x-vendor", 8u8), Some(7u8.into()));
+
+        let keys: Vec<_> = value.extensions.keys().cloned().collect();
+        assert_eq!(
+            keys,
+            vec![
+                ExtensionName::Text("x-vendor".to_owned()),
+                ExtensionName::Number(1),
+                ExtensionName::Number(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn borrowed_nested_part() {
+        let content = b"# Welcome!".to_vec();
+        let borrowed = NestedPart::single_borrowed(
+            Disposition::Render,
+            "text/markdown;variant=GFM-MIMI",
+            &content,
+        );
+        let owned = NestedPart::SinglePart {
+            disposition: Disposition::Render,
+            language: "".to_owned(),
+            content_type: "text/markdown;variant=GFM-MIMI".to_owned(),
+            content: content.clone(),
+        };
+
+        let mut borrowed_bytes = Vec::new();
+        minicbor::encode(&borrowed, &mut borrowed_bytes).unwrap();
+        let mut owned_bytes = Vec::new();
+        minicbor::encode(&owned, &mut owned_bytes).unwrap();
+        assert_eq!(borrowed_bytes, owned_bytes);
+        assert_eq!(borrowed.clone().into_owned(), owned);
+
+        let multipart = NestedPart::MultiPart {
+            disposition: Disposition::Render,
+            language: "".to_owned(),
+            part_semantics: PartSemantics::ChooseOne,
+            parts: vec![owned, NestedPart::default()],
+        };
+        let mut bytes = Vec::new();
+        minicbor::encode(&multipart, &mut bytes).unwrap();
+
+        let decoded: NestedPartRef = minicbor::decode(&bytes).unwrap();
+        assert_eq!(decoded, multipart.as_part_ref());
+        assert_eq!(decoded.into_owned(), multipart);
+    }
+
+    #[test]
+    fn expiration_validation() {
+        let never = Expiration {
+            relative: false,
+            time: 0,
+        };
+        assert!(never.validate().is_ok());
+
+        let immediately = Expiration {
+            relative: true,
+            time: 0,
+        };
+        assert!(matches!(
+            immediately.validate(),
+            Err(Error::InvalidExpiration)
+        ));
+
+        let absolute = Expiration {
+            relative: false,
+            time: 1644390004,
+        };
+        assert!(absolute.validate().is_ok());
+
+        let relative = Expiration {
+            relative: true,
+            time: 3600,
+        };
+        assert!(relative.validate().is_ok());
+    }
+
+    #[test]
+    fn message_id_newtype() {
+        let value = MimiContent {
+            salt: hex::decode("5eed9406c2545547ab6f09f20a18b003").unwrap(),
+            extensions: extensions_alice(),
+            ..Default::default()
+        };
+        let id = value
+            .message_id(
+                b"mimi://example.com/u/alice-smith",
+                b"mimi://example.com/r/engineering_team",
+            )
+            .unwrap();
+        assert_eq!(id.len(), MESSAGE_ID_LEN);
+        assert_eq!(id[0], MESSAGE_ID_VERSION);
+
+        let message_id = MessageId::try_from(id.clone()).unwrap();
+        assert_eq!(Vec::from(message_id), id);
+
+        let mut wrong_version = id.clone();
+        wrong_version[0] = 0x02;
+        assert!(matches!(
+            MessageId::try_from(wrong_version),
+            Err(Error::MalformedMessageId)
+        ));
+        assert!(matches!(
+            MessageId::try_from(&id[..MESSAGE_ID_LEN - 1]),
+            Err(Error::MalformedMessageId)
+        ));
+    }
+
+    #[test]
+    fn strict_deserialization() {
+        let value = MimiContent {
+            salt: hex::decode("5eed9406c2545547ab6f09f20a18b003").unwrap(),
+            nested_part: NestedPart::MultiPart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+                part_semantics: PartSemantics::ProcessAll,
+                parts: vec![NestedPart::NullPart {
+                    disposition: Disposition::Custom(200),
+                    language: "".to_owned(),
+                }],
+            },
+            ..Default::default()
+        };
+        let bytes = value.serialize().unwrap();
+
+        assert_eq!(MimiContent::deserialize(&bytes).unwrap(), value);
+        assert!(matches!(
+            MimiContent::deserialize_strict(&bytes),
+            Err(Error::UnknownDiscriminant {
+                enum_name: "Disposition",
+                value: 200
+            })
+        ));
+    }
+
+    #[test]
+    fn byte_newtypes_as_ref() {
+        let salt = Salt::from([0x5e; 16]);
+        let value = MimiContent {
+            salt: salt.into(),
+            ..Default::default()
+        };
+        let id = value.message_id(b"sender", b"room").unwrap();
+        let message_id = MessageId::try_from(id.as_slice()).unwrap();
+
+        fn len_of(bytes: impl AsRef<[u8]>) -> usize {
+            bytes.as_ref().len()
+        }
+        assert_eq!(len_of(salt), 16);
+        assert_eq!(len_of(message_id), MESSAGE_ID_LEN);
+        assert_eq!(&*message_id, id.as_slice());
+        assert_eq!(salt.as_bytes(), &[0x5e; 16]);
+
+        // Newtypes are drop-in for `&[u8]` parameters.
+        assert!(value.message_id(&message_id, &salt).is_ok());
+    }
+
+    #[test]
+    fn primary_content() {
+        let single = MimiContent {
+            nested_part: NestedPart::SinglePart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+                content_type: "application/octet-stream".to_owned(),
+                content: vec![0xff, 0x00],
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            single.primary_content(),
+            Some(("application/octet-stream", &[0xff, 0x00][..]))
+        );
+
+        let null = MimiContent::default();
+        assert_eq!(null.primary_content(), None);
+
+        let external = MimiContent {
+            nested_part: NestedPart::ExternalPart {
+                disposition: Disposition::Attachment,
+                language: "".to_owned(),
+                content_type: "image/jpeg".to_owned(),
+                url: "https://example.com/image.jpg".to_owned(),
+                expires: 0,
+                size: 0,
+                enc_alg: EncryptionAlgorithm::None,
+                key: vec![],
+                nonce: vec![],
+                aad: vec![],
+                hash_alg: HashAlgorithm::Unspecified,
+                content_hash: vec![],
+                description: "".to_owned(),
+                filename: "".to_owned(),
+            },
+            ..Default::default()
+        };
+        assert_eq!(external.primary_content(), None);
+
+        let multi = MimiContent {
+            nested_part: NestedPart::MultiPart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+                part_semantics: PartSemantics::ChooseOne,
+                parts: vec![single.nested_part.clone()],
+            },
+            ..Default::default()
+        };
+        assert_eq!(multi.primary_content(), None);
+    }
+
+    #[test]
+    fn tombstone() {
+        let replaces =
+            hex::decode(b"01a419aef4e16d43cfc06c28235ecfbe9faebc740d0148e7ca20b22150930836")
+                .unwrap();
+        let salt: [u8; 16] = hex::decode("0a590d73b2c7761c39168be5ebf7f2e6")
+            .unwrap()
+            .try_into()
+            .unwrap();
+
+        let mut value = MimiContent::tombstone(
+            MessageId::try_from(replaces.as_slice()).unwrap(),
+            Salt::from(salt),
+        );
+        assert!(value.is_tombstone());
+
+        // Matches the structure of the `delete` fixture
+        value.in_reply_to = Some(
+            hex::decode("01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79")
+                .unwrap(),
+        );
+        value.extensions = extensions_bob();
+        assert_eq!(
+            hex::encode(
+                value
+                    .message_id(
+                        b"mimi://example.com/u/bob-jones",
+                        b"mimi://example.com/r/engineering_team"
+                    )
+                    .unwrap()
+            ),
+            "01b85744b443e9db85de5bb826c04bcd65b625e53d17839dc8a3f21321421088"
+        );
+
+        let value2 = MimiContent::deserialize(&value.serialize().unwrap()).unwrap();
+        assert_eq!(value, value2);
+        assert!(value2.is_tombstone());
+
+        assert!(!MimiContent::default().is_tombstone());
+        let edit = MimiContent {
+            replaces: Some(replaces),
+            ..MimiContent::simple_markdown_message("edited".to_owned(), salt)
+        };
+        assert!(!edit.is_tombstone());
+    }
+
+    #[test]
+    fn digest_all() {
+        let digests = HashAlgorithm::digest_all(
+            &[
+                HashAlgorithm::Sha256,
+                HashAlgorithm::Unspecified,
+                HashAlgorithm::Sha512,
+                HashAlgorithm::Custom(42),
+            ],
+            b"abc",
+        );
+        let sha256 =
+            hex::decode("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+                .unwrap();
+        let sha512 = hex::decode(
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+        )
+        .unwrap();
+        assert_eq!(
+            digests,
+            vec![
+                (HashAlgorithm::Sha256, sha256.clone()),
+                (HashAlgorithm::Sha512, sha512),
+            ]
+        );
+        assert_eq!(
+            HashAlgorithm::Sha256_64.digest(b"abc"),
+            Some(sha256[..8].to_vec())
+        );
+        let mut hasher = HashAlgorithm::Sha256_64.hasher().unwrap();
+        hasher.update(b"a");
+        hasher.update(b"");
+        hasher.update(b"bc");
+        assert_eq!(hasher.finalize(), sha256[..8]);
+        assert!(HashAlgorithm::Sha3_256.hasher().is_none());
+
+        let part = NestedPart::ExternalPart {
+            disposition: Disposition::Attachment,
+            language: "".to_owned(),
+            content_type: "text/plain".to_owned(),
+            url: "https://example.com/abc.txt".to_owned(),
+            expires: 0,
+            size: 3,
+            enc_alg: EncryptionAlgorithm::None,
+            key: vec![],
+            nonce: vec![],
+            aad: vec![],
+            hash_alg: HashAlgorithm::Sha256,
+            content_hash: sha256,
+            description: "".to_owned(),
+            filename: "abc.txt".to_owned(),
+        };
+        let limits = Limits::default();
+        assert!(part.verify_content_hash(b"abc", &limits).unwrap());
+        assert!(!part.verify_content_hash(b"abd", &limits).unwrap());
+        assert!(matches!(
+            NestedPart::default().verify_content_hash(b"abc", &limits),
+            Err(Error::NotExternalPart)
+        ));
+    }
+
+    #[test]
+    fn custom_disposition() {
+        fn round_trip(disposition: Disposition) -> (Vec<u8>, Disposition) {
+            let mut buf = Vec::new();
+            minicbor::encode(disposition, &mut buf).unwrap();
+            let decoded = minicbor::decode(&buf).unwrap();
+            (buf, decoded)
+        }
+
+        // First custom value
+        assert_eq!(
+            round_trip(Disposition::Custom(9)),
+            (vec![0x09], Disposition::Custom(9))
+        );
+        assert_eq!(
+            round_trip(Disposition::Custom(42)),
+            (vec![0x18, 0x2a], Disposition::Custom(42))
+        );
+        assert_eq!(
+            round_trip(Disposition::Preview),
+            (vec![0x08], Disposition::Preview)
+        );
+
+        // Custom values colliding with named variants decode as those
+        assert_eq!(
+            round_trip(Disposition::Custom(0)),
+            (vec![0x00], Disposition::Unspecified)
+        );
+        assert_eq!(
+            round_trip(Disposition::Custom(8)),
+            (vec![0x08], Disposition::Preview)
+        );
+    }
+
+    #[test]
+    fn merge_extensions() {
+        let other = MimiContent::default()
+            .with_extension_number(1, "mimi://example.com/u/bob-jones")
+            .with_extension_number(3, "forwarded")
+            .extensions;
+
+        let mut kept = MimiContent {
+            extensions: extensions_alice(),
+            ..Default::default()
+        };
+        let id = kept.message_id(b"sender", b"room").unwrap();
+        kept.merge_extensions(other.clone(), false);
+        assert_eq!(
+            kept.extensions,
+            extensions_alice()
+                .into_iter()
+                .chain([(ExtensionName::Number(3), "forwarded".into())])
+                .collect::<BTreeMap<_, _>>()
+        );
+        assert_ne!(kept.message_id(b"sender", b"room").unwrap(), id);
+
+        let mut overwritten = MimiContent {
+            extensions: extensions_alice(),
+            ..Default::default()
+        };
+        overwritten.merge_extensions(other, true);
+        assert_eq!(
+            overwritten.extensions,
+            extensions_bob()
+                .into_iter()
+                .chain([(ExtensionName::Number(3), "forwarded".into())])
+                .collect::<BTreeMap<_, _>>()
+        );
+    }
+
+    #[test]
+    fn finalize_external_part() {
+        let external = |hash_alg| NestedPart::ExternalPart {
+            disposition: Disposition::Attachment,
+            language: "".to_owned(),
+            content_type: "text/plain".to_owned(),
+            url: "https://example.com/abc.txt".to_owned(),
+            expires: 0,
+            size: 0,
+            enc_alg: EncryptionAlgorithm::None,
+            key: vec![],
+            nonce: vec![],
+            aad: vec![],
+            hash_alg,
+            content_hash: vec![],
+            description: "".to_owned(),
+            filename: "abc.txt".to_owned(),
+        };
+
+        let mut part = external(HashAlgorithm::Sha256);
+        part.finalize(b"abc").unwrap();
+        assert!(part
+            .verify_content_hash(b"abc", &Limits::default())
+            .unwrap());
+        let NestedPart::ExternalPart { size, .. } = part else {
+            unreachable!()
+        };
+        assert_eq!(size, 3);
+
+        let mut part = external(HashAlgorithm::Unspecified);
+        part.finalize(b"abc").unwrap();
+        let NestedPart::ExternalPart {
+            size, content_hash, ..
+        } = part
+        else {
+            unreachable!()
+        };
+        assert_eq!(size, 3);
+        assert!(content_hash.is_empty());
+
+        assert!(matches!(
+            external(HashAlgorithm::Sha3_256).finalize(b"abc"),
+            Err(Error::UnsupportedHashAlgorithm)
+        ));
+        assert!(matches!(
+            NestedPart::default().finalize(b"abc"),
+            Err(Error::NotExternalPart)
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn borrowed_nested_part_serde() {
+        let value = NestedPart::MultiPart {
+            disposition: Disposition::Render,
+            language: "".to_owned(),
+            part_semantics: PartSemantics::SingleUnit,
+            parts: vec![
+                NestedPart::SinglePart {
+                    disposition: Disposition::Render,
+                    language: "en".to_owned(),
+                    content_type: "text/markdown;variant=GFM-MIMI".to_owned(),
+                    content: b"# Welcome!".to_vec(),
+                },
+                NestedPart::ExternalPart {
+                    disposition: Disposition::Attachment,
+                    language: "".to_owned(),
+                    content_type: "image/jpeg".to_owned(),
+                    url: "https://example.com/image.jpg".to_owned(),
+                    expires: 0,
+                    size: 3,
+                    enc_alg: EncryptionAlgorithm::Aes128Gcm,
+                    key: vec![1; 16],
+                    nonce: vec![2; 12],
+                    aad: vec![],
+                    hash_alg: HashAlgorithm::Sha256,
+                    content_hash: vec![3; 32],
+                    description: "".to_owned(),
+                    filename: "image.jpg".to_owned(),
+                },
+            ],
+        };
+        let mut bytes = Vec::new();
+        minicbor::encode(&value, &mut bytes).unwrap();
+
+        let borrowed: NestedPartRef = minicbor_serde::from_slice(&bytes).unwrap();
+        assert_eq!(borrowed, value.as_part_ref());
+        assert_eq!(minicbor_serde::to_vec(&borrowed).unwrap(), bytes);
+    }
+
+    #[test]
+    fn profile_and_icon_parts() {
+        let profile = NestedPart::profile("image/png", vec![0x89, 0x50, 0x4e, 0x47]);
+        let icon = NestedPart::icon("image/svg+xml", b"<svg/>".to_vec());
+        assert_eq!(profile.disposition(), Disposition::Profile);
+        assert_eq!(icon.disposition(), Disposition::Icon);
+
+        for part in [profile, icon] {
+            let value = MimiContent {
+                nested_part: part,
+                ..Default::default()
+            };
+            let value2 = MimiContent::deserialize(&value.serialize().unwrap()).unwrap();
+            assert_eq!(value, value2);
+        }
+    }
+
+    #[test]
+    fn deserialize_from_reader() {
+        let value = MimiContent::simple_markdown_message("Hello".to_owned(), [1; 16]);
+        let bytes = value.serialize().unwrap();
+        assert_eq!(
+            MimiContent::deserialize_from(bytes.as_slice()).unwrap(),
+            value
+        );
+
+        // Reading stops at the end of the message
+        let mut reader = bytes.as_slice().chain(io::repeat(0));
+        assert_eq!(MimiContent::deserialize_from(&mut reader).unwrap(), value);
+        let mut rest = [1; 4];
+        reader.read_exact(&mut rest).unwrap();
+        assert_eq!(rest, [0; 4]);
+
+        // Indefinite-length arrays are read up to their break
+        let mut indefinite = bytes.clone();
+        indefinite[0] = 0x9f;
+        indefinite.push(0xff);
+        assert_eq!(
+            MimiContent::deserialize_from(indefinite.as_slice()).unwrap(),
+            value
+        );
+
+        assert!(matches!(
+            MimiContent::deserialize_from(&bytes[..bytes.len() - 1]),
+            Err(Error::Io(_))
+        ));
+
+        // A byte string declared longer than the limit is rejected before
+        // it is read
+        let mut oversized = vec![0x81, 0x5b];
+        oversized.extend_from_slice(&MAX_INPUT_LEN.to_be_bytes());
+        assert!(matches!(
+            MimiContent::deserialize_from(oversized.as_slice().chain(io::repeat(0))),
+            Err(Error::InputTooLarge)
+        ));
+
+        // Nesting is bounded while reading, not only when decoding
+        for byte in [0x81, 0xc0, 0x9f] {
+            assert!(matches!(
+                MimiContent::deserialize_from(io::repeat(byte)),
+                Err(Error::Decode(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn well_known_extension_names() {
+        assert_eq!(
+            ExtensionName::Number(1).well_known_name(),
+            Some("senderUri")
+        );
+        assert_eq!(ExtensionName::Number(2).well_known_name(), Some("roomUri"));
+        assert_eq!(ExtensionName::Number(3).well_known_name(), None);
+        assert_eq!(
+            ExtensionName::Text("senderUri".to_owned()).well_known_name(),
+            None
+        );
+    }
+
+    #[test]
+    fn custom_boundaries() {
+        fn decode<T: for<'b> minicbor::Decode<'b, ()>>(value: u16) -> T {
+            let mut buf = Vec::new();
+            minicbor::encode(value, &mut buf).unwrap();
+            minicbor::decode(&buf).unwrap()
+        }
+
+        assert_eq!(decode::<Disposition>(0), Disposition::Unspecified);
+        assert_eq!(decode::<Disposition>(8), Disposition::Preview);
+        assert_eq!(decode::<Disposition>(9), Disposition::Custom(9));
+
+        assert_eq!(decode::<PartSemantics>(0), PartSemantics::ChooseOne);
+        assert_eq!(decode::<PartSemantics>(2), PartSemantics::ProcessAll);
+        assert_eq!(decode::<PartSemantics>(3), PartSemantics::Custom(3));
+
+        assert_eq!(decode::<HashAlgorithm>(0), HashAlgorithm::Unspecified);
+        assert_eq!(decode::<HashAlgorithm>(12), HashAlgorithm::Sha3_512);
+        assert_eq!(decode::<HashAlgorithm>(13), HashAlgorithm::Custom(13));
+
+        assert_eq!(decode::<EncryptionAlgorithm>(0), EncryptionAlgorithm::None);
+        assert_eq!(
+            decode::<EncryptionAlgorithm>(33),
+            EncryptionAlgorithm::Aegis256
+        );
+        assert_eq!(
+            decode::<EncryptionAlgorithm>(34),
+            EncryptionAlgorithm::Custom(34)
+        );
+        assert_eq!(
+            decode::<EncryptionAlgorithm>(256),
+            EncryptionAlgorithm::Custom(256)
+        );
+    }
+
+    #[test]
+    fn custom_values() {
+        let value = MimiContent {
+            nested_part: NestedPart::MultiPart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+                part_semantics: PartSemantics::Custom(7),
+                parts: vec![
+                    NestedPart::SinglePart {
+                        disposition: Disposition::Render,
+                        language: "".to_owned(),
+                        content_type: "text/plain".to_owned(),
+                        content: b"Hi".to_vec(),
+                    },
+                    NestedPart::NullPart {
+                        disposition: Disposition::Custom(200),
+                        language: "".to_owned(),
+                    },
+                ],
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            value.custom_values(),
+            vec![
+                (Vec::<usize>::new(), CustomValue::PartSemantics(7)),
+                (vec![1], CustomValue::Disposition(200)),
+            ]
+        );
+        assert!(MimiContent::default().custom_values().is_empty());
+    }
+
+    #[test]
+    fn clone_with_new_salt() {
+        let value = MimiContent {
+            extensions: extensions_alice(),
+            ..MimiContent::simple_markdown_message("Hi".to_owned(), [1; 16])
+        };
+        let resent = value.clone_with_new_salt(Salt::from([2; 16]));
+
+        assert_eq!(resent.salt, vec![2; 16]);
+        assert_eq!(resent.nested_part, value.nested_part);
+        assert_eq!(resent.extensions, value.extensions);
+        assert_ne!(
+            resent.message_id(b"sender", b"room").unwrap(),
+            value.message_id(b"sender", b"room").unwrap()
+        );
+    }
+
+    #[test]
+    fn render_markdown() {
+        let markdown = |content_type: &str, content: &[u8]| MimiContent {
+            nested_part: NestedPart::SinglePart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+                content_type: content_type.to_owned(),
+                content: content.to_vec(),
+            },
+            ..Default::default()
+        };
+
+        let value = markdown("text/markdown;variant=GFM-MIMI", b"# Welcome!");
+        assert_eq!(
+            value.render_markdown().unwrap().unwrap(),
+            (MarkdownVariant::GfmMimi, "# Welcome!".to_owned())
+        );
+
+        let value = markdown("text/markdown", b"Hi");
+        assert_eq!(
+            value.render_markdown().unwrap().unwrap(),
+            (MarkdownVariant::Unspecified, "Hi".to_owned())
+        );
+
+        let value = markdown("text/markdown; variant=CommonMark", b"Hi");
+        assert_eq!(
+            value.render_markdown().unwrap().unwrap(),
+            (
+                MarkdownVariant::Other("CommonMark".to_owned()),
+                "Hi".to_owned()
+            )
+        );
+
+        let value = markdown("text/markdown", &[0xff]);
+        assert!(matches!(value.render_markdown(), Some(Err(Error::NotUtf8))));
+
+        let value = markdown("text/plain", b"Hi");
+        assert!(value.render_markdown().is_none());
+        assert!(MimiContent::default().render_markdown().is_none());
+    }
+
+    #[test]
+    fn external_size_overflow() {
+        let external = |size| NestedPart::ExternalPart {
+            disposition: Disposition::Attachment,
+            language: "".to_owned(),
+            content_type: "video/mp4".to_owned(),
+            url: "https://example.com/video.mp4".to_owned(),
+            expires: 0,
+            size,
+            enc_alg: EncryptionAlgorithm::None,
+            key: vec![],
+            nonce: vec![],
+            aad: vec![],
+            hash_alg: HashAlgorithm::Unspecified,
+            content_hash: vec![],
+            description: "".to_owned(),
+            filename: "".to_owned(),
+        };
+        let multipart = |parts| MimiContent {
+            nested_part: NestedPart::MultiPart {
+                disposition: Disposition::Attachment,
+                language: "".to_owned(),
+                part_semantics: PartSemantics::ProcessAll,
+                parts,
+            },
+            ..Default::default()
+        };
+        let unlimited = Limits {
+            max_total_external_size: u64::MAX,
+            ..Default::default()
+        };
+
+        let value = multipart(vec![external(1000), external(24)]);
+        assert_eq!(value.total_external_size(), Some(1024));
+        assert!(value.validate_limits(&Limits::default()).is_ok());
+
+        // Wraps around to 1 if added naively
+        let value = multipart(vec![external(u64::MAX), external(2)]);
+        assert_eq!(value.total_external_size(), None);
+        assert!(matches!(
+            value.validate_limits(&unlimited),
+            Err(Error::TotalSizeTooLarge)
+        ));
+    }
+
+    #[test]
+    fn validate_collects_all_violations() {
+        let value = MimiContent::simple_markdown_message("Hi".to_owned(), [1; 16]);
+        assert!(value.validate(&Limits::default()).is_ok());
+        assert!(value.validate_first(&Limits::default()).is_ok());
+
+        let value = MimiContent {
+            salt: vec![1; 15],
+            in_reply_to: Some(vec![0x01; 10]),
+            expires: Some(Expiration {
+                relative: true,
+                time: 0,
+            }),
+            ..value
+        };
+        let errors = value.validate(&Limits::default()).unwrap_err();
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                Error::InvalidSalt,
+                Error::MalformedMessageId,
+                Error::InvalidExpiration
+            ]
+        ));
+        assert!(matches!(
+            value.validate_first(&Limits::default()),
+            Err(Error::InvalidSalt)
+        ));
+    }
+
+    #[test]
+    fn iterate_extensions() {
+        let value = MimiContent {
+            extensions: extensions_alice(),
+            ..Default::default()
+        };
+
+        let names: Vec<_> = value
+            .extension_keys()
+            .filter_map(ExtensionName::well_known_name)
+            .collect();
+        assert_eq!(names, vec!["senderUri", "roomUri"]);
+
+        let extensions: Vec<_> = value.extensions_iter().collect();
+        assert_eq!(
+            extensions,
+            vec![
+                (
+                    &ExtensionName::Number(1),
+                    &cbor::Value::from("mimi://example.com/u/alice-smith")
+                ),
+                (
+                    &ExtensionName::Number(2),
+                    &cbor::Value::from("mimi://example.com/r/engineering_team")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn external_part_aad() {
+        let mut part = NestedPart::ExternalPart {
+            disposition: Disposition::Attachment,
+            language: "".to_owned(),
+            content_type: "image/jpeg".to_owned(),
+            url: "https://example.com/image.jpg".to_owned(),
+            expires: 0,
+            size: 0,
+            enc_alg: EncryptionAlgorithm::Aes128Gcm,
+            key: vec![1; 16],
+            nonce: vec![2; 12],
+            aad: vec![],
+            hash_alg: HashAlgorithm::Unspecified,
+            content_hash: vec![],
+            description: "".to_owned(),
+            filename: "".to_owned(),
+        };
+
+        // Empty AAD is encoded as an empty byte string and stays empty
+        let mut buf = Vec::new();
+        minicbor::encode(&part, &mut buf).unwrap();
+        let decoded: NestedPart = minicbor::decode(&buf).unwrap();
+        assert_eq!(decoded, part);
+
+        part.set_aad(&cbor::Value::from("room-42")).unwrap();
+        let NestedPart::ExternalPart { aad, .. } = &part else {
+            unreachable!()
+        };
+        assert_eq!(aad, &hex::decode("67726f6f6d2d3432").unwrap());
+
+        // The AAD survives a round trip and is covered by the message ID
+        let without_aad = MimiContent {
+            nested_part: decoded,
+            ..MimiContent::from_markdown("", Salt::from([3; 16]))
+        };
+        let with_aad = MimiContent {
+            nested_part: part.clone(),
+            ..without_aad.clone()
+        };
+        assert_eq!(
+            MimiContent::deserialize(&with_aad.serialize().unwrap()).unwrap(),
+            with_aad
+        );
+        assert_ne!(
+            with_aad.message_id(b"alice", b"room").unwrap(),
+            without_aad.message_id(b"alice", b"room").unwrap()
+        );
+
+        assert!(matches!(
+            NestedPart::default().set_aad(&cbor::Value::Null),
+            Err(Error::NotExternalPart)
+        ));
+    }
+
+    #[test]
+    fn length_prefixed() {
+        let value = MimiContent::simple_markdown_message("Hello".to_owned(), [1; 16]);
+        let mut stream = value.to_length_prefixed().unwrap();
+        let serialized = value.serialize().unwrap();
+        assert_eq!(stream[..4], (serialized.len() as u32).to_be_bytes());
+        assert_eq!(stream[4..], serialized);
+
+        // Trailing data of the stream is not consumed
+        stream.extend_from_slice(b"next frame");
+        let (decoded, consumed) = MimiContent::from_length_prefixed(&stream).unwrap();
+        assert_eq!(decoded, value);
+        assert_eq!(consumed, 4 + serialized.len());
+
+        assert!(matches!(
+            MimiContent::from_length_prefixed(&stream[..3]),
+            Err(Error::Decode(_))
+        ));
+        assert!(matches!(
+            MimiContent::from_length_prefixed(&stream[..consumed - 1]),
+            Err(Error::Decode(_))
+        ));
+
+        // A length prefix past the end of the content is detected
+        let mut too_long = stream.clone();
+        too_long[..4].copy_from_slice(&(serialized.len() as u32 + 1).to_be_bytes());
+        assert!(matches!(
+            MimiContent::from_length_prefixed(&too_long),
+            Err(Error::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn replace_nested_part() {
+        let mut value = MimiContent::simple_markdown_message("Hi".to_owned(), [1; 16]);
+        let original = value.nested_part.clone();
+        let edited = NestedPart::SinglePart {
+            disposition: Disposition::Render,
+            language: "".to_owned(),
+            content_type: "text/markdown".to_owned(),
+            content: b"Hi all".to_vec(),
+        };
+
+        let old = value.replace_nested_part(edited.clone());
+        assert_eq!(old, original);
+        assert_eq!(value.nested_part, edited);
+        assert_eq!(value.string_rendering().unwrap(), "Hi all");
+    }
+
+    #[test]
+    fn reaction_emoji() {
+        let reaction = |content: &[u8]| MimiContent {
+            in_reply_to: Some(
+                hex::decode("01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79")
+                    .unwrap(),
+            ),
+            extensions: extensions_cathy(),
+            nested_part: NestedPart::SinglePart {
+                disposition: Disposition::Reaction,
+                language: "".to_owned(),
+                content_type: "text/plain;charset=utf-8".to_owned(),
+                content: content.to_vec(),
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            reaction("❤".as_bytes()).reaction_emoji().unwrap().unwrap(),
+            "❤"
+        );
+        assert!(matches!(
+            reaction(&[0xe2, 0x9d]).reaction_emoji(),
+            Some(Err(Error::NotUtf8))
+        ));
+
+        let message = MimiContent::simple_markdown_message("❤".to_owned(), [1; 16]);
+        assert!(message.reaction_emoji().is_none());
+    }
+
+    #[test]
+    fn normalize_languages() {
+        let part = |language: &str| NestedPart::NullPart {
+            disposition: Disposition::Render,
+            language: language.to_owned(),
+        };
+        let mut value = MimiContent {
+            nested_part: NestedPart::MultiPart {
+                disposition: Disposition::Render,
+                language: "EN-us".to_owned(),
+                part_semantics: PartSemantics::ChooseOne,
+                parts: vec![
+                    part("zh-hant-tw"),
+                    part("de-CH-x-Phnx"),
+                    part("FR, en-gb"),
+                    part(""),
+                ],
+            },
+            ..Default::default()
+        };
+        value.normalize_languages();
+
+        assert_eq!(
+            value.nested_part,
+            NestedPart::MultiPart {
+                disposition: Disposition::Render,
+                language: "en-US".to_owned(),
+                part_semantics: PartSemantics::ChooseOne,
+                parts: vec![
+                    part("zh-Hant-TW"),
+                    part("de-CH-x-phnx"),
+                    part("fr,en-GB"),
+                    part(""),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn multipart_disposition() {
+        let multipart = |disposition| MimiContent {
+            nested_part: NestedPart::MultiPart {
+                disposition,
+                language: "".to_owned(),
+                part_semantics: PartSemantics::ProcessAll,
+                parts: vec![
+                    NestedPart::SinglePart {
+                        disposition: Disposition::Render,
+                        language: "".to_owned(),
+                        content_type: "text/plain".to_owned(),
+                        content: b"See attached".to_vec(),
+                    },
+                    NestedPart::NullPart {
+                        disposition: Disposition::Attachment,
+                        language: "".to_owned(),
+                    },
+                ],
+            },
+            ..Default::default()
+        };
+
+        assert!(multipart(Disposition::Render).validate_semantics().is_ok());
+        assert!(multipart(Disposition::Unspecified)
+            .validate_semantics()
+            .is_ok());
+        assert!(matches!(
+            multipart(Disposition::Attachment).validate_semantics(),
+            Err(Error::InvalidMultiPartDisposition)
+        ));
+        assert!(matches!(
+            multipart(Disposition::Attachment)
+                .validate(&Limits::default())
+                .unwrap_err()
+                .as_slice(),
+            [Error::InvalidSalt, Error::InvalidMultiPartDisposition]
+        ));
+    }
+
+    #[test]
+    fn trailing_fields() {
+        let value = MimiContent::simple_markdown_message("Hi".to_owned(), [1; 16]);
+        let mut input = value.serialize().unwrap();
+        assert_eq!(input[0], 0x87);
+        // Append an eighth field
+        input[0] = 0x88;
+        input.extend_from_slice(&[0x63, b'n', b'e', b'w']);
+
+        assert_eq!(MimiContent::deserialize(&input).unwrap(), value);
+        let (decoded, unknown_tail) = MimiContent::deserialize_with_unknown_tail(&input).unwrap();
+        assert_eq!(decoded, value);
+        assert_eq!(unknown_tail, vec![cbor::Value::from("new")]);
+
+        assert!(matches!(
+            MimiContent::deserialize_strict(&input),
+            Err(Error::TrailingFields)
+        ));
+        assert!(MimiContent::deserialize_strict(&value.serialize().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn markdown_shorthand() {
+        assert_eq!(
+            NestedPart::from("hi"),
+            NestedPart::SinglePart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+                content_type: "text/markdown;variant=GFM-MIMI".to_owned(),
+                content: b"hi".to_vec(),
+            }
+        );
+
+        let salt: [u8; 16] = hex::decode("5eed9406c2545547ab6f09f20a18b003")
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let value = MimiContent::from_markdown(
+            "Hi everyone, we just shipped release 2.0. __Good  work__!",
+            Salt::from(salt),
+        )
+        .with_extension_number(1, "mimi://example.com/u/alice-smith")
+        .with_extension_number(2, "mimi://example.com/r/engineering_team");
+        assert_eq!(
+            hex::encode(
+                value
+                    .message_id(
+                        b"mimi://example.com/u/alice-smith",
+                        b"mimi://example.com/r/engineering_team"
+                    )
+                    .unwrap()
+            ),
+            "01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79"
+        );
+    }
+
+    #[test]
+    fn ephemeral_messages() {
+        let mut value = MimiContent::from_markdown("Hi", Salt::from([0; 16]));
+        assert!(!value.is_ephemeral());
+        assert_eq!(value.expires_at(1644390000), None);
+
+        value.expires = Some(Expiration {
+            relative: false,
+            time: 0,
+        });
+        assert!(!value.is_ephemeral());
+        assert_eq!(value.expires_at(1644390000), None);
+
+        value.expires = Some(Expiration {
+            relative: false,
+            time: 1644390004,
+        });
+        assert!(value.is_ephemeral());
+        assert_eq!(value.expires_at(1644390000), Some(1644390004));
+
+        value.expires = Some(Expiration {
+            relative: true,
+            time: 3600,
+        });
+        assert!(value.is_ephemeral());
+        assert_eq!(value.expires_at(1644390000), Some(1644393600));
+        assert_eq!(value.expires_at(u64::MAX), Some(u64::MAX));
+    }
+
+    #[test]
+    fn charset_validation() {
+        let part = |content_type: &str, content: &[u8]| NestedPart::SinglePart {
+            disposition: Disposition::Render,
+            language: "".to_owned(),
+            content_type: content_type.to_owned(),
+            content: content.to_vec(),
+        };
+        let mut value = MimiContent::from_markdown("Hi", Salt::from([0; 16]));
+        assert!(value.validate_semantics().is_ok());
+
+        value.nested_part = part("text/plain;charset=utf-8", "Grüße".as_bytes());
+        assert!(value.validate_semantics().is_ok());
+
+        value.nested_part = part("text/plain;charset=US-ASCII", b"Hello");
+        assert!(value.validate_semantics().is_ok());
+
+        value.nested_part = NestedPart::MultiPart {
+            disposition: Disposition::Render,
+            language: "".to_owned(),
+            part_semantics: PartSemantics::ChooseOne,
+            parts: vec![
+                part("text/plain;charset=utf-8", b"Hello"),
+                part("text/plain;charset=utf-8", b"\xc3\x28"),
+            ],
+        };
+        assert!(matches!(
+            value.validate_semantics(),
+            Err(Error::CharsetMismatch { part_path }) if part_path == [1]
+        ));
+        assert_eq!(
+            value.validate_semantics().unwrap_err().to_string(),
+            "content at part 1 does not match its charset"
+        );
+
+        value.nested_part = part("text/plain;charset=us-ascii", "Grüße".as_bytes());
+        assert!(matches!(
+            value.validate_semantics(),
+            Err(Error::CharsetMismatch { part_path }) if part_path.is_empty()
+        ));
+
+        value.nested_part = part("text/plain;charset=utf-16", b"\xff\xfeH\x00");
+        assert!(matches!(
+            value.validate_semantics(),
+            Err(Error::UnsupportedCharset { part_path }) if part_path.is_empty()
+        ));
+    }
+
+    #[test]
+    fn extension_size_limit() {
+        assert_eq!(extension_encoded_len(&cbor::Value::Int(1)), 1);
+        assert_eq!(extension_encoded_len(&"abc".into()), 4);
+        assert_eq!(
+            extension_encoded_len(&cbor::Value::Bytes(vec![0; 1000])),
+            1003
+        );
+
+        let limits = Limits {
+            max_extension_size: 1024,
+            ..Default::default()
+        };
+        let value = MimiContent::from_markdown("Hi", Salt::from([0; 16]))
+            .with_extension_text("thumbnail", cbor::Value::Bytes(vec![0; 1021]));
+        assert!(value.validate_limits(&limits).is_ok());
+
+        let value = value.with_extension_text("thumbnail", cbor::Value::Bytes(vec![0; 1022]));
+        assert!(matches!(
+            value.validate_limits(&limits),
+            Err(Error::ExtensionTooLarge)
+        ));
+    }
+
+    #[test]
+    fn select_part() {
+        let part = |content_type: &str| NestedPart::SinglePart {
+            disposition: Disposition::Render,
+            language: "".to_owned(),
+            content_type: content_type.to_owned(),
+            content: b"Hi".to_vec(),
+        };
+        let value = MimiContent {
+            nested_part: NestedPart::MultiPart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+                part_semantics: PartSemantics::ChooseOne,
+                parts: vec![
+                    part("text/plain"),
+                    part("text/markdown;variant=GFM-MIMI"),
+                    part("application/vnd.examplevendor-fancy-im-message"),
+                ],
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            value.select_part(&["text/plain", "text/markdown"]),
+            Some(&part("text/markdown;variant=GFM-MIMI"))
+        );
+        assert_eq!(
+            value.select_part(&["text/plain"]),
+            Some(&part("text/plain"))
+        );
+        assert_eq!(value.select_part(&["image/png"]), Some(&part("text/plain")));
+
+        // The fallback skips alternatives that cannot be rendered as text
+        let value = MimiContent {
+            nested_part: NestedPart::MultiPart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+                part_semantics: PartSemantics::ChooseOne,
+                parts: vec![
+                    NestedPart::NullPart {
+                        disposition: Disposition::Render,
+                        language: "".to_owned(),
+                    },
+                    part("application/vnd.examplevendor-fancy-im-message"),
+                    part("text/markdown"),
+                    part("text/plain"),
+                ],
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            value.select_part(&["image/png"]),
+            Some(&part("text/markdown"))
+        );
+        assert_eq!(
+            value.select_part(&["application/vnd.examplevendor-fancy-im-message"]),
+            Some(&part("application/vnd.examplevendor-fancy-im-message"))
+        );
+
+        let value = MimiContent::from_markdown("Hi", Salt::from([0; 16]));
+        assert_eq!(value.select_part(&[]), Some(&value.nested_part));
+    }
 
-#[cfg(test)]
-mod tests {
-    use std::collections::BTreeMap;
+    #[test]
+    fn default_encryption_algorithm() {
+        assert_eq!(EncryptionAlgorithm::default(), EncryptionAlgorithm::None);
+        assert_eq!(u16::from(EncryptionAlgorithm::default()), 0);
+    }
 
-    use crate::{cbor, hex_decode};
+    #[test]
+    fn null_and_empty_bodies() {
+        let deleted = MimiContent::tombstone(
+            MessageId::try_from(vec![0x01; 32]).unwrap(),
+            Salt::from([0; 16]),
+        );
+        assert!(deleted.is_null_body());
+        assert!(!deleted.has_empty_content());
 
-    use super::*;
+        let empty = MimiContent::from_markdown("", Salt::from([0; 16]));
+        assert!(!empty.is_null_body());
+        assert!(empty.has_empty_content());
 
-    fn extensions_alice() -> BTreeMap<ExtensionName, cbor::Value> {
-        let mut extensions = BTreeMap::new();
-        extensions.insert(
-            ExtensionName::Number(1),
-            "mimi://example.com/u/alice-smith".into(),
+        let value = MimiContent::from_markdown("Hi", Salt::from([0; 16]));
+        assert!(!value.is_null_body());
+        assert!(!value.has_empty_content());
+    }
+
+    #[test]
+    fn identified_content() {
+        let sender = b"mimi://example.com/u/alice-smith";
+        let room = b"mimi://example.com/r/engineering_team";
+        let value = MimiContent::from_draft_vector("original_message").unwrap();
+
+        let mut identified = IdentifiedContent::new(value.clone(), sender, room).unwrap();
+        assert_eq!(
+            identified.id().as_ref(),
+            value.message_id(sender, room).unwrap()
         );
-        extensions.insert(
-            ExtensionName::Number(2),
-            "mimi://example.com/r/engineering_team".into(),
+
+        identified
+            .set(|content| content.topic_id = b"Foo 118".to_vec())
+            .unwrap();
+        assert_ne!(
+            identified.id().as_ref(),
+            value.message_id(sender, room).unwrap()
         );
 
-        extensions
+        let (content, id) = identified.into_parts();
+        assert_eq!(content.topic_id, b"Foo 118");
+        assert_eq!(id.as_ref(), content.message_id(sender, room).unwrap());
     }
 
-    fn extensions_bob() -> BTreeMap<ExtensionName, cbor::Value> {
-        let mut extensions = BTreeMap::new();
-        extensions.insert(
-            ExtensionName::Number(1),
-            "mimi://example.com/u/bob-jones".into(),
+    #[test]
+    fn topic_limits() {
+        let limits = Limits {
+            max_topic_size: 64,
+            max_topic_chars: 8,
+            ..Default::default()
+        };
+        let mut value = MimiContent::from_markdown("Hi", Salt::from([0; 16]));
+
+        value.topic_id = b"Foo 118".to_vec();
+        assert!(value.validate_limits(&limits).is_ok());
+
+        // Opaque topic IDs are only limited in bytes
+        value.topic_id = vec![0xff; 64];
+        assert!(value.validate_limits(&limits).is_ok());
+
+        value.topic_id = vec![0xff; 65];
+        assert!(matches!(
+            value.validate_limits(&limits),
+            Err(Error::TopicTooLarge)
+        ));
+
+        // 9 combining characters, but only 18 bytes
+        value.topic_id = "\u{301}".repeat(9).into_bytes();
+        assert!(matches!(
+            value.validate_limits(&limits),
+            Err(Error::TopicTooManyChars)
+        ));
+    }
+
+    #[test]
+    fn renderable_parts() {
+        let markdown = NestedPart::from("Hi");
+        assert!(markdown.is_renderable());
+        assert!(NestedPart::SinglePart {
+            disposition: Disposition::Render,
+            language: "".to_owned(),
+            content_type: "Text/Plain;charset=utf-8".to_owned(),
+            content: b"Hi".to_vec(),
+        }
+        .is_renderable());
+
+        let icon = NestedPart::icon("image/png", vec![0x89, 0x50]);
+        assert!(!icon.is_renderable());
+        assert!(!NestedPart::default().is_renderable());
+        assert!(!MimiContent::from_draft_vector("attachments")
+            .unwrap()
+            .nested_part
+            .is_renderable());
+
+        let multipart = |parts| NestedPart::MultiPart {
+            disposition: Disposition::Render,
+            language: "".to_owned(),
+            part_semantics: PartSemantics::ChooseOne,
+            parts,
+        };
+        assert!(multipart(vec![icon.clone(), markdown]).is_renderable());
+        assert!(!multipart(vec![icon]).is_renderable());
+        assert!(!multipart(vec![]).is_renderable());
+    }
+
+    #[test]
+    #[allow(clippy::unnecessary_fallible_conversions)]
+    fn discriminant_conversions() {
+        // `FromPrimitive` provides `From<u8>`, and with it an infallible
+        // `TryFrom<u8>`, mapping unknown values to `Custom`.
+        assert_eq!(Disposition::try_from(1u8), Ok(Disposition::Render));
+        assert_eq!(Disposition::try_from(9u8), Ok(Disposition::Custom(9)));
+        assert_eq!(u8::from(Disposition::Preview), 8);
+        assert_eq!(u8::from(Disposition::Custom(9)), 9);
+
+        assert_eq!(PartSemantics::try_from(2u8), Ok(PartSemantics::ProcessAll));
+        assert_eq!(PartSemantics::try_from(3u8), Ok(PartSemantics::Custom(3)));
+        assert_eq!(u8::from(PartSemantics::ChooseOne), 0);
+        assert_eq!(u8::from(PartSemantics::Custom(255)), 255);
+
+        assert_eq!(MessageStatus::try_from(6u8), Ok(MessageStatus::Error));
+        assert_eq!(MessageStatus::try_from(7u8), Ok(MessageStatus::Custom(7)));
+        assert_eq!(u8::from(MessageStatus::Read), 2);
+        assert_eq!(u8::from(MessageStatus::Custom(7)), 7);
+
+        assert_eq!(
+            EncryptionAlgorithm::try_from(33u16),
+            Ok(EncryptionAlgorithm::Aegis256)
         );
-        extensions.insert(
-            ExtensionName::Number(2),
-            "mimi://example.com/r/engineering_team".into(),
+        assert_eq!(
+            EncryptionAlgorithm::try_from(1000u16),
+            Ok(EncryptionAlgorithm::Custom(1000))
         );
+        assert_eq!(u16::from(EncryptionAlgorithm::Aes128Gcm), 1);
+        assert_eq!(u16::from(EncryptionAlgorithm::Custom(1000)), 1000);
 
-        extensions
+        for disposition in Disposition::all_known() {
+            assert_eq!(Disposition::from(u8::from(*disposition)), *disposition);
+        }
     }
 
-    fn extensions_cathy() -> BTreeMap<ExtensionName, cbor::Value> {
-        let mut extensions = BTreeMap::new();
-        extensions.insert(
-            ExtensionName::Number(1),
-            "mimi://example.com/u/cathy-washington".into(),
+    #[test]
+    fn message_id_from_extensions() {
+        let value = MimiContent::from_draft_vector("original_message").unwrap();
+        assert_eq!(
+            value.message_id_from_extensions().unwrap().as_ref(),
+            value
+                .message_id(
+                    b"mimi://example.com/u/alice-smith",
+                    b"mimi://example.com/r/engineering_team"
+                )
+                .unwrap()
         );
-        extensions.insert(
-            ExtensionName::Number(2),
-            "mimi://example.com/r/engineering_team".into(),
+
+        let mut value = value;
+        value.extensions.remove(&ExtensionName::Number(2));
+        assert_eq!(value.message_id_from_extensions(), None);
+
+        let value = value.with_extension_number(2, cbor::Value::Bytes(b"room".to_vec()));
+        assert_eq!(value.message_id_from_extensions(), None);
+    }
+
+    #[test]
+    fn ambiguous_extension_keys() {
+        let value = MimiContent::from_draft_vector("original_message").unwrap();
+        assert!(value.validate_extension_keys().is_ok());
+
+        let value = value.with_extension_text("3", "mimi://example.com/u/bob-jones");
+        assert!(value.validate_extension_keys().is_ok());
+
+        let value = value.with_extension_text("1", "mimi://example.com/u/bob-jones");
+        assert!(matches!(
+            value.validate_extension_keys(),
+            Err(Error::AmbiguousExtensionKeys(1))
+        ));
+        assert!(value.validate(&Limits::default()).is_ok());
+
+        let keys: Vec<_> = value.extension_keys().cloned().collect();
+        assert_eq!(
+            keys,
+            [
+                ExtensionName::Text("1".to_owned()),
+                ExtensionName::Text("3".to_owned()),
+                ExtensionName::Number(1),
+                ExtensionName::Number(2),
+            ]
         );
+    }
 
-        extensions
+    #[test]
+    fn status_receipt() {
+        let first = MessageId::try_from(vec![0x01; 32]).unwrap();
+        let mut second = vec![0x02; 32];
+        second[0] = MESSAGE_ID_VERSION;
+        let second = MessageId::try_from(second).unwrap();
+        let value = MimiContent::status_receipt(
+            &[
+                (first, MessageStatus::Read),
+                (second, MessageStatus::Unread),
+            ],
+            Salt::from([0; 16]),
+        )
+        .unwrap();
+        assert!(value.is_status_update());
+
+        let (_, content) = value.primary_content().unwrap();
+        let report = MessageStatusReport::deserialize(content).unwrap();
+        assert_eq!(
+            report,
+            MessageStatusReport::new()
+                .with_status(first, MessageStatus::Read)
+                .with_status(second, MessageStatus::Unread)
+        );
     }
 
     #[test]
-    fn original_message() {
-        let value = MimiContent {
-            salt: hex::decode("5eed9406c2545547ab6f09f20a18b003").unwrap(),
-            replaces: None,
-            topic_id: b"".to_vec(),
-            expires: None,
-            in_reply_to: None,
-            extensions: extensions_alice(),
-            nested_part: NestedPart::SinglePart {
-                disposition: Disposition::Render,
-                language: "".to_owned(),
-                content_type: "text/markdown;variant=GFM-MIMI".to_owned(),
-                content: b"Hi everyone, we just shipped release 2.0. __Good  work__!".to_vec(),
-            },
+    fn deserialize_never_panics() {
+        // xorshift64, to keep the inputs reproducible
+        let mut state = 0x2545f4914f6cdd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for vector in crate::draft_vectors::draft_vectors() {
+            let encoded = vector.encoded().unwrap();
+            for _ in 0..1000 {
+                let mut input = encoded.clone();
+                for _ in 0..1 + next() % 4 {
+                    let index = next() as usize % input.len();
+                    input[index] = next() as u8;
+                }
+                input.truncate(next() as usize % (input.len() + 1));
+                let _ = MimiContent::deserialize(&input);
+                let _: Result<NestedPartRef, _> = minicbor::decode(&input);
+            }
+        }
+
+        for _ in 0..10000 {
+            let input: Vec<u8> = (0..next() % 64).map(|_| next() as u8).collect();
+            let _ = MimiContent::deserialize(&input);
+        }
+
+        let mut header = vec![0x87, 0x50];
+        header.extend([0; 16]);
+        header.extend([0xf6, 0x40, 0xf6, 0xf6]);
+
+        // Multiparts nested far deeper than the stack allows
+        let mut input = header.clone();
+        input.push(0xa0);
+        for _ in 0..100_000 {
+            input.extend([0x85, 0x00, 0x60, 0x03, 0x00, 0x81]);
+        }
+        input.extend([0x83, 0x00, 0x60, 0x00]);
+        assert!(MimiContent::deserialize(&input).is_err());
+        let part: Result<NestedPartRef, _> = minicbor::decode(&input[header.len() + 1..]);
+        assert!(part.is_err());
+
+        // Deeply nested extension value
+        let mut input = header.clone();
+        input.extend([0xa1, 0x01]);
+        input.extend([0x81; 100_000]);
+        input.extend([0x00, 0x83, 0x00, 0x60, 0x00]);
+        assert!(MimiContent::deserialize(&input).is_err());
+
+        // Extension value declaring far more elements than present
+        let mut input = header.clone();
+        input.extend([
+            0xa1, 0x01, 0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        ]);
+        input.extend([0x83, 0x00, 0x60, 0x00]);
+        assert!(MimiContent::deserialize(&input).is_err());
+
+        // External parts whose sizes overflow when summed
+        let mut value = MimiContent::from_draft_vector("attachments").unwrap();
+        if let NestedPart::ExternalPart { size, .. } = &mut value.nested_part {
+            *size = u64::MAX;
+        }
+        value.nested_part = NestedPart::MultiPart {
+            disposition: Disposition::Render,
+            language: "".to_owned(),
+            part_semantics: PartSemantics::ProcessAll,
+            parts: vec![value.nested_part.clone(), value.nested_part.clone()],
         };
+        let value = MimiContent::deserialize(&value.serialize().unwrap()).unwrap();
+        assert_eq!(value.total_external_size(), None);
+        assert!(value.validate_limits(&Limits::default()).is_err());
+    }
 
+    #[test]
+    fn nested_values_within_limit() {
+        let mut header = vec![0x87, 0x50];
+        header.extend([0; 16]);
+        header.extend([0xf6, 0x40, 0xf6, 0xf6]);
+        let mut input = header.clone();
+        input.extend([0xa1, 0x01]);
+        input.extend([0x81; cbor::MAX_VALUE_DEPTH - 1]);
+        input.extend([0x80, 0x83, 0x00, 0x60, 0x00]);
+        let value = MimiContent::deserialize(&input).unwrap();
+        assert_eq!(value.nested_part, NestedPart::default());
         assert_eq!(
-            hex::encode(
-                value
-                    .message_id(
-                        b"mimi://example.com/u/alice-smith",
-                        b"mimi://example.com/r/engineering_team"
-                    )
-                    .unwrap()
-            ),
-            "01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79"
+            MimiContent::deserialize_from(input.as_slice()).unwrap(),
+            value
         );
+        assert!(MimiContent::is_well_formed(&input));
 
-        let result = value.serialize().unwrap();
+        let mut too_deep = header.clone();
+        too_deep.extend([0xa1, 0x01]);
+        too_deep.extend([0x81; cbor::MAX_VALUE_DEPTH]);
+        too_deep.extend([0x80, 0x83, 0x00, 0x60, 0x00]);
+        assert!(MimiContent::deserialize(&too_deep).is_err());
+        assert!(!MimiContent::is_well_formed(&too_deep));
 
-        // Test deserialization
-        let value2 = MimiContent::deserialize(&result).unwrap();
-        assert_eq!(value, value2);
+        // Nested indefinite-length arrays stop at the depth limit
+        let mut indefinite = header.clone();
+        indefinite.extend([0xa1, 0x01]);
+        indefinite.extend([0x9f; 100_000]);
+        assert!(!MimiContent::is_well_formed(&indefinite));
 
-        // TODO: Mimi draft is wrong here
-        let target = hex_decode(
-            r#"
-            87                                      # array(7)
-               50                                   # bytes(16)
-                  5eed9406c2545547ab6f09f20a18b003
-               f6                                   # primitive(22)
-               40                                   # bytes(0)
-               f6                                   # primitive(22)
-               f6                                   # primitive(22)
-               a2                                   # map(2)
-                  01                                # unsigned(1)
-                  78 20                             # text(32)
-                     6d696d693a2f2f6578616d706c652e63
-                     6f6d2f752f616c6963652d736d697468
-                     # "mimi://example.com/u/alice-smith"
-                  02                                # unsigned(2)
-                  78 25                             # text(37)
-                     6d696d693a2f2f6578616d706c652e63
-                     6f6d2f722f656e67696e656572696e67
-                     5f7465616d
-                     # "mimi://example.com/r/engineering_team"
-               85                                   # array(5)
-                  01                                # unsigned(1)
-                  60                                # text(0)
-                                                    # ""
-                  01                                # unsigned(1)
-                  78 1e                             # text(30)
-                     746578742f6d61726b646f776e3b7661
-                     7269616e743d47464d2d4d494d49
-                     # "text/markdown;variant=GFM-MIMI"
-                  58 39                             # bytes(57)
-                     48692065766572796f6e652c20776520
-                     6a75737420736869707065642072656c
-                     6561736520322e302e205f5f476f6f64
-                     2020776f726b5f5f21
-                     # "Hi everyone, we just shipped release 2.0. __Good  work__!"
+        let mut input = header;
+        input.push(0xa0);
+        for _ in 0..MAX_PART_DEPTH {
+            input.extend([0x85, 0x00, 0x60, 0x03, 0x00, 0x81]);
+        }
+        input.extend([0x83, 0x00, 0x60, 0x00]);
+        let value = MimiContent::deserialize(&input).unwrap();
+        assert!(value.nested_part.is_multi_part());
+        assert_eq!(
+            MimiContent::deserialize_from(input.as_slice()).unwrap(),
+            value
+        );
+        assert!(MimiContent::is_well_formed(&input));
+    }
 
+    #[test]
+    fn topics() {
+        let conferencing = MimiContent::from_draft_vector("conferencing").unwrap();
+        let value = MimiContent::from_markdown("Hi", Salt::from([0; 16]));
+        assert_eq!(value.topic_id_str(), Some(""));
 
-            "#,
-        );
+        let value = value.with_topic("Foo 118");
+        assert_eq!(value.topic_id, conferencing.topic_id);
+        assert_eq!(value.topic_id_str(), Some("Foo 118"));
+        assert!(value.topic_matches("Foo 118"));
+        assert!(conferencing.topic_matches("Foo 118"));
+        assert!(!value.topic_matches("Foo"));
 
-        assert_eq!(hex::encode(result), hex::encode(target));
+        let mut value = value;
+        value.topic_id = vec![0xff];
+        assert_eq!(value.topic_id_str(), None);
     }
 
     #[test]
-    fn reply() {
-        let value = MimiContent {
-            salt: hex::decode("11a458c73b8dd2cf404db4b378b8fe4d").unwrap(),
-            replaces: None,
-            topic_id: b"".to_vec(),
-            expires: None,
-            in_reply_to: Some(
-                hex::decode("01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79")
-                    .unwrap(),
-            ),
-            extensions: extensions_bob(),
-            nested_part: NestedPart::SinglePart {
-                disposition: Disposition::Render,
-                language: "".to_owned(),
-                content_type: "text/markdown;variant=GFM-MIMI".to_owned(),
-                content: b"Right on! _Congratulations_ 'all!".to_vec(),
-            },
+    fn resolve_expiration() {
+        let never = Expiration {
+            relative: false,
+            time: 0,
         };
+        assert_eq!(never.resolve(1644390000), None);
 
-        let result = value.serialize().unwrap();
+        let absolute = Expiration {
+            relative: false,
+            time: 1644390004,
+        };
+        assert_eq!(absolute.resolve(1644390000), Some(1644390004));
+        assert_eq!(absolute.resolve(u64::MAX), Some(1644390004));
 
-        assert_eq!(
-            hex::encode(
-                value
-                    .message_id(
-                        b"mimi://example.com/u/bob-jones",
-                        b"mimi://example.com/r/engineering_team"
-                    )
-                    .unwrap()
+        let relative = Expiration {
+            relative: true,
+            time: u32::MAX,
+        };
+        assert_eq!(relative.resolve(1644390000), Some(1644390000 + 4294967295));
+        assert_eq!(relative.resolve(u64::MAX - 1), Some(u64::MAX));
+    }
+
+    #[test]
+    fn extension_name_encoding() {
+        let names = [
+            (ExtensionName::Number(1), "01"),
+            (ExtensionName::Number(1000), "1903e8"),
+            (
+                ExtensionName::Text("senderUri".to_owned()),
+                "6973656e646572557269",
             ),
-            "01a419aef4e16d43cfc06c28235ecfbe9faebc740d0148e7ca20b22150930836"
-        );
+        ];
+        for (name, expected) in names {
+            let mut bytes = Vec::new();
+            minicbor::encode(&name, &mut bytes).unwrap();
+            assert_eq!(hex::encode(&bytes), expected);
+            #[cfg(feature = "serde")]
+            assert_eq!(minicbor_serde::to_vec(&name).unwrap(), bytes);
+        }
+    }
 
-        // Test deserialization
-        let value2 = MimiContent::deserialize(&result).unwrap();
-        assert_eq!(value, value2);
+    #[test]
+    fn extension_name_round_trip() {
+        let names = [
+            ExtensionName::Number(1),
+            ExtensionName::Number(u64::MAX),
+            ExtensionName::Text("x-urgent".to_owned()),
+            ExtensionName::Text("".to_owned()),
+        ];
+        for name in names {
+            let bytes = minicbor::to_vec(&name).unwrap();
+            assert_eq!(minicbor::decode::<ExtensionName>(&bytes).unwrap(), name);
+        }
 
-        // Taken from MIMI content format draft
-        let target = hex_decode(
-            r#"
-            87                                      # array(7)
-               50                                   # bytes(16)
-                  11a458c73b8dd2cf404db4b378b8fe4d
-               f6                                   # primitive(22)
-               40                                   # bytes(0)
-               f6                                   # primitive(22)
-               58 20                                # bytes(32)
-                  01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79
-               a2                                   # map(2)
-                  01                                # unsigned(1)
-                  78 1e                             # text(30)
-                     6d696d693a2f2f6578616d706c652e63
-                     6f6d2f752f626f622d6a6f6e6573
-                     # "mimi://example.com/u/bob-jones"
-                  02                                # unsigned(2)
-                  78 25                             # text(37)
-                     6d696d693a2f2f6578616d706c652e63
-                     6f6d2f722f656e67696e656572696e67
-                     5f7465616d
-                     # "mimi://example.com/r/engineering_team"
-               85                                   # array(5)
-                  01                                # unsigned(1)
-                  60                                # text(0)
-                                                    # ""
-                  01                                # unsigned(1)
-                  78 1e                             # text(30)
-                     746578742f6d61726b646f776e3b7661
-                     7269616e743d47464d2d4d494d49
-                     # "text/markdown;variant=GFM-MIMI"
-                  58 21                             # bytes(33)
-                     5269676874206f6e21205f436f6e6772
-                     6174756c6174696f6e735f2027616c6c
-                     21
-                     # "Right on! _Congratulations_ 'all!"
-            "#,
+        // Negative integers and byte strings are not extension names
+        for invalid in ["20", "41ff"] {
+            let bytes = hex::decode(invalid).unwrap();
+            assert!(minicbor::decode::<ExtensionName>(&bytes).is_err());
+        }
+
+        let value = MimiContent::from_markdown("Hi", Salt::from([0; 16]))
+            .with_extension_text("x-urgent", true)
+            .with_extension_number(7, 1);
+        let decoded = MimiContent::deserialize(&value.serialize().unwrap()).unwrap();
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn diff() {
+        let reply = MimiContent::from_draft_vector("reply").unwrap();
+        let edit = MimiContent::from_draft_vector("edit").unwrap();
+        assert!(reply.diff(&reply).is_empty());
+        assert_eq!(
+            edit.diff(&reply),
+            ContentDiff {
+                replaces: true,
+                nested_part: true,
+                ..Default::default()
+            }
         );
 
-        assert_eq!(hex::encode(result), hex::encode(target));
+        let edit = edit
+            .with_extension_number(1, "mimi://example.com/u/cathy-washington")
+            .with_extension_text("edited", true);
+        let mut reply = reply.with_topic("Foo 118");
+        reply.extensions.remove(&ExtensionName::Number(2));
+        let diff = edit.diff(&reply);
+        assert!(diff.topic_id);
+        assert_eq!(
+            diff.extensions_added,
+            [
+                ExtensionName::Text("edited".to_owned()),
+                ExtensionName::Number(2)
+            ]
+        );
+        assert_eq!(diff.extensions_changed, [ExtensionName::Number(1)]);
+        assert!(diff.extensions_removed.is_empty());
+        assert_eq!(reply.diff(&edit).extensions_removed, diff.extensions_added);
     }
 
     #[test]
-    fn reaction() {
+    fn route_parts() {
+        use std::cell::RefCell;
+
         let value = MimiContent {
-            salt: hex::decode("d37bc0e6a8b4f04e9e6382375f587bf6").unwrap(),
-            replaces: None,
-            topic_id: b"".to_vec(),
-            expires: None,
-            in_reply_to: Some(
-                hex::decode("01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79")
-                    .unwrap(),
-            ),
-            extensions: extensions_cathy(),
-            nested_part: NestedPart::SinglePart {
-                disposition: Disposition::Reaction,
+            nested_part: NestedPart::MultiPart {
+                disposition: Disposition::Render,
                 language: "".to_owned(),
-                content_type: "text/plain;charset=utf-8".to_owned(),
-                content: "❤".as_bytes().to_vec(),
+                part_semantics: PartSemantics::ProcessAll,
+                parts: vec![
+                    NestedPart::from("Check out this video"),
+                    MimiContent::from_draft_vector("attachments")
+                        .unwrap()
+                        .nested_part,
+                    NestedPart::default(),
+                ],
             },
+            ..Default::default()
         };
 
-        let result = value.serialize().unwrap();
+        let text = RefCell::new(Vec::new());
+        let video = RefCell::new(Vec::new());
+        let router = PartRouter::new()
+            .route("text/*", |path, _| text.borrow_mut().push(path.to_vec()))
+            .route("video/mp4", |path, part| {
+                assert!(part.is_external_part());
+                video.borrow_mut().push(path.to_vec())
+            });
+        value.route_parts(&router);
 
-        assert_eq!(
-            hex::encode(
-                value
-                    .message_id(
-                        b"mimi://example.com/u/cathy-washington",
-                        b"mimi://example.com/r/engineering_team"
-                    )
-                    .unwrap()
-            ),
-            "01b1a14a88f4480e1336be86987854f838a3ec82944d4533d8d4088578550ed7"
-        );
+        assert_eq!(*text.borrow(), [[0]]);
+        assert_eq!(*video.borrow(), [[1]]);
+    }
 
-        // Test deserialization
-        let value2 = MimiContent::deserialize(&result).unwrap();
-        assert_eq!(value, value2);
+    #[test]
+    fn content_eq_ignores_salt() {
+        let value = MimiContent::from_draft_vector("original_message").unwrap();
+        let resent = value.clone_with_new_salt(Salt::from([0; 16]));
+        assert_ne!(value, resent);
+        assert!(value.content_eq(&resent));
 
-        // TODO: Draft repo has wrong message ids here
-        let target = hex_decode(
-            r#"
-            87                                      # array(7)
-               50                                   # bytes(16)
-                  d37bc0e6a8b4f04e9e6382375f587bf6
-               f6                                   # primitive(22)
-               40                                   # bytes(0)
-               f6                                   # primitive(22)
-               58 20                                # bytes(32)
-               01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79
-               a2                                   # map(2)
-                  01                                # unsigned(1)
-                  78 25                             # text(37)
-                     6d696d693a2f2f6578616d706c652e63
-                     6f6d2f752f63617468792d7761736869
-                     6e67746f6e
-                     # "mimi://example.com/u/cathy-washington"
-                  02                                # unsigned(2)
-                  78 25                             # text(37)
-                     6d696d693a2f2f6578616d706c652e63
-                     6f6d2f722f656e67696e656572696e67
-                     5f7465616d
-                     # "mimi://example.com/r/engineering_team"
-               85                                   # array(5)
-                  02                                # unsigned(2)
-                  60                                # text(0)
-                                                    # ""
-                  01                                # unsigned(1)
-                  78 18                             # text(24)
-                     746578742f706c61696e3b6368617273
-                     65743d7574662d38
-                     # "text/plain;charset=utf-8"
-                  43                                # bytes(3)
-                     e29da4                         # "❤"
-            "#,
-        );
+        let edited = resent.with_topic("Foo 118");
+        assert!(!value.content_eq(&edited));
+    }
 
-        assert_eq!(hex::encode(result), hex::encode(target));
+    #[test]
+    fn embedded_content() {
+        let original = MimiContent::from_draft_vector("original_message").unwrap();
+        let forward = MimiContent {
+            nested_part: NestedPart::embedded_mimi(&original).unwrap(),
+            ..MimiContent::from_markdown("", Salt::from([0; 16]))
+        };
+        assert_eq!(forward.as_embedded().unwrap().unwrap(), original);
+        assert!(original.as_embedded().is_none());
+
+        let forward_again = MimiContent {
+            nested_part: NestedPart::embedded_mimi(&forward).unwrap(),
+            ..Default::default()
+        };
+        let embedded = forward_again.as_embedded().unwrap().unwrap();
+        assert_eq!(embedded, forward);
+        assert_eq!(embedded.as_embedded().unwrap().unwrap(), original);
+
+        let mut corrupt = forward;
+        if let NestedPart::SinglePart { content, .. } = &mut corrupt.nested_part {
+            content.truncate(10);
+        }
+        assert!(corrupt.as_embedded().unwrap().is_err());
     }
 
     #[test]
-    fn edit() {
+    fn error_source() {
+        use std::error::Error as _;
+
+        let error = MimiContent::deserialize(&[0x87]).unwrap_err();
+        let source = error.source().unwrap();
+        assert!(source.is::<minicbor::decode::Error>());
+        assert_eq!(error.to_string(), "decoding failed");
+
+        let error = MessageStatusReport::deserialize(&[0xff]).unwrap_err();
+        assert!(error.source().unwrap().is::<minicbor::decode::Error>());
+
+        assert!(Error::InvalidSalt.source().is_none());
+    }
+
+    #[test]
+    fn canonical_message_id() {
+        let sender = b"mimi://example.com/u/alice-smith";
+        let room = b"mimi://example.com/r/engineering_team";
+        let vector = crate::draft_vectors::draft_vectors().next().unwrap();
+        let canonical = vector.encoded().unwrap();
+
+        let sender_entry = [&[0x01, 0x78, 0x20][..], sender].concat();
+        let room_entry = [&[0x02, 0x78, 0x25][..], room].concat();
+        let entries = [sender_entry.as_slice(), &room_entry].concat();
+        let start = canonical
+            .windows(entries.len())
+            .position(|window| window == entries)
+            .unwrap();
+        let input = [
+            &canonical[..start],
+            &room_entry,
+            &sender_entry,
+            &canonical[start + entries.len()..],
+        ]
+        .concat();
+        assert_ne!(input, canonical);
+
+        let id = MimiContent::canonical_message_id(&input, sender, room).unwrap();
+        assert_eq!(Some(id.to_vec()), vector.message_id());
+        assert_eq!(
+            id,
+            MimiContent::canonical_message_id(&canonical, sender, room).unwrap()
+        );
+    }
+
+    #[test]
+    fn previews() {
+        let preview = NestedPart::preview("image/png", vec![0x89, 0x50, 0x4e, 0x47]);
         let value = MimiContent {
-            salt: hex::decode("b8c2e6d8800ecf45df39be6c45f4c042").unwrap(),
-            replaces: Some(
-                hex::decode(b"01a419aef4e16d43cfc06c28235ecfbe9faebc740d0148e7ca20b22150930836")
-                    .unwrap(),
-            ),
-            topic_id: b"".to_vec(),
-            expires: None,
-            in_reply_to: Some(
-                hex::decode("01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79")
-                    .unwrap(),
-            ),
-            extensions: extensions_bob(),
-            nested_part: NestedPart::SinglePart {
+            nested_part: NestedPart::MultiPart {
                 disposition: Disposition::Render,
                 language: "".to_owned(),
-                content_type: "text/markdown;variant=GFM-MIMI".to_owned(),
-                content: b"Right on! _Congratulations_ y'all!".to_vec(),
+                part_semantics: PartSemantics::ProcessAll,
+                parts: vec![NestedPart::from("See https://example.com"), preview.clone()],
             },
+            ..Default::default()
         };
 
-        let result = value.serialize().unwrap();
+        assert_eq!(value.previews().collect::<Vec<_>>(), [&preview]);
+        assert_eq!(preview.disposition(), Disposition::Preview);
+        assert_eq!(value.parts_with_disposition(Disposition::Render).count(), 2);
 
-        assert_eq!(
-            hex::encode(
-                value
-                    .message_id(
-                        b"mimi://example.com/u/bob-jones",
-                        b"mimi://example.com/r/engineering_team"
-                    )
-                    .unwrap()
-            ),
-            "01fdcd2f418e4b16f6ba319800a44c12b3b0730871f29385bdc6d151b15751ad"
-        );
+        let value = MimiContent::from_markdown("Hi", Salt::from([0; 16]));
+        assert_eq!(value.previews().count(), 0);
+    }
 
-        // Test deserialization
-        let value2 = MimiContent::deserialize(&result).unwrap();
-        assert_eq!(value, value2);
+    #[test]
+    fn float_extensions() {
+        let strict = Limits {
+            reject_float_extensions: true,
+            ..Default::default()
+        };
+        let value = MimiContent::from_draft_vector("original_message")
+            .unwrap()
+            .with_extension_text(
+                "sizes",
+                cbor::Value::Array(vec![cbor::Value::Int(1), "2".into()]),
+            );
+        assert!(value.validate_limits(&strict).is_ok());
 
-        // TODO: Draft repo has wrong message ids here
-        let target = hex_decode(
-            r#"
-            87                                      # array(7)
-               50                                   # bytes(16)
-                  b8c2e6d8800ecf45df39be6c45f4c042  # "\xB8\xC2\xE6؀\u000E\xCFE\xDF9\xBElE\xF4\xC0B"
-               58 20                                # bytes(32)
-                  01a419aef4e16d43cfc06c28235ecfbe9faebc740d0148e7ca20b22150930836
-               40                                   # bytes(0)
-                                                    # ""
-               f6                                   # primitive(22)
-               58 20                                # bytes(32)
-                  01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79
-               a2                                   # map(2)
-                  01                                # unsigned(1)
-                  78 1e                             # text(30)
-                     6d696d693a2f2f6578616d706c652e636f6d2f752f626f622d6a6f6e6573 # "mimi://example.com/u/bob-jones"
-                  02                                # unsigned(2)
-                  78 25                             # text(37)
-                     6d696d693a2f2f6578616d706c652e636f6d2f722f656e67696e656572696e675f7465616d # "mimi://example.com/r/engineering_team"
-               85                                   # array(5)
-                  01                                # unsigned(1)
-                  60                                # text(0)
-                                                    # ""
-                  01                                # unsigned(1)
-                  78 1e                             # text(30)
-                     746578742f6d61726b646f776e3b76617269616e743d47464d2d4d494d49 # "text/markdown;variant=GFM-MIMI"
-                  58 22                             # bytes(34)
-                     5269676874206f6e21205f436f6e67726174756c6174696f6e735f207927616c6c21 # "Right on! _Congratulations_ y'all!"
-            "#,
-        );
+        let value = value.with_extension_text("ratio", 1.5f64);
+        assert!(value.validate_limits(&Limits::default()).is_ok());
+        assert!(matches!(
+            value.validate_limits(&strict),
+            Err(Error::DisallowedExtensionValue)
+        ));
 
-        assert_eq!(hex::encode(result), hex::encode(target));
+        let mut nested = BTreeMap::new();
+        nested.insert("ratio".to_owned(), cbor::Value::Float(1.5));
+        let value = MimiContent::from_draft_vector("original_message")
+            .unwrap()
+            .with_extension_number(3, cbor::Value::Map(nested));
+        assert!(matches!(
+            value.validate_limits(&strict),
+            Err(Error::DisallowedExtensionValue)
+        ));
     }
 
     #[test]
-    fn delete() {
-        let value = MimiContent {
-            salt: hex::decode("0a590d73b2c7761c39168be5ebf7f2e6").unwrap(),
-            replaces: Some(
-                hex::decode(b"01a419aef4e16d43cfc06c28235ecfbe9faebc740d0148e7ca20b22150930836")
-                    .unwrap(),
-            ),
-            topic_id: b"".to_vec(),
-            expires: None,
-            in_reply_to: Some(
-                hex::decode("01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79")
-                    .unwrap(),
-            ),
-            extensions: extensions_bob(),
-            nested_part: NestedPart::NullPart {
-                disposition: Disposition::Render,
-                language: "".to_owned(),
-            },
+    fn serialize_allocates_once() {
+        for vector in crate::draft_vectors::draft_vectors() {
+            let value = MimiContent::from_draft_vector(vector.name).unwrap();
+            let bytes = value.serialize().unwrap();
+            if !value.nested_part.is_multi_part() {
+                assert_eq!(bytes.capacity(), bytes.len(), "{}", vector.name);
+            }
+        }
+    }
+
+    #[test]
+    fn extensions_byte_size() {
+        let value = MimiContent::from_draft_vector("original_message").unwrap();
+        assert_eq!(value.extension_count(), 2);
+
+        let mut bytes = Vec::new();
+        minicbor::encode(extensions_alice(), &mut bytes).unwrap();
+        assert_eq!(value.extensions_byte_size(), bytes.len());
+        assert_eq!(value.extensions_byte_size(), 76);
+
+        let value = MimiContent::from_markdown("Hi", Salt::from([0; 16]));
+        assert_eq!(value.extension_count(), 0);
+        assert_eq!(value.extensions_byte_size(), 1);
+    }
+
+    #[test]
+    fn deserialize_partial() {
+        let value = MimiContent::from_draft_vector("reply").unwrap();
+        let bytes = value.serialize().unwrap();
+
+        let (partial, error) = MimiContent::deserialize_partial(&bytes);
+        assert!(error.is_none());
+        assert_eq!(partial.decoded_fields, 7);
+        assert_eq!(partial.nested_part.as_ref(), Some(&value.nested_part));
+
+        // Cut off inside the content of the nested part
+        let (partial, error) = MimiContent::deserialize_partial(&bytes[..bytes.len() - 5]);
+        assert!(matches!(error, Some(Error::Decode(_))));
+        assert_eq!(partial.decoded_fields, 6);
+        assert_eq!(partial.salt, Some(value.salt));
+        assert_eq!(partial.replaces, None);
+        assert_eq!(partial.topic_id, Some(value.topic_id));
+        assert_eq!(partial.in_reply_to, value.in_reply_to);
+        assert_eq!(partial.extensions, Some(value.extensions));
+        assert_eq!(partial.nested_part, None);
+
+        let (partial, error) = MimiContent::deserialize_partial(&[0x87]);
+        assert!(error.is_some());
+        assert_eq!(partial, PartialContent::default());
+
+        // Indefinite-length arrays are accepted as by `deserialize`
+        let mut indefinite = bytes.clone();
+        indefinite[0] = 0x9f;
+        indefinite.push(0xff);
+        assert!(MimiContent::deserialize(&indefinite).is_ok());
+        let (partial, error) = MimiContent::deserialize_partial(&indefinite);
+        assert!(error.is_none());
+        assert_eq!(partial.decoded_fields, 7);
+
+        let (partial, error) = MimiContent::deserialize_partial(&[0x9f, 0xff]);
+        assert!(error.is_some());
+        assert_eq!(partial.decoded_fields, 0);
+    }
+
+    #[test]
+    fn semantic_eq() {
+        let part = |language: &str| NestedPart::SinglePart {
+            disposition: Disposition::Render,
+            language: language.to_owned(),
+            content_type: "text/plain".to_owned(),
+            content: b"Hi".to_vec(),
         };
+        assert!(part("en").semantic_eq(&part("en"), false));
+        assert!(!part("en").semantic_eq(&part("en-US"), false));
+        assert!(part("en").semantic_eq(&part("en-US"), true));
+        assert!(!part("en").semantic_eq(&NestedPart::from("Hi"), true));
 
-        let result = value.serialize().unwrap();
+        let multipart = |language: &str, parts| NestedPart::MultiPart {
+            disposition: Disposition::Render,
+            language: language.to_owned(),
+            part_semantics: PartSemantics::ChooseOne,
+            parts,
+        };
+        let original = multipart("en", vec![part("en"), NestedPart::default()]);
+        let translated = multipart("de", vec![part("de"), NestedPart::default()]);
+        assert!(!original.semantic_eq(&translated, false));
+        assert!(original.semantic_eq(&translated, true));
+        assert!(!original.semantic_eq(&multipart("de", vec![part("de")]), true));
+        assert!(!original.semantic_eq(&part("en"), true));
+        assert!(!part("en").semantic_eq(&original, true));
+    }
+
+    #[test]
+    fn binary_topics() {
+        let mut value = MimiContent::from_markdown("Hi", Salt::from([0; 16])).with_topic("Foo 118");
+        assert_eq!(value.topic_id_bytes(), b"Foo 118");
+
+        value.set_topic_bytes(&[0x00, 0xff, 0x10]);
+        assert_eq!(value.topic_id_bytes(), [0x00, 0xff, 0x10]);
+        assert_eq!(value.topic_id_str(), None);
 
+        let decoded = MimiContent::deserialize(&value.serialize().unwrap()).unwrap();
+        assert_eq!(decoded.topic_id_bytes(), [0x00, 0xff, 0x10]);
+    }
+
+    #[test]
+    fn batch() {
+        let batch = ["original_message", "reply", "reaction"]
+            .map(|name| MimiContent::from_draft_vector(name).unwrap());
+        let encoded = MimiContent::serialize_batch(&batch).unwrap();
+        assert_eq!(encoded[0], 0x83);
         assert_eq!(
-            hex::encode(
-                value
-                    .message_id(
-                        b"mimi://example.com/u/bob-jones",
-                        b"mimi://example.com/r/engineering_team"
-                    )
-                    .unwrap()
-            ),
-            "01b85744b443e9db85de5bb826c04bcd65b625e53d17839dc8a3f21321421088"
+            encoded[1..],
+            batch
+                .iter()
+                .flat_map(|c| c.serialize().unwrap())
+                .collect::<Vec<_>>()
         );
+        assert_eq!(MimiContent::deserialize_batch(&encoded).unwrap(), batch);
 
-        // Test deserialization
-        let value2 = MimiContent::deserialize(&result).unwrap();
-        assert_eq!(value, value2);
+        assert_eq!(
+            MimiContent::deserialize_batch(&MimiContent::serialize_batch(&[]).unwrap()).unwrap(),
+            Vec::<MimiContent>::new()
+        );
+        assert!(MimiContent::deserialize_batch(&batch[0].serialize().unwrap()).is_err());
+    }
 
-        // TODO: Draft repo has wrong message ids here
-        let target = hex_decode(
-            r#"
-            87                                      # array(7)
-                50                                   # bytes(16)
-                0a590d73b2c7761c39168be5ebf7f2e6  # "\nY\rs\xB2\xC7v\u001C9\u0016\x8B\xE5\xEB\xF7\xF2\xE6"
-                58 20                                # bytes(32)
-                01a419aef4e16d43cfc06c28235ecfbe9faebc740d0148e7ca20b22150930836
-                40                                   # bytes(0)
-                                                    # ""
-                f6                                   # primitive(22)
-                58 20                                # bytes(32)
-                01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79
-                a2                                   # map(2)
-                01                                # unsigned(1)
-                78 1e                             # text(30)
-                    6d696d693a2f2f6578616d706c652e636f6d2f752f626f622d6a6f6e6573 # "mimi://example.com/u/bob-jones"
-                02                                # unsigned(2)
-                78 25                             # text(37)
-                    6d696d693a2f2f6578616d706c652e636f6d2f722f656e67696e656572696e675f7465616d # "mimi://example.com/r/engineering_team"
-                83                                   # array(3)
-                01                                # unsigned(1)
-                60                                # text(0)
-                                                    # ""
-                00                                # unsigned(0)
-            "#,
+    #[test]
+    fn extension_accessors() {
+        let mut value = MimiContent {
+            extensions: extensions_alice(),
+            ..Default::default()
+        };
+        let sender = ExtensionName::Number(1);
+        let vendor = ExtensionName::Text("x-vendor".to_owned());
+
+        assert!(value.has_extension(&sender));
+        assert!(!value.has_extension(&vendor));
+        assert_eq!(
+            value.get_extension(&sender),
+            Some(&"mimi://example.com/u/alice-smith".into())
         );
+        assert_eq!(value.get_extension(&vendor), None);
 
-        assert_eq!(hex::encode(result), hex::encode(target));
+        assert_eq!(
+            value.remove_extension(&sender),
+            Some("mimi://example.com/u/alice-smith".into())
+        );
+        assert_eq!(value.remove_extension(&sender), None);
+        assert!(!value.has_extension(&sender));
+        assert_eq!(value.extension_count(), 1);
     }
 
     #[test]
-    fn expiring() {
-        let value = MimiContent {
-            salt: hex::decode("33be993eb39f418f9295afc2ae160d2d")
-                .unwrap(),
-            replaces: None,
-            topic_id: b"".to_vec(),
-            expires: Some(Expiration { relative: false, time: 1644390004 }),
-            in_reply_to: None,
+    fn body_fingerprint() {
+        let alice = MimiContent {
             extensions: extensions_alice(),
-            nested_part: NestedPart::SinglePart {
-                disposition: Disposition::Render,
-                language: "".to_owned(),
-                content_type: "text/markdown;variant=GFM-MIMI".to_owned(),
-                content: b"__*VPN GOING DOWN*__ I'm rebooting the VPN in ten minutes unless anyone objects.".to_vec(),
-            },
+            ..MimiContent::from_markdown("Hi everyone", Salt::from([1; 16]))
+        };
+        let bob = MimiContent {
+            extensions: extensions_bob(),
+            in_reply_to: Some(vec![0x01; MESSAGE_ID_LEN]),
+            ..MimiContent::from_markdown("Hi everyone", Salt::from([2; 16]))
         };
+        assert_ne!(
+            alice.message_id(b"alice", b"room").unwrap(),
+            bob.message_id(b"bob", b"room").unwrap()
+        );
+        assert_eq!(alice.body_fingerprint(), bob.body_fingerprint());
 
-        let result = value.serialize().unwrap();
+        let other = MimiContent::from_markdown("Hi everyone!", Salt::from([1; 16]));
+        assert_ne!(alice.body_fingerprint(), other.body_fingerprint());
+    }
+
+    #[test]
+    fn application_extensions() {
+        #[derive(Debug, PartialEq)]
+        struct Ticket {
+            title: String,
+            priority: i64,
+            urgent: bool,
+        }
+
+        impl MimiExtensions for Ticket {
+            fn to_extensions(&self) -> BTreeMap<ExtensionName, cbor::Value> {
+                BTreeMap::from([
+                    (ExtensionName::Number(0x10), self.title.clone().into()),
+                    (ExtensionName::Number(0x11), self.priority.into()),
+                    (
+                        ExtensionName::Text("x-urgent".to_owned()),
+                        self.urgent.into(),
+                    ),
+                ])
+            }
+
+            fn from_extensions(extensions: &BTreeMap<ExtensionName, cbor::Value>) -> Option<Self> {
+                let get = |key| extensions.get(&key);
+                let (
+                    Some(cbor::Value::Text(title)),
+                    Some(cbor::Value::Int(priority)),
+                    Some(cbor::Value::Bool(urgent)),
+                ) = (
+                    get(ExtensionName::Number(0x10)),
+                    get(ExtensionName::Number(0x11)),
+                    get(ExtensionName::Text("x-urgent".to_owned())),
+                )
+                else {
+                    return None;
+                };
+                Some(Self {
+                    title: title.to_string(),
+                    priority: *priority,
+                    urgent: *urgent,
+                })
+            }
+        }
+
+        let ticket = Ticket {
+            title: "Printer on fire".to_owned(),
+            priority: -1,
+            urgent: true,
+        };
+        let mut value = MimiContent {
+            extensions: extensions_alice(),
+            ..MimiContent::from_markdown("See ticket", Salt::from([0; 16]))
+        };
+        assert_eq!(Ticket::extract_from(&value), None);
+
+        ticket.apply_to(&mut value);
+        assert_eq!(value.extension_count(), 5);
+        let decoded = MimiContent::deserialize(&value.serialize().unwrap()).unwrap();
+        assert_eq!(Ticket::extract_from(&decoded), Some(ticket));
+    }
 
+    #[test]
+    fn clamp_expiration() {
+        let now = 1_700_000_000;
+        let expiration = |relative, time| Some(Expiration { relative, time });
+        let mut value = MimiContent::from_markdown("Hi", Salt::from([0; 16]));
+
+        value.set_ttl(7 * 86400);
+        assert_eq!(value.expires, expiration(true, 7 * 86400));
+        value.clamp_expiration(86400, now);
+        assert_eq!(value.expires, expiration(true, 86400));
+        value.clamp_expiration(2 * 86400, now);
+        assert_eq!(value.expires, expiration(true, 86400));
+
+        value.expires = expiration(false, now as u32 + 7 * 86400);
+        value.clamp_expiration(86400, now);
+        assert_eq!(value.expires, expiration(false, now as u32 + 86400));
+        value.expires = expiration(false, now as u32 + 60);
+        value.clamp_expiration(86400, now);
+        assert_eq!(value.expires, expiration(false, now as u32 + 60));
+
+        value.expires = expiration(false, 0);
+        value.clamp_expiration(86400, now);
+        assert_eq!(value.expires, expiration(true, 86400));
+        value.expires = None;
+        value.clamp_expiration(0, now);
+        assert_eq!(value.expires, expiration(true, 1));
+        value.expires = expiration(false, u32::MAX);
+        value.clamp_expiration(86400, u64::from(u32::MAX));
+        assert_eq!(value.expires, expiration(false, u32::MAX));
+    }
+
+    #[test]
+    fn external_accessors() {
+        let attachment = MimiContent::from_draft_vector("attachments").unwrap();
         assert_eq!(
-            hex::encode(
-                value
-                    .message_id(
-                        b"mimi://example.com/u/alice-smith",
-                        b"mimi://example.com/r/engineering_team"
-                    )
-                    .unwrap()
-            ),
-            "0106308e2c03346eba95b24abdfa9fe643aa247debfb7192feae647155316920"
+            attachment.nested_part.external_url(),
+            Some("https://example.com/storage/8ksB4bSrrRE.mp4")
         );
+        assert_eq!(attachment.nested_part.external_size(), Some(708234961));
 
-        // Test deserialization
-        let value2 = MimiContent::deserialize(&result).unwrap();
-        assert_eq!(value, value2);
-
-        // Taken from MIMI content format draft
-        let target = hex_decode(
-            r#"
-            87                                      # array(7)
-               50                                   # bytes(16)
-                  33be993eb39f418f9295afc2ae160d2d
-               f6                                   # primitive(22)
-               40                                   # bytes(0)
-                                                    # ""
-               82                                   # array(2)
-                  f4                                # primitive(20)
-                  1a 62036674                       # unsigned(1644390004)
-               f6                                   # primitive(22)
-               a2                                   # map(2)
-                  01                                # unsigned(1)
-                  78 20                             # text(32)
-                     6d696d693a2f2f6578616d706c652e63
-                     6f6d2f752f616c6963652d736d697468
-                     # "mimi://example.com/u/alice-smith"
-                  02                                # unsigned(2)
-                  78 25                             # text(37)
-                     6d696d693a2f2f6578616d706c652e63
-                     6f6d2f722f656e67696e656572696e67
-                     5f7465616d
-                     # "mimi://example.com/r/engineering_team"
-               85                                   # array(5)
-                  01                                # unsigned(1)
-                  60                                # text(0)
-                                                    # ""
-                  01                                # unsigned(1)
-                  78 1e                             # text(30)
-                     746578742f6d61726b646f776e3b7661
-                     7269616e743d47464d2d4d494d49
-                     # "text/markdown;variant=GFM-MIMI"
-                  58 50                             # bytes(80)
-                     5f5f2a56504e20474f494e4720444f57
-                     4e2a5f5f2049276d207265626f6f7469
-                     6e67207468652056504e20696e207465
-                     6e206d696e7574657320756e6c657373
-                     20616e796f6e65206f626a656374732e
-                     # "__*VPN GOING DOWN*__ I'm rebooting the VPN in" +
-                     # " ten minutes unless anyone objects."
-            "#,
+        let conference = MimiContent::from_draft_vector("conferencing").unwrap();
+        assert_eq!(
+            conference.nested_part.external_url(),
+            Some("https://example.com/join/12345")
         );
+        assert_eq!(conference.nested_part.external_size(), Some(0));
 
-        assert_eq!(hex::encode(result), hex::encode(target));
+        let reply = MimiContent::from_draft_vector("reply").unwrap();
+        assert_eq!(reply.nested_part.external_url(), None);
+        assert_eq!(reply.nested_part.external_size(), None);
     }
 
     #[test]
-    fn attachments() {
-        let value = MimiContent {
-            salt: hex::decode("18fac6371e4e53f1aeaf8a013155c166").unwrap(),
-            replaces: None,
-            topic_id: b"".to_vec(),
-            expires: None,
-            in_reply_to: None,
-            extensions: extensions_bob(),
-            nested_part: NestedPart::ExternalPart {
-                disposition: Disposition::Attachment,
-                language: "en".to_owned(),
-                content_type: "video/mp4".to_owned(),
-                url: "https://example.com/storage/8ksB4bSrrRE.mp4".to_owned(),
-                expires: 0,
-                size: 708234961,
-                enc_alg: EncryptionAlgorithm::Aes128Gcm,
-                key: hex::decode("21399320958a6f4c745dde670d95e0d8").unwrap(),
-                nonce: hex::decode("c86cf2c33f21527d1dd76f5b").unwrap(),
-                aad: b"".to_vec(),
-                hash_alg: HashAlgorithm::Sha256,
-                content_hash: hex::decode(
-                    "9ab17a8cf0890baaae7ee016c7312fcc080ba46498389458ee44f0276e783163",
+    fn salt_len() {
+        assert_eq!(SALT_LEN, 16);
+        let salt = Salt::from_bytes(&[0x5e; SALT_LEN]).unwrap();
+        assert_eq!(salt, Salt::from([0x5e; 16]));
+        assert!(matches!(
+            Salt::from_bytes(&[0x5e; 15]),
+            Err(Error::InvalidSalt)
+        ));
+        assert!(matches!(
+            Salt::from_bytes(&[0x5e; 17]),
+            Err(Error::InvalidSalt)
+        ));
+    }
+
+    #[test]
+    fn display_topic() {
+        let mut value = MimiContent::from_draft_vector("conferencing").unwrap();
+        assert!(value.is_valid_utf8_topic());
+        assert_eq!(value.display_topic(), "Foo 118");
+
+        value.set_topic_bytes(b"");
+        assert!(value.is_valid_utf8_topic());
+        assert_eq!(value.display_topic(), "");
+
+        value.set_topic_bytes(&[0x46, 0xff, 0x00]);
+        assert!(!value.is_valid_utf8_topic());
+        assert_eq!(value.display_topic(), "0x46ff00");
+
+        value.set_topic_bytes(b"46ff00");
+        assert_eq!(value.display_topic(), "46ff00");
+    }
+
+    #[test]
+    fn reaction_removal() {
+        let reaction = MimiContent::from_draft_vector("reaction").unwrap();
+        let reaction_id = MessageId::try_from(
+            reaction
+                .message_id(
+                    b"mimi://example.com/u/cathy-washington",
+                    b"mimi://example.com/r/engineering_team",
                 )
                 .unwrap(),
-                description: "2 hours of key signing video".to_owned(),
-                filename: "bigfile.mp4".to_owned(),
-            },
-        };
+        )
+        .unwrap();
 
-        let result = value.serialize().unwrap();
+        let removal = MimiContent::reaction_removal(reaction_id, Salt::from([0; 16]));
+        assert!(removal.is_reaction_removal());
+        assert!(removal.is_tombstone());
+        assert_eq!(removal.replaces.as_deref(), Some(&reaction_id[..]));
+        let decoded = MimiContent::deserialize(&removal.serialize().unwrap()).unwrap();
+        assert!(decoded.is_reaction_removal());
 
-        assert_eq!(
-            hex::encode(
-                value
-                    .message_id(
-                        b"mimi://example.com/u/bob-jones",
-                        b"mimi://example.com/r/engineering_team"
+        let deletion = MimiContent::tombstone(reaction_id, Salt::from([0; 16]));
+        assert!(!deletion.is_reaction_removal());
+        assert!(!reaction.is_reaction_removal());
+    }
+
+    /// Strategies generating valid messages for the property tests.
+    mod strategies {
+        use proptest::{collection, option, prelude::*, sample::select};
+
+        use super::*;
+
+        fn bytes(max_len: usize) -> impl Strategy<Value = Vec<u8>> {
+            collection::vec(any::<u8>(), 0..=max_len)
+        }
+
+        fn message_id() -> impl Strategy<Value = Vec<u8>> {
+            collection::vec(any::<u8>(), MESSAGE_ID_LEN - 1).prop_map(|rest| {
+                let mut id = vec![MESSAGE_ID_VERSION];
+                id.extend(rest);
+                id
+            })
+        }
+
+        fn value() -> impl Strategy<Value = cbor::Value> {
+            let leaf = prop_oneof![
+                any::<bool>().prop_map(cbor::Value::Bool),
+                any::<i64>().prop_map(cbor::Value::Int),
+                ".{0,8}".prop_map(|text| cbor::Value::Text(text.into())),
+                bytes(8).prop_map(cbor::Value::Bytes),
+                Just(cbor::Value::Null),
+            ];
+            leaf.prop_recursive(2, 16, 3, |inner| {
+                prop_oneof![
+                    collection::vec(inner.clone(), 0..3).prop_map(cbor::Value::Array),
+                    collection::btree_map(".{0,4}", inner, 0..3).prop_map(cbor::Value::Map),
+                ]
+            })
+        }
+
+        fn extension_name() -> impl Strategy<Value = ExtensionName> {
+            prop_oneof![
+                (0..64u64).prop_map(ExtensionName::Number),
+                ".{0,4}".prop_map(ExtensionName::Text),
+            ]
+        }
+
+        fn part_header() -> impl Strategy<Value = (Disposition, String)> {
+            (
+                select(Disposition::all_known()),
+                select(&["", "en", "de-CH"][..]).prop_map(str::to_owned),
+            )
+        }
+
+        fn external_part() -> impl Strategy<Value = NestedPart> {
+            (
+                (
+                    part_header(),
+                    ".{0,16}",
+                    ".{0,16}",
+                    any::<u32>(),
+                    any::<u64>(),
+                ),
+                (
+                    select(EncryptionAlgorithm::all_known()),
+                    bytes(32),
+                    bytes(12),
+                    bytes(8),
+                    (0..13u8).prop_map(HashAlgorithm::from),
+                    bytes(32),
+                ),
+                (".{0,16}", ".{0,8}"),
+            )
+                .prop_map(
+                    |(
+                        ((disposition, language), content_type, url, expires, size),
+                        (enc_alg, key, nonce, aad, hash_alg, content_hash),
+                        (description, filename),
+                    )| NestedPart::ExternalPart {
+                        disposition,
+                        language,
+                        content_type,
+                        url,
+                        expires,
+                        size,
+                        enc_alg,
+                        key,
+                        nonce,
+                        aad,
+                        hash_alg,
+                        content_hash,
+                        description,
+                        filename,
+                    },
+                )
+        }
+
+        fn part() -> impl Strategy<Value = NestedPart> {
+            let leaf = prop_oneof![
+                part_header().prop_map(|(disposition, language)| NestedPart::NullPart {
+                    disposition,
+                    language,
+                }),
+                (part_header(), ".{0,16}", bytes(32)).prop_map(
+                    |((disposition, language), content_type, content)| NestedPart::SinglePart {
+                        disposition,
+                        language,
+                        content_type,
+                        content,
+                    }
+                ),
+                external_part(),
+            ];
+            leaf.prop_recursive(3, 32, 4, |inner| {
+                (
+                    part_header(),
+                    select(PartSemantics::all_known()),
+                    collection::vec(inner, 0..4),
+                )
+                    .prop_map(
+                        |((disposition, language), part_semantics, parts)| NestedPart::MultiPart {
+                            disposition,
+                            language,
+                            part_semantics,
+                            parts,
+                        },
                     )
-                    .unwrap()
-            ),
-            "01ad825f6116adeb437a7b1f95a9d9acbcc708f83f5df505d32af9c2826e8b5f"
-        );
+            })
+        }
 
-        // Test deserialization
-        let value2 = MimiContent::deserialize(&result).unwrap();
-        assert_eq!(value, value2);
+        pub(super) fn content() -> impl Strategy<Value = MimiContent> {
+            (
+                collection::vec(any::<u8>(), SALT_LEN),
+                option::of(message_id()),
+                bytes(8),
+                option::of(
+                    (any::<bool>(), any::<u32>())
+                        .prop_map(|(relative, time)| Expiration { relative, time }),
+                ),
+                option::of(message_id()),
+                collection::btree_map(extension_name(), value(), 0..4),
+                part(),
+            )
+                .prop_map(
+                    |(salt, replaces, topic_id, expires, in_reply_to, extensions, nested_part)| {
+                        MimiContent {
+                            salt,
+                            replaces,
+                            topic_id,
+                            expires,
+                            in_reply_to,
+                            extensions,
+                            nested_part,
+                        }
+                    },
+                )
+        }
+    }
 
-        // Taken from MIMI content format draft
-        let target = hex_decode(
-            r#"
-            87                                      # array(7)
-               50                                   # bytes(16)
-                  18fac6371e4e53f1aeaf8a013155c166
-               f6                                   # primitive(22)
-               40                                   # bytes(0)
-                                                    # ""
-               f6                                   # primitive(22)
-               f6                                   # primitive(22)
-               a2                                   # map(2)
-                  01                                # unsigned(1)
-                  78 1e                             # text(30)
-                     6d696d693a2f2f6578616d706c652e63
-                     6f6d2f752f626f622d6a6f6e6573
-                     # "mimi://example.com/u/bob-jones"
-                  02                                # unsigned(2)
-                  78 25                             # text(37)
-                     6d696d693a2f2f6578616d706c652e63
-                     6f6d2f722f656e67696e656572696e67
-                     5f7465616d
-                     # "mimi://example.com/r/engineering_team"
-               8f                                   # array(15)
-                  06                                # unsigned(6)
-                  62                                # text(2)
-                     656e                           # "en"
-                  02                                # unsigned(2)
-                  69                                # text(9)
-                     766964656f2f6d7034             # "video/mp4"
-                  78 2b                             # text(43)
-                     68747470733a2f2f6578616d706c652e
-                     636f6d2f73746f726167652f386b7342
-                     346253727252452e6d7034
-                     # "https://example.com/storage/8ksB4bSrrRE.mp4"
-                  00                                # unsigned(0)
-                  1a 2a36ced1                       # unsigned(708234961)
-                  01                                # unsigned(1)
-                  50                                # bytes(16)
-                     21399320958a6f4c745dde670d95e0d8
-                  4c                                # bytes(12)
-                     c86cf2c33f21527d1dd76f5b
-                  40                                # bytes(0)
-                  01                                # unsigned(1)
-                  58 20                             # bytes(32)
-                     9ab17a8cf0890baaae7ee016c7312fcc
-                     080ba46498389458ee44f0276e783163
-                  78 1c                             # text(28)
-                     3220686f757273206f66206b65792073
-                     69676e696e6720766964656f
-                     # "2 hours of key signing video"
-                  6b                                # text(11)
-                     62696766696c652e6d7034         # "bigfile.mp4"
-            "#,
+    proptest::proptest! {
+        #[test]
+        fn randomized_round_trip(value in strategies::content()) {
+            let encoded = value.serialize()?;
+            let decoded = MimiContent::deserialize(&encoded)?;
+            proptest::prop_assert_eq!(&decoded, &value);
+            proptest::prop_assert_eq!(decoded.serialize()?, encoded);
+            proptest::prop_assert_eq!(
+                decoded.message_id(b"sender", b"room")?,
+                value.message_id(b"sender", b"room")?
+            );
+        }
+    }
+
+    #[test]
+    fn borrowed_content() {
+        for vector in crate::draft_vectors::draft_vectors() {
+            let encoded = vector.encoded().unwrap();
+            let borrowed = MimiContentRef::deserialize(&encoded).unwrap();
+            let mut reencoded = Vec::new();
+            minicbor::encode(&borrowed, &mut reencoded).unwrap();
+            assert_eq!(reencoded, encoded, "{}", vector.name);
+
+            let owned = borrowed.clone().into_owned();
+            assert_eq!(owned.serialize().unwrap(), encoded, "{}", vector.name);
+            assert_eq!(owned.as_content_ref(), borrowed, "{}", vector.name);
+        }
+
+        let mut encoded = MimiContent::from_draft_vector("reply")
+            .unwrap()
+            .serialize()
+            .unwrap();
+        encoded[0] += 1;
+        encoded.push(0xf6);
+        let borrowed = MimiContentRef::deserialize(&encoded).unwrap();
+        assert_eq!(
+            borrowed.into_owned(),
+            MimiContent::deserialize(&encoded).unwrap()
         );
+        assert!(MimiContentRef::deserialize(&[0x86]).is_err());
+    }
 
-        assert_eq!(hex::encode(result), hex::encode(target));
+    #[test]
+    fn check_sender_room() {
+        let sender = b"mimi://example.com/u/alice-smith";
+        let room = b"mimi://example.com/r/engineering_team";
+        let mut value = MimiContent::from_draft_vector("original_message").unwrap();
+        assert!(value.check_sender_room(sender, room).is_ok());
+        assert!(matches!(
+            value.check_sender_room(b"mimi://example.com/u/bob-jones", room),
+            Err(Error::SenderRoomMismatch)
+        ));
+        assert!(matches!(
+            value.check_sender_room(sender, b"mimi://example.com/r/another_team"),
+            Err(Error::SenderRoomMismatch)
+        ));
+
+        value.extensions.remove(&ExtensionName::Number(2));
+        assert!(value.check_sender_room(sender, b"anything").is_ok());
+        value.set_extension_number(1, sender.to_vec());
+        assert!(matches!(
+            value.check_sender_room(sender, room),
+            Err(Error::SenderRoomMismatch)
+        ));
     }
 
     #[test]
-    fn conferencing() {
+    fn available_languages() {
+        let part = |language: &str| NestedPart::SinglePart {
+            disposition: Disposition::Render,
+            language: language.to_owned(),
+            content_type: "text/plain".to_owned(),
+            content: b"Hello".to_vec(),
+        };
         let value = MimiContent {
-            salt: hex::decode("678ac6cd54de049c3e9665cd212470fa").unwrap(),
-            replaces: None,
-            topic_id: b"Foo 118".to_vec(),
-            expires: None,
-            in_reply_to: None,
-            extensions: extensions_alice(),
-            nested_part: NestedPart::ExternalPart {
-                disposition: Disposition::Session,
+            nested_part: NestedPart::MultiPart {
+                disposition: Disposition::Render,
                 language: "".to_owned(),
-                content_type: "".to_owned(),
-                url: "https://example.com/join/12345".to_owned(),
-                expires: 0,
-                size: 0,
-                enc_alg: EncryptionAlgorithm::None,
-                key: Vec::new(),
-                nonce: Vec::new(),
-                aad: Vec::new(),
-                hash_alg: HashAlgorithm::Unspecified,
-                content_hash: b"".to_vec(),
-                description: "Join the Foo 118 conference".to_owned(),
-                filename: "".to_owned(),
+                part_semantics: PartSemantics::ChooseOne,
+                parts: vec![part("en"), part("FR"), part(""), part("en, de-ch")],
             },
+            ..Default::default()
         };
+        assert_eq!(
+            value.available_languages(),
+            BTreeSet::from(["de-CH", "en", "fr"].map(String::from))
+        );
+        assert!(MimiContent::from_draft_vector("reply")
+            .unwrap()
+            .available_languages()
+            .is_empty());
+    }
 
-        let result = value.serialize().unwrap();
+    #[test]
+    fn deserialize_debug() {
+        let encoded = MimiContent::from_draft_vector("reply")
+            .unwrap()
+            .serialize()
+            .unwrap();
+        assert!(MimiContent::deserialize_debug(&encoded).is_ok());
 
+        let error = MimiContent::deserialize_debug(&[0x86]).unwrap_err();
+        assert_eq!((error.field, error.offset), ("header", 0));
+
+        // The salt is followed by `replaces`; make that an integer
+        let mut corrupt = encoded.clone();
+        corrupt[18] = 0x01;
+        let error = MimiContent::deserialize_debug(&corrupt).unwrap_err();
+        assert_eq!(error.field, "replaces");
+        assert!((18..=19).contains(&error.offset), "{}", error.offset);
+
+        let truncated = &encoded[..encoded.len() - 4];
+        let error = MimiContent::deserialize_debug(truncated).unwrap_err();
+        assert_eq!(error.field, "nested_part");
+        assert!(error.part_path.is_empty());
+        assert!(error.offset > 60 && error.offset <= truncated.len());
         assert_eq!(
-            hex::encode(
-                value
-                    .message_id(
-                        b"mimi://example.com/u/alice-smith",
-                        b"mimi://example.com/r/engineering_team"
-                    )
-                    .unwrap()
-            ),
-            "01d8dab2e22b75dee4f5e52bb181d2d732008a235b80375113803e36b32a5f06"
+            error.to_string(),
+            format!("decoding nested_part failed near byte {}", error.offset)
         );
+        assert!(std::error::Error::source(&error).is_some());
 
-        // Test deserialization
-        let value2 = MimiContent::deserialize(&result).unwrap();
-        assert_eq!(value, value2);
+        // Indefinite-length arrays are located like definite ones
+        let mut indefinite = corrupt.clone();
+        indefinite[0] = 0x9f;
+        indefinite.push(0xff);
+        let error = MimiContent::deserialize_debug(&indefinite).unwrap_err();
+        assert_eq!(error.field, "replaces");
 
-        // Taken from MIMI content format draft
-        let target = hex_decode(
-            r#"
-            87                                      # array(7)
-               50                                   # bytes(16)
-                  678ac6cd54de049c3e9665cd212470fa
-               f6                                   # primitive(22)
-               47                                   # bytes(7)
-                  466f6f20313138                    # "Foo 118"
-               f6                                   # primitive(22)
-               f6                                   # primitive(22)
-               a2                                   # map(2)
-                  01                                # unsigned(1)
-                  78 20                             # text(32)
-                     6d696d693a2f2f6578616d706c652e63
-                     6f6d2f752f616c6963652d736d697468
-                     # "mimi://example.com/u/alice-smith"
-                  02                                # unsigned(2)
-                  78 25                             # text(37)
-                     6d696d693a2f2f6578616d706c652e63
-                     6f6d2f722f656e67696e656572696e67
-                     5f7465616d
-                     # "mimi://example.com/r/engineering_team"
-               8f                                   # array(15)
-                  07                                # unsigned(7)
-                  60                                # text(0)
-                                                    # ""
-                  02                                # unsigned(2)
-                  60                                # text(0)
-                                                    # ""
-                  78 1e                             # text(30)
-                     68747470733a2f2f6578616d706c652e
-                     636f6d2f6a6f696e2f3132333435
-                     # "https://example.com/join/12345"
-                  00                                # unsigned(0)
-                  00                                # unsigned(0)
-                  00                                # unsigned(0)
-                  40                                # bytes(0)
-                                                    # ""
-                  40                                # bytes(0)
-                                                    # ""
-                  40                                # bytes(0)
-                                                    # ""
-                  00                                # unsigned(0)
-                  40                                # bytes(0)
-                                                    # ""
-                  78 1b                             # text(27)
-                     4a6f696e2074686520466f6f20313138
-                     20636f6e666572656e6365
-                     # "Join the Foo 118 conference"
-                  60                                # text(0)
-            "#,
+        // Make the content type of the second part of the inner multipart
+        // invalid UTF-8
+        let part = |content_type: &str| NestedPart::SinglePart {
+            disposition: Disposition::Render,
+            language: "".to_owned(),
+            content_type: content_type.to_owned(),
+            content: b"Hi".to_vec(),
+        };
+        let multipart = |parts| NestedPart::MultiPart {
+            disposition: Disposition::Render,
+            language: "".to_owned(),
+            part_semantics: PartSemantics::ProcessAll,
+            parts,
+        };
+        let value = MimiContent {
+            nested_part: multipart(vec![
+                part("text/plain"),
+                multipart(vec![part("text/plain"), part("corrupt")]),
+            ]),
+            ..MimiContent::from_markdown("", Salt::from([0; 16]))
+        };
+        let mut corrupt = value.serialize().unwrap();
+        let start = corrupt
+            .windows(7)
+            .position(|window| window == b"corrupt")
+            .unwrap();
+        corrupt[start] = 0xff;
+        let error = MimiContent::deserialize_debug(&corrupt).unwrap_err();
+        assert_eq!(error.field, "nested_part");
+        assert_eq!(error.part_path, [1, 1]);
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "decoding nested_part at part 1.1 failed near byte {}",
+                error.offset
+            )
         );
 
-        assert_eq!(hex::encode(result), hex::encode(target));
+        let mut indefinite = corrupt.clone();
+        indefinite[0] = 0x9f;
+        indefinite.push(0xff);
+        let error = MimiContent::deserialize_debug(&indefinite).unwrap_err();
+        assert_eq!(error.field, "nested_part");
+        assert_eq!(error.part_path, [1, 1]);
     }
 
     #[test]
-    fn multipart() {
+    fn multipart_order() {
+        // Deliberately not sorted by any field
+        let parts: Vec<_> = ["e", "a", "d", "a", "b"]
+            .into_iter()
+            .enumerate()
+            .map(|(index, text)| NestedPart::SinglePart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+                content_type: "text/plain".to_owned(),
+                content: format!("{text}{index}").into_bytes(),
+            })
+            .collect();
         let value = MimiContent {
-            salt: hex::decode("261c953e178af653fe3d42641b91d814").unwrap(),
-            replaces: None,
-            topic_id: b"".to_vec(),
-            expires: None,
-            in_reply_to: None,
-            extensions: extensions_alice(),
             nested_part: NestedPart::MultiPart {
                 disposition: Disposition::Render,
                 language: "".to_owned(),
-                part_semantics: PartSemantics::ChooseOne,
-                parts: vec![
-                    NestedPart::SinglePart {
-                        disposition: Disposition::Render,
-                        language: "".to_owned(),
-                        content_type: "text/markdown;variant=GFM-MIMI".to_owned(),
-                        content: b"# Welcome!".to_vec(),
-                    },
-                    NestedPart::SinglePart {
-                        disposition: Disposition::Render,
-                        language: "".to_owned(),
-                        content_type: "application/vnd.examplevendor-fancy-im-message".to_owned(),
-                        content: hex::decode("dc861ebaa718fd7c3ca159f71a2001").unwrap(),
-                    },
-                ],
+                part_semantics: PartSemantics::ProcessAll,
+                parts: parts.clone(),
             },
+            ..MimiContent::from_markdown("", Salt::from([0; 16]))
         };
 
-        let result = value.serialize().unwrap();
+        let encoded = value.serialize().unwrap();
+        let NestedPart::MultiPart {
+            parts: decoded_parts,
+            ..
+        } = MimiContent::deserialize(&encoded).unwrap().nested_part
+        else {
+            panic!("not a multipart");
+        };
+        assert_eq!(decoded_parts, parts);
 
+        let borrowed = MimiContentRef::deserialize(&encoded).unwrap();
+        let NestedPartRef::MultiPart {
+            parts: borrowed_parts,
+            ..
+        } = borrowed.nested_part
+        else {
+            panic!("not a multipart");
+        };
+        let contents: Vec<_> = borrowed_parts
+            .iter()
+            .map(|part| match part {
+                NestedPartRef::SinglePart { content, .. } => content.to_vec(),
+                _ => Vec::new(),
+            })
+            .collect();
         assert_eq!(
-            hex::encode(
-                value
-                    .message_id(
-                        b"mimi://example.com/u/alice-smith",
-                        b"mimi://example.com/r/engineering_team"
-                    )
-                    .unwrap()
-            ),
-            "015c0469c52da0938c27cfa16702e27735a4729746be5f64bc5838f754828464"
+            contents,
+            ["e0", "a1", "d2", "a3", "b4"].map(|text| text.as_bytes().to_vec())
         );
+    }
 
-        // Test deserialization
-        let value2 = MimiContent::deserialize(&result).unwrap();
-        assert_eq!(value, value2);
+    #[test]
+    fn id_prefix() {
+        assert_eq!(HashAlgorithm::Sha256.id_prefix(), Some(0x01));
+        assert_eq!(HashAlgorithm::Sha512.id_prefix(), None);
+        assert_eq!(HashAlgorithm::Unspecified.id_prefix(), None);
 
-        // Taken from MIMI content format draft
-        let target = hex_decode(
-            r##"
-            87                                      # array(7)
-                50                                   # bytes(16)
-                    261c953e178af653fe3d42641b91d814  # "&\u001C\x95>\u0017\x8A\xF6S\xFE=Bd\e\x91\xD8\u0014"
-                f6                                   # primitive(22)
-                40                                   # bytes(0)
-                                                    # ""
-                f6                                   # primitive(22)
-                f6                                   # primitive(22)
-                a2                                   # map(2)
-                    01                                # unsigned(1)
-                    78 20                             # text(32)
-                        6d696d693a2f2f6578616d706c652e636f6d2f752f616c6963652d736d697468 # "mimi://example.com/u/alice-smith"
-                    02                                # unsigned(2)
-                    78 25                             # text(37)
-                        6d696d693a2f2f6578616d706c652e636f6d2f722f656e67696e656572696e675f7465616d # "mimi://example.com/r/engineering_team"
-                85                                   # array(5)
-                    01                                # unsigned(1)
-                    60                                # text(0)
-                                                    # ""
-                    03                                # unsigned(3)
-                    00                                # unsigned(0)
-                    82                                # array(2)
-                        85                             # array(5)
-                        01                          # unsigned(1)
-                        60                          # text(0)
-                                                    # ""
-                        01                          # unsigned(1)
-                        78 1e                       # text(30)
-                            746578742f6d61726b646f776e3b76617269616e743d47464d2d4d494d49 # "text/markdown;variant=GFM-MIMI"
-                        4a                          # bytes(10)
-                            232057656c636f6d6521     # "# Welcome!"
-                        85                             # array(5)
-                        01                          # unsigned(1)
-                        60                          # text(0)
-                                                    # ""
-                        01                          # unsigned(1)
-                        78 2e                       # text(46)
-                            6170706c69636174696f6e2f766e642e6578616d706c6576656e646f722d66616e63792d696d2d6d657373616765 # "application/vnd.examplevendor-fancy-im-message"
-                        4f                          # bytes(15)
-                            dc861ebaa718fd7c3ca159f71a2001 # "܆\u001E\xBA\xA7\u0018\xFD|<\xA1Y\xF7\u001A \u0001"
-            "##,
+        let value = MimiContent::from_draft_vector("reply").unwrap();
+        let sender = b"mimi://example.com/u/bob-jones";
+        let room = b"mimi://example.com/r/engineering_team";
+        let id = value
+            .message_id_with(HashAlgorithm::Sha256, sender, room)
+            .unwrap();
+        assert_eq!(id[0], HashAlgorithm::Sha256.id_prefix().unwrap());
+        assert_eq!(id, value.message_id(sender, room).unwrap());
+        assert_eq!(
+            hex::encode(id),
+            "01a419aef4e16d43cfc06c28235ecfbe9faebc740d0148e7ca20b22150930836"
         );
+        assert!(matches!(
+            value.message_id_with(HashAlgorithm::Sha512, sender, room),
+            Err(Error::UnsupportedHashAlgorithm)
+        ));
+    }
 
-        assert_eq!(hex::encode(result), hex::encode(target));
+    #[test]
+    fn strip_extensions() {
+        let sender = b"mimi://example.com/u/alice-smith";
+        let room = b"mimi://example.com/r/engineering_team";
+        let mut value = MimiContent::from_draft_vector("original_message")
+            .unwrap()
+            .with_extension_text("x-vendor", 7u8);
+
+        let stripped = value.without_extensions();
+        assert_eq!(stripped.extension_count(), 0);
+        assert_eq!(stripped.nested_part, value.nested_part);
+        assert_ne!(
+            stripped.message_id(sender, room).unwrap(),
+            value.message_id(sender, room).unwrap()
+        );
+
+        value.retain_extensions(&[
+            ExtensionName::Number(2),
+            ExtensionName::Text("x-vendor".to_owned()),
+        ]);
+        assert!(!value.has_extension(&ExtensionName::Number(1)));
+        assert_eq!(
+            value.extension_keys().collect::<Vec<_>>(),
+            [
+                &ExtensionName::Text("x-vendor".to_owned()),
+                &ExtensionName::Number(2)
+            ]
+        );
     }
 
-    #[cfg(feature = "serde")]
     #[test]
-    fn multipart_minicbor_serde_compat() {
-        let value = MimiContent {
-            salt: hex::decode("261c953e178af653fe3d42641b91d814").unwrap(),
-            replaces: None,
-            topic_id: b"".to_vec(),
-            expires: None,
-            in_reply_to: None,
+    fn deserialize_version() {
+        let v1 = MimiContentV1 {
+            topic_id: b"Foo 118".to_vec(),
+            last_seen: vec![vec![0x01; 32].into()],
             extensions: extensions_alice(),
+            nested_part: "Hi".into(),
+            ..Default::default()
+        };
+        let mut encoded_v1 = Vec::new();
+        minicbor::encode(&v1, &mut encoded_v1).unwrap();
+        assert_eq!(
+            MimiContent::deserialize_version(&encoded_v1, 1).unwrap(),
+            v1.clone().upgrade()
+        );
+        assert!(MimiContent::deserialize_version(&encoded_v1, CONTENT_VERSION).is_err());
+
+        let reply = MimiContent::from_draft_vector("reply").unwrap();
+        let encoded = reply.serialize().unwrap();
+        assert_eq!(
+            MimiContent::deserialize_version(&encoded, CONTENT_VERSION).unwrap(),
+            reply
+        );
+        assert!(MimiContent::deserialize_version(&encoded, 1).is_err());
+        assert!(matches!(
+            MimiContent::deserialize_version(&encoded, 3),
+            Err(Error::UnsupportedVersion(3))
+        ));
+    }
+
+    #[test]
+    fn is_well_formed() {
+        for vector in crate::draft_vectors::draft_vectors() {
+            assert!(
+                MimiContent::is_well_formed(&vector.encoded().unwrap()),
+                "{}",
+                vector.name
+            );
+        }
+
+        let mut not_mimi = Vec::new();
+        let value = cbor::Value::Map(BTreeMap::from([(
+            "salt".to_owned(),
+            cbor::Value::Bytes(vec![0; 16]),
+        )]));
+        minicbor::encode(&value, &mut not_mimi).unwrap();
+        assert!(!MimiContent::is_well_formed(&not_mimi));
+        assert!(!MimiContent::is_well_formed(&[]));
+
+        // Array of seven integers
+        assert!(!MimiContent::is_well_formed(&[0x87, 0, 1, 2, 3, 4, 5, 6]));
+
+        let encoded = MimiContent::from_draft_vector("multipart")
+            .unwrap()
+            .serialize()
+            .unwrap();
+        assert!(!MimiContent::is_well_formed(&encoded[..encoded.len() - 1]));
+        let mut short_salt = MimiContent::from_draft_vector("reply").unwrap();
+        short_salt.salt.pop();
+        assert!(!MimiContent::is_well_formed(
+            &short_salt.serialize().unwrap()
+        ));
+
+        // Indefinite-length top-level array
+        let mut indefinite = encoded.clone();
+        assert_eq!(indefinite[0], 0x87);
+        indefinite[0] = 0x9f;
+        indefinite.push(0xff);
+        assert!(MimiContent::deserialize(&indefinite).is_ok());
+        assert!(MimiContent::is_well_formed(&indefinite));
+        assert!(!MimiContent::is_well_formed(&[0x9f, 0xff]));
+        assert!(!MimiContent::is_well_formed(&indefinite[..8]));
+    }
+
+    #[test]
+    #[cfg(feature = "markdown")]
+    fn validate_markdown_variant() {
+        let markdown = |text: &str| MimiContent::from_markdown(text, Salt::from([0; 16]));
+
+        for text in [
+            "Hi **there**, see <https://example.com> or <MIMI://example.com/u/alice>",
+            "Mail <alice@example.com>, as 1 < 2 and <3",
+            "Inline `<b>bold</b>` and ``a ` <i>`` code",
+            "Escaped \\<b>",
+            "```html\n<script>alert(1)</script>\n```\nafter",
+            "    <div>indented code</div>",
+            "\t<b>indented with a tab</b>",
+            "`a\n<b>` x",
+        ] {
+            assert!(markdown(text).validate_markdown_variant().is_ok(), "{text}");
+        }
+
+        for text in [
+            "Hello <b>world</b>",
+            "<script>alert(1)</script>",
+            "Click <javascript:alert(1)>",
+            "<img src=x onerror=alert(1)>",
+            "<!-- hidden -->",
+            "`code` then <a href=\"https://example.com\">link</a>",
+            "```\nfenced\n```\n</div>",
+            // Indented continuation lines belong to the paragraph or list
+            // item, not to an indented code block
+            "Hello\n    <script>alert(1)</script>",
+            "Hello\n\t<b>x</b>",
+            "- item\n\n    <b>x</b>",
+            // A backtick in the info string means this is not a fence
+            "``` a`b\n<script>alert(1)</script>",
+        ] {
+            assert!(
+                matches!(
+                    markdown(text).validate_markdown_variant(),
+                    Err(Error::InvalidMarkdownVariant { part_path }) if part_path.is_empty()
+                ),
+                "{text}"
+            );
+        }
+
+        // Only parts declaring the variant are checked, and the first
+        // violation is reported
+        let part = |content_type: &str, text: &str| NestedPart::SinglePart {
+            disposition: Disposition::Render,
+            language: "".to_owned(),
+            content_type: content_type.to_owned(),
+            content: text.as_bytes().to_vec(),
+        };
+        let value = MimiContent {
             nested_part: NestedPart::MultiPart {
                 disposition: Disposition::Render,
                 language: "".to_owned(),
                 part_semantics: PartSemantics::ChooseOne,
                 parts: vec![
-                    NestedPart::SinglePart {
-                        disposition: Disposition::Render,
-                        language: "".to_owned(),
-                        content_type: "text/markdown;variant=GFM-MIMI".to_owned(),
-                        content: b"# Welcome!".to_vec(),
-                    },
-                    NestedPart::SinglePart {
-                        disposition: Disposition::Render,
-                        language: "".to_owned(),
-                        content_type: "application/vnd.examplevendor-fancy-im-message".to_owned(),
-                        content: hex::decode("dc861ebaa718fd7c3ca159f71a2001").unwrap(),
-                    },
+                    part("text/markdown", "<b>plain markdown</b>"),
+                    part("text/html", "<b>html</b>"),
+                    part("text/markdown;variant=gfm-mimi", "<b>mimi</b>"),
+                    part("text/markdown;variant=GFM-MIMI", "<i>also invalid</i>"),
                 ],
             },
+            ..Default::default()
         };
+        assert!(matches!(
+            value.validate_markdown_variant(),
+            Err(Error::InvalidMarkdownVariant { part_path }) if part_path == [2]
+        ));
+    }
 
-        let minicbor_bytes = value.serialize().unwrap();
-        let minicbor_serde_bytes = minicbor_serde::to_vec(&value).unwrap();
-
+    #[test]
+    fn content_types() {
+        let mut value = MimiContent::from_draft_vector("multipart").unwrap();
         assert_eq!(
-            hex::encode(&minicbor_bytes),
-            hex::encode(&minicbor_serde_bytes)
+            value.content_types(),
+            BTreeSet::from(
+                [
+                    "application/vnd.examplevendor-fancy-im-message",
+                    "text/markdown"
+                ]
+                .map(String::from)
+            )
         );
 
-        let minicbor_content: MimiContent = MimiContent::deserialize(&minicbor_bytes).unwrap();
-        let minicbor_serde_content: MimiContent =
-            minicbor_serde::from_slice(&minicbor_serde_bytes).unwrap();
-        assert_eq!(minicbor_content, minicbor_serde_content);
+        let supported = BTreeSet::from(["text/markdown".to_owned(), "text/plain".to_owned()]);
+        assert!(value.renderable_with(&supported));
+
+        let NestedPart::MultiPart { part_semantics, .. } = &mut value.nested_part else {
+            panic!("not a multipart");
+        };
+        *part_semantics = PartSemantics::ProcessAll;
+        assert!(!value.renderable_with(&supported));
+        assert!(!value.renderable_with(&BTreeSet::new()));
+
+        let mut all = supported.clone();
+        all.insert("application/vnd.examplevendor-fancy-im-message".to_owned());
+        assert!(value.renderable_with(&all));
+    }
+
+    #[test]
+    fn external_size_limit() {
+        let limits = Limits::default();
+        let mut value = MimiContent::from_draft_vector("attachments").unwrap();
+        assert!(value.validate_limits(&limits).is_ok());
+
+        let NestedPart::ExternalPart { size, .. } = &mut value.nested_part else {
+            panic!("not an external part");
+        };
+        *size = u64::MAX;
+        assert!(matches!(
+            value.nested_part.verify_content_hash(b"", &limits),
+            Err(Error::SizeTooLarge)
+        ));
+        let errors = value.validate(&limits).unwrap_err();
+        assert!(errors
+            .iter()
+            .any(|error| matches!(error, Error::SizeTooLarge)));
+
+        let strict = Limits {
+            max_external_size: 1024,
+            ..Default::default()
+        };
+        let attachment = MimiContent::from_draft_vector("attachments").unwrap();
+        assert!(matches!(
+            attachment.validate_limits(&strict),
+            Err(Error::SizeTooLarge)
+        ));
+
+        // The received data is limited even if the declared size is not
+        let mut small = attachment.nested_part.clone();
+        let NestedPart::ExternalPart { size, .. } = &mut small else {
+            panic!("not an external part");
+        };
+        *size = 1;
+        assert!(matches!(
+            small.verify_content_hash(&[0; 1025], &strict),
+            Err(Error::SizeTooLarge)
+        ));
     }
 
     #[test]
@@ -0,0 +1,282 @@
+// SPDX-FileCopyrightText: 2026 Phoenix R&D GmbH <hello@phnx.im>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Test vectors for the examples in the appendix of the MIMI content format
+//! draft.
+//!
+//! The vectors are bundled in `draft_vectors.txt`, so adding a vector only
+//! requires a change to that file. Each vector holds the values of the
+//! appendix verbatim. Where the appendix is wrong, an erratum overrides
+//! single values and gives the reason, so the file shows both what the draft
+//! says and what this crate produces.
+//!
+//! Available with the `draft-vectors` feature.
+
+use crate::MimiContent;
+
+const VECTORS: &str = include_str!("draft_vectors.txt");
+
+/// A vector that failed to round-trip.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("test vector {vector}: {field} mismatch")]
+pub struct Mismatch {
+    /// Name of the vector
+    pub vector: &'static str,
+    /// One of `encoded` (missing or malformed hex), `decode`, `encode`,
+    /// `message_id` or `erratum` (an erratum repeating the appendix)
+    pub field: &'static str,
+}
+
+/// Values of a vector, as given in the appendix or an erratum.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Values {
+    message_id: Option<&'static str>,
+    encoded: Option<&'static str>,
+}
+
+/// A single test vector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DraftVector {
+    pub name: &'static str,
+    pub sender: &'static str,
+    pub room: &'static str,
+    /// Reason of the erratum overriding values of the appendix, if any
+    pub erratum: Option<&'static str>,
+    appendix: Values,
+    corrected: Values,
+}
+
+/// A section of the bundled file.
+struct Section {
+    name: &'static str,
+    is_erratum: bool,
+    fields: Vec<(&'static str, &'static str)>,
+}
+
+impl Section {
+    fn parse(section: &'static str) -> Option<Self> {
+        let (header, rest) = section.split_once("]\n")?;
+        let (name, is_erratum) = match header.split_once(' ') {
+            Some((name, "erratum")) => (name, true),
+            Some(_) => return None,
+            None => (header, false),
+        };
+        let (rest, encoded) = match rest.split_once("encoded =") {
+            Some((rest, encoded)) => (rest, Some(encoded)),
+            None => (rest, None),
+        };
+        let mut fields: Vec<_> = rest
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .collect();
+        fields.extend(encoded.map(|encoded| ("encoded", encoded)));
+        Some(Self {
+            name,
+            is_erratum,
+            fields,
+        })
+    }
+
+    fn field(&self, key: &str) -> Option<&'static str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| *field == key)
+            .map(|(_, value)| *value)
+    }
+
+    fn values(&self) -> Values {
+        Values {
+            message_id: self.field("message_id"),
+            encoded: self.field("encoded"),
+        }
+    }
+}
+
+impl DraftVector {
+    /// Returns the expected message ID.
+    pub fn message_id(&self) -> Option<Vec<u8>> {
+        decode_hex(self.corrected.message_id.or(self.appendix.message_id)?)
+    }
+
+    /// Returns the expected encoding of the message.
+    pub fn encoded(&self) -> Option<Vec<u8>> {
+        decode_hex(self.corrected.encoded.or(self.appendix.encoded)?)
+    }
+
+    /// Checks that the vector decodes, encodes to the same bytes and has the
+    /// expected message ID, and that its erratum, if any, still differs from
+    /// the appendix.
+    pub fn check(&self) -> Result<(), Mismatch> {
+        let mismatch = |field| Mismatch {
+            vector: self.name,
+            field,
+        };
+
+        for (appendix, corrected) in [
+            (self.appendix.message_id, self.corrected.message_id),
+            (self.appendix.encoded, self.corrected.encoded),
+        ] {
+            if let (Some(appendix), Some(corrected)) = (appendix, corrected) {
+                if decode_hex(appendix) == decode_hex(corrected) {
+                    return Err(mismatch("erratum"));
+                }
+            }
+        }
+
+        let encoded = self.encoded().ok_or_else(|| mismatch("encoded"))?;
+        let content = MimiContent::deserialize(&encoded).map_err(|_| mismatch("decode"))?;
+        if content.serialize().ok() != Some(encoded) {
+            return Err(mismatch("encode"));
+        }
+        let message_id = content
+            .message_id(self.sender.as_bytes(), self.room.as_bytes())
+            .ok();
+        if message_id.is_none() || message_id != self.message_id() {
+            return Err(mismatch("message_id"));
+        }
+        Ok(())
+    }
+}
+
+/// Returns all bundled test vectors, with their errata applied.
+pub fn draft_vectors() -> impl Iterator<Item = DraftVector> {
+    let sections: Vec<_> = VECTORS
+        .split("\n[")
+        .skip(1)
+        .filter_map(Section::parse)
+        .collect();
+    let errata: Vec<_> = sections
+        .iter()
+        .filter(|section| section.is_erratum)
+        .map(|erratum| (erratum.name, erratum.field("reason"), erratum.values()))
+        .collect();
+    sections
+        .into_iter()
+        .filter(|section| !section.is_erratum)
+        .map(move |section| {
+            let (erratum, corrected) = errata
+                .iter()
+                .find(|(name, ..)| *name == section.name)
+                .map(|(_, reason, values)| (*reason, values.clone()))
+                .unwrap_or_default();
+            DraftVector {
+                name: section.name,
+                sender: section.field("sender").unwrap_or_default(),
+                room: section.field("room").unwrap_or_default(),
+                erratum,
+                appendix: section.values(),
+                corrected,
+            }
+        })
+}
+
+/// Checks every bundled test vector and returns the first mismatch.
+pub fn check_draft_vectors() -> Result<(), Mismatch> {
+    draft_vectors().try_for_each(|vector| vector.check())
+}
+
+impl MimiContent {
+    /// Decodes the bundled test vector `name`.
+    pub fn from_draft_vector(name: &str) -> Option<Self> {
+        let vector = draft_vectors().find(|vector| vector.name == name)?;
+        MimiContent::deserialize(&vector.encoded()?).ok()
+    }
+}
+
+/// Decodes hex, ignoring whitespace and comments starting with `#`.
+fn decode_hex(input: &str) -> Option<Vec<u8>> {
+    let digits = input
+        .lines()
+        .flat_map(|line| line.split('#').next().unwrap_or_default().chars())
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).map(|digit| digit as u8))
+        .collect::<Option<Vec<_>>>()?;
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    Some(
+        digits
+            .chunks(2)
+            .map(|pair| (pair[0] << 4) | pair[1])
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_vectors() {
+        assert_eq!(draft_vectors().count(), 9);
+        assert_eq!(check_draft_vectors(), Ok(()));
+
+        let reply = MimiContent::from_draft_vector("reply").unwrap();
+        assert_eq!(
+            reply.in_reply_to,
+            decode_hex("01b0084467273cc43d6f0ebeac13eb84229c4fffe8f6c3594c905f47779e5a79")
+        );
+        assert_eq!(MimiContent::from_draft_vector("unknown"), None);
+    }
+
+    #[test]
+    fn reports_mismatch() {
+        let mut vector = draft_vectors().next().unwrap();
+        vector.room = "mimi://example.com/r/another_team";
+        assert_eq!(
+            vector.check(),
+            Err(Mismatch {
+                vector: "original_message",
+                field: "message_id",
+            })
+        );
+
+        vector.corrected.encoded = Some("87 50 5eed");
+        assert_eq!(
+            vector.check(),
+            Err(Mismatch {
+                vector: "original_message",
+                field: "decode",
+            })
+        );
+
+        vector.corrected.encoded = Some("87 5");
+        assert_eq!(vector.encoded(), None);
+        vector.corrected.encoded = None;
+        assert_eq!(
+            vector.check(),
+            Err(Mismatch {
+                vector: "original_message",
+                field: "encoded",
+            })
+        );
+    }
+
+    #[test]
+    fn errata() {
+        let vectors: Vec<_> = draft_vectors().collect();
+        let reply = vectors
+            .iter()
+            .find(|vector| vector.name == "reply")
+            .unwrap();
+        assert!(reply.appendix.encoded.is_some());
+        assert_eq!(reply.corrected.encoded, None);
+        let edit = vectors.iter().find(|vector| vector.name == "edit").unwrap();
+        assert_eq!(edit.appendix.encoded, None);
+        assert!(edit.erratum.is_some_and(|reason| reason.contains("wrong")));
+
+        // An erratum repeating the appendix is stale
+        let mut stale = reply.clone();
+        stale.corrected.encoded = stale.appendix.encoded;
+        assert_eq!(
+            stale.check(),
+            Err(Mismatch {
+                vector: "reply",
+                field: "erratum",
+            })
+        );
+    }
+}
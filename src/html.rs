@@ -0,0 +1,170 @@
+// SPDX-FileCopyrightText: 2026 Phoenix R&D GmbH <hello@phnx.im>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use std::collections::HashSet;
+
+use pulldown_cmark::{Event, Tag, TagEnd};
+
+use crate::{
+    content_container::MarkdownVariant,
+    markdown::{self, LINK_SCHEMES},
+    Error, MimiContent, Result,
+};
+
+impl MimiContent {
+    /// Renders a top-level markdown part to sanitized HTML, e.g. for display
+    /// in a webview.
+    ///
+    /// Parts of type `text/markdown;variant=GFM-MIMI`, or `text/markdown`
+    /// without a variant, are rendered with the GFM tables and strikethrough
+    /// extensions. Raw HTML is not part of GFM-MIMI and is escaped, so it is
+    /// shown as text. Images are replaced by their alt text, since loading
+    /// them would tell their server that the message was read. The result is
+    /// then sanitized with ammonia, which removes scripts, images, event
+    /// handler and style attributes, and links to schemes other than `http`,
+    /// `https`, `mailto` and `mimi`.
+    ///
+    /// Returns [`Error::UnsupportedContentType`] for any other part and
+    /// [`Error::NotUtf8`] if the markdown is not UTF-8.
+    pub fn render_html(&self) -> Result<String> {
+        let (variant, markdown) = self
+            .render_markdown()
+            .ok_or(Error::UnsupportedContentType)??;
+        if !matches!(
+            variant,
+            MarkdownVariant::GfmMimi | MarkdownVariant::Unspecified
+        ) {
+            return Err(Error::UnsupportedContentType);
+        }
+
+        let events = markdown::parser(&markdown).filter_map(|event| match event {
+            Event::Html(html) | Event::InlineHtml(html) => Some(Event::Text(html)),
+            Event::Start(Tag::Image { .. }) | Event::End(TagEnd::Image) => None,
+            event => Some(event),
+        });
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, events);
+
+        Ok(ammonia::Builder::default()
+            .url_schemes(HashSet::from(LINK_SCHEMES))
+            .rm_tags(["img"])
+            .clean(&html)
+            .to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{content_container::Salt, Disposition, NestedPart};
+
+    use super::*;
+
+    fn markdown(text: &str) -> MimiContent {
+        MimiContent::from_markdown(text, Salt::from([0; 16]))
+    }
+
+    #[test]
+    fn render_html() {
+        assert_eq!(
+            markdown("Hello **world**, ~~bye~~").render_html().unwrap(),
+            "<p>Hello <strong>world</strong>, <del>bye</del></p>\n"
+        );
+        assert_eq!(
+            markdown("[room](mimi://example.com/r/engineering)")
+                .render_html()
+                .unwrap(),
+            "<p><a href=\"mimi://example.com/r/engineering\" rel=\"noopener noreferrer\">room</a></p>\n"
+        );
+
+        let plain = MimiContent {
+            nested_part: NestedPart::SinglePart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+                content_type: "text/plain;charset=utf-8".to_owned(),
+                content: b"Hello".to_vec(),
+            },
+            ..markdown("")
+        };
+        assert!(matches!(
+            plain.render_html(),
+            Err(Error::UnsupportedContentType)
+        ));
+        let other_variant = MimiContent {
+            nested_part: NestedPart::SinglePart {
+                disposition: Disposition::Render,
+                language: "".to_owned(),
+                content_type: "text/markdown;variant=Original".to_owned(),
+                content: b"Hello".to_vec(),
+            },
+            ..markdown("")
+        };
+        assert!(matches!(
+            other_variant.render_html(),
+            Err(Error::UnsupportedContentType)
+        ));
+        assert!(matches!(
+            MimiContent::default().render_html(),
+            Err(Error::UnsupportedContentType)
+        ));
+    }
+
+    #[test]
+    fn render_html_xss() {
+        let attempts = [
+            "<script>alert(1)</script>",
+            "Hi <img src=x onerror=alert(1)>",
+            "<a href=\"javascript:alert(1)\">click</a>",
+            "[click](javascript:alert(1))",
+            "[click](JaVaScRiPt:alert(1))",
+            "<javascript:alert(1)>",
+            "[click](data:text/html;base64,PHNjcmlwdD5hbGVydCgxKTwvc2NyaXB0Pg==)",
+            "![x](x\" onerror=\"alert(1))",
+            "<div style=\"background:url(javascript:alert(1))\">x</div>",
+            "<iframe src=\"https://example.com\"></iframe>",
+        ];
+        for attempt in attempts {
+            let html = markdown(attempt)
+                .render_html()
+                .unwrap()
+                .to_ascii_lowercase();
+            // Escaped text never contains `<`, so this finds the actual tags
+            for tag in html.split('<').skip(1) {
+                let tag = tag.split('>').next().unwrap();
+                let name = tag.split(' ').next().unwrap().trim_start_matches('/');
+                assert!(
+                    ["p", "a"].contains(&name),
+                    "{attempt:?} rendered as {html:?}"
+                );
+                for forbidden in ["javascript:", "data:", "onerror", "style"] {
+                    assert!(!tag.contains(forbidden), "{attempt:?} rendered as {html:?}");
+                }
+            }
+        }
+
+        // Images are not loaded, but their alt text is kept
+        for image in [
+            "![tracker](https://example.com/pixel.png)",
+            "![tracker](http://example.com/pixel.png \"title\")",
+            "![tracker][pixel]\n\n[pixel]: https://example.com/pixel.png",
+        ] {
+            assert_eq!(
+                markdown(image).render_html().unwrap(),
+                "<p>tracker</p>\n",
+                "{image:?}"
+            );
+        }
+        assert_eq!(
+            markdown("[![logo](https://example.com/logo.png)](https://example.com)")
+                .render_html()
+                .unwrap(),
+            "<p><a href=\"https://example.com\" rel=\"noopener noreferrer\">logo</a></p>\n"
+        );
+
+        // Raw HTML is shown as text rather than dropped
+        assert_eq!(
+            markdown("<script>alert(1)</script>").render_html().unwrap(),
+            "&lt;script&gt;alert(1)&lt;/script&gt;"
+        );
+    }
+}
@@ -3,13 +3,31 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 pub mod cbor;
 pub mod content_container;
+#[cfg(any(test, feature = "draft-vectors"))]
+pub mod draft_vectors;
+#[cfg(feature = "html")]
+mod html;
+#[cfg(feature = "markdown")]
+mod markdown;
+mod media_type;
 mod message_status;
+mod nonce_tracker;
+mod part_router;
+mod patch;
+#[cfg(feature = "postcard")]
+mod postcard;
+mod reactions;
 #[cfg(feature = "serde")]
 mod serde;
 pub(crate) mod util;
 
 pub use content_container::{Disposition, Error, MimiContent, NestedPart, Result};
+pub use media_type::MediaType;
 pub use message_status::{MessageStatus, MessageStatusReport, PerMessageStatus, Timestamp};
+pub use nonce_tracker::NonceTracker;
+pub use part_router::PartRouter;
+pub use patch::ContentPatch;
+pub use reactions::ReactionAggregator;
 
 #[cfg(test)]
 fn hex_decode(input: &str) -> Vec<u8> {
@@ -0,0 +1,17 @@
+// SPDX-FileCopyrightText: 2026 Phoenix R&D GmbH <hello@phnx.im>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use pulldown_cmark::{Options, Parser};
+
+/// URI schemes of links allowed by GFM-MIMI
+pub(crate) const LINK_SCHEMES: [&str; 4] = ["http", "https", "mailto", "mimi"];
+
+/// Parses `markdown` as CommonMark with the GFM extensions of GFM-MIMI,
+/// tables and strikethrough.
+pub(crate) fn parser(markdown: &str) -> Parser<'_> {
+    Parser::new_ext(
+        markdown,
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
+    )
+}
@@ -0,0 +1,82 @@
+// SPDX-FileCopyrightText: 2026 Phoenix R&D GmbH <hello@phnx.im>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+/// A media type as found in the `content_type` of a part, e.g.
+/// `text/markdown;variant=GFM-MIMI`.
+///
+/// Parsing is lenient: the input is split at `;` and `=` without validating
+/// the token syntax of RFC 6838. Comparisons of type, subtype and parameter
+/// names are case-insensitive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MediaType<'a> {
+    type_: &'a str,
+    subtype: &'a str,
+    parameters: &'a str,
+}
+
+impl<'a> MediaType<'a> {
+    pub fn parse(content_type: &'a str) -> Self {
+        let (essence, parameters) = content_type.split_once(';').unwrap_or((content_type, ""));
+        let (type_, subtype) = essence.split_once('/').unwrap_or((essence, ""));
+        Self {
+            type_: type_.trim(),
+            subtype: subtype.trim(),
+            parameters,
+        }
+    }
+
+    pub fn type_(&self) -> &'a str {
+        self.type_
+    }
+
+    pub fn subtype(&self) -> &'a str {
+        self.subtype
+    }
+
+    /// Returns whether the type and subtype equal `essence`, e.g.
+    /// `text/markdown`.
+    pub fn is(&self, essence: &str) -> bool {
+        let (type_, subtype) = essence.split_once('/').unwrap_or((essence, ""));
+        self.type_.eq_ignore_ascii_case(type_) && self.subtype.eq_ignore_ascii_case(subtype)
+    }
+
+    /// Returns the value of the parameter `name`, without surrounding quotes.
+    pub fn parameter(&self, name: &str) -> Option<&'a str> {
+        self.parameters.split(';').find_map(|parameter| {
+            let (key, value) = parameter.split_once('=')?;
+            key.trim().eq_ignore_ascii_case(name).then(|| {
+                let value = value.trim();
+                value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value)
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let media_type = MediaType::parse("text/markdown;variant=GFM-MIMI");
+        assert_eq!(media_type.type_(), "text");
+        assert_eq!(media_type.subtype(), "markdown");
+        assert!(media_type.is("text/markdown"));
+        assert!(media_type.is("Text/Markdown"));
+        assert!(!media_type.is("text/plain"));
+        assert_eq!(media_type.parameter("variant"), Some("GFM-MIMI"));
+        assert_eq!(media_type.parameter("charset"), None);
+
+        let media_type = MediaType::parse("text/plain; Charset=\"utf-8\"");
+        assert!(media_type.is("text/plain"));
+        assert_eq!(media_type.parameter("charset"), Some("utf-8"));
+
+        let media_type = MediaType::parse("application/octet-stream");
+        assert!(media_type.is("application/octet-stream"));
+        assert_eq!(media_type.parameter("charset"), None);
+    }
+}
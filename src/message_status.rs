@@ -2,26 +2,125 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+use std::io;
+
 use num_enum::{FromPrimitive, IntoPrimitive};
 
-use crate::{impl_encode_decode_num_enum, Error, Result};
+use crate::{content_container::MessageId, impl_encode_decode_num_enum, Error, Result};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct MessageStatusReport {
     pub statuses: Vec<PerMessageStatus>,
 }
 
 impl MessageStatusReport {
+    /// Creates an empty report.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends the status of the message `mimi_id` and returns the report.
+    pub fn with_status(mut self, mimi_id: impl Into<Vec<u8>>, status: MessageStatus) -> Self {
+        self.push(PerMessageStatus {
+            mimi_id: mimi_id.into(),
+            status,
+        });
+        self
+    }
+
+    /// Appends a per-message status to the report.
+    pub fn push(&mut self, status: PerMessageStatus) {
+        self.statuses.push(status);
+    }
+
+    pub fn len(&self) -> usize {
+        self.statuses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.statuses.is_empty()
+    }
+
     pub fn serialize(&self) -> Result<Vec<u8>> {
         let mut buf = Vec::new();
-        minicbor::encode(&self.statuses, &mut buf).map_err(Error::Encode)?;
+        self.serialize_to(&mut buf)?;
         Ok(buf)
     }
 
+    /// Serializes the report into `writer`, one per-message status at a
+    /// time, so that large reports need not be buffered in full.
+    pub fn serialize_to<W: io::Write>(&self, mut writer: W) -> Result<()> {
+        let mut buf = Vec::new();
+        minicbor::Encoder::new(&mut buf)
+            .array(self.statuses.len() as u64)
+            .map_err(Error::Encode)?;
+        writer.write_all(&buf).map_err(Error::Io)?;
+        for status in &self.statuses {
+            buf.clear();
+            minicbor::encode(status, &mut buf).map_err(Error::Encode)?;
+            writer.write_all(&buf).map_err(Error::Io)?;
+        }
+        Ok(())
+    }
+
+    /// Deserializes a report, checking that every `mimi_id` is a well-formed
+    /// message ID.
     pub fn deserialize(input: &[u8]) -> Result<Self> {
-        Ok(Self {
+        let report = Self {
             statuses: minicbor::decode(input).map_err(Error::Decode)?,
-        })
+        };
+        report.validate()?;
+        Ok(report)
+    }
+
+    /// Checks that every `mimi_id` is a well-formed message ID.
+    pub fn validate(&self) -> Result<()> {
+        for (index, status) in self.statuses.iter().enumerate() {
+            MessageId::try_from(status.mimi_id.as_slice())
+                .map_err(|_| Error::InvalidMessageId { index })?;
+        }
+        Ok(())
+    }
+
+    /// Like [`MessageStatusReport::deserialize`], but rejects `Custom`
+    /// statuses.
+    pub fn deserialize_strict(input: &[u8]) -> Result<Self> {
+        let report = Self::deserialize(input)?;
+        for status in &report.statuses {
+            if let MessageStatus::Custom(value) = status.status {
+                return Err(Error::UnknownDiscriminant {
+                    enum_name: "MessageStatus",
+                    value: value.into(),
+                });
+            }
+        }
+        Ok(report)
+    }
+}
+
+impl FromIterator<PerMessageStatus> for MessageStatusReport {
+    fn from_iter<T: IntoIterator<Item = PerMessageStatus>>(iter: T) -> Self {
+        Self {
+            statuses: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl IntoIterator for MessageStatusReport {
+    type Item = PerMessageStatus;
+    type IntoIter = std::vec::IntoIter<PerMessageStatus>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.statuses.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a MessageStatusReport {
+    type Item = &'a PerMessageStatus;
+    type IntoIter = std::slice::Iter<'a, PerMessageStatus>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.statuses.iter()
     }
 }
 
@@ -39,8 +138,33 @@ pub struct PerMessageStatus {
     pub status: MessageStatus,
 }
 
+impl PerMessageStatus {
+    /// Creates the status of the message `id`, checking that it is a
+    /// well-formed message ID.
+    pub fn new(id: impl TryInto<MessageId>, status: MessageStatus) -> Result<Self> {
+        let id = id.try_into().map_err(|_| Error::MalformedMessageId)?;
+        Ok(Self {
+            mimi_id: id.into(),
+            status,
+        })
+    }
+
+    pub fn unread(id: impl TryInto<MessageId>) -> Result<Self> {
+        Self::new(id, MessageStatus::Unread)
+    }
+
+    pub fn delivered(id: impl TryInto<MessageId>) -> Result<Self> {
+        Self::new(id, MessageStatus::Delivered)
+    }
+
+    pub fn read(id: impl TryInto<MessageId>) -> Result<Self> {
+        Self::new(id, MessageStatus::Read)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, FromPrimitive)]
 #[repr(u8)]
+#[non_exhaustive]
 pub enum MessageStatus {
     Unread = 0,
     Delivered = 1,
@@ -49,12 +173,55 @@ pub enum MessageStatus {
     Deleted = 4,
     Hidden = 5,
     Error = 6,
+    /// Custom status in the range 7 to 255
     #[num_enum(catch_all)]
     Custom(u8),
 }
 
 impl_encode_decode_num_enum!(MessageStatus, u8);
 
+/// Lifecycle of a message from the point of view of one recipient:
+///
+/// - `Delivered` can become `Unread`, `Read`, `Hidden` or `Error`.
+/// - `Unread`, `Read` and `Hidden` can become each other.
+/// - `Error` can become `Delivered`, when a retry succeeds.
+/// - Any of the above can become `Expired` or `Deleted`, which are terminal.
+///
+/// Transitions from or to a `Custom` status are allowed unless the current
+/// status is terminal, as their meaning is not known.
+impl MessageStatus {
+    /// Returns whether no further transitions are expected.
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, Self::Expired | Self::Deleted)
+    }
+
+    /// Returns whether the status may change to `next`.
+    pub fn can_transition_to(&self, next: MessageStatus) -> bool {
+        use MessageStatus::*;
+        match (*self, next) {
+            (current, _) if current.is_terminal() || current == next => false,
+            (Custom(_), _) | (_, Custom(_)) | (_, Expired | Deleted) => true,
+            (Delivered, Unread | Read | Hidden | Error) => true,
+            (Unread | Read | Hidden, Unread | Read | Hidden) => true,
+            (Error, Delivered) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns all named variants, i.e. every value except `Custom`.
+    pub fn all_known() -> &'static [Self] {
+        &[
+            Self::Unread,
+            Self::Delivered,
+            Self::Read,
+            Self::Expired,
+            Self::Deleted,
+            Self::Hidden,
+            Self::Error,
+        ]
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -126,4 +293,266 @@ mod tests {
 
         assert_eq!(hex::encode(result), hex::encode(target));
     }
+
+    #[test]
+    fn fluent_report() {
+        let read = hex::decode(b"010714238126772e253118df3cd18fa69f90841d7df1f6f0cddab1f0dc0c9a26")
+            .unwrap();
+        let unread =
+            hex::decode(b"0103d50d4980c0a7a0990f65534ebd4f0fa36b1f4680d6e080c19ea4a95def7b")
+                .unwrap();
+
+        let mut report = MessageStatusReport::new();
+        assert!(report.is_empty());
+
+        report = report.with_status(read.clone(), MessageStatus::Read);
+        report.push(PerMessageStatus {
+            mimi_id: unread.clone(),
+            status: MessageStatus::Unread,
+        });
+        assert_eq!(report.len(), 2);
+        assert_eq!(
+            report.statuses,
+            vec![
+                PerMessageStatus {
+                    mimi_id: read,
+                    status: MessageStatus::Read,
+                },
+                PerMessageStatus {
+                    mimi_id: unread,
+                    status: MessageStatus::Unread,
+                },
+            ]
+        );
+
+        let decoded = MessageStatusReport::deserialize(&report.serialize().unwrap()).unwrap();
+        assert_eq!(report, decoded);
+    }
+
+    #[test]
+    fn strict_deserialization() {
+        let report = MessageStatusReport::new().with_status(
+            hex::decode(b"010714238126772e253118df3cd18fa69f90841d7df1f6f0cddab1f0dc0c9a26")
+                .unwrap(),
+            MessageStatus::Custom(200),
+        );
+        let bytes = report.serialize().unwrap();
+
+        assert_eq!(MessageStatusReport::deserialize(&bytes).unwrap(), report);
+        assert!(matches!(
+            MessageStatusReport::deserialize_strict(&bytes),
+            Err(Error::UnknownDiscriminant {
+                enum_name: "MessageStatus",
+                value: 200
+            })
+        ));
+    }
+
+    #[test]
+    fn invalid_message_id() {
+        let report = MessageStatusReport::new()
+            .with_status(
+                hex::decode(b"010714238126772e253118df3cd18fa69f90841d7df1f6f0cddab1f0dc0c9a26")
+                    .unwrap(),
+                MessageStatus::Read,
+            )
+            .with_status(vec![0x01; 10], MessageStatus::Read);
+        assert!(matches!(
+            report.validate(),
+            Err(Error::InvalidMessageId { index: 1 })
+        ));
+
+        let bytes = report.serialize().unwrap();
+        assert!(matches!(
+            MessageStatusReport::deserialize(&bytes),
+            Err(Error::InvalidMessageId { index: 1 })
+        ));
+    }
+
+    #[test]
+    fn custom_boundary() {
+        for (value, status) in [
+            (0u8, MessageStatus::Unread),
+            (6, MessageStatus::Error),
+            (7, MessageStatus::Custom(7)),
+            (255, MessageStatus::Custom(255)),
+        ] {
+            let mut buf = Vec::new();
+            minicbor::encode(value, &mut buf).unwrap();
+            let decoded: MessageStatus = minicbor::decode(&buf).unwrap();
+            assert_eq!(decoded, status);
+        }
+    }
+
+    #[test]
+    fn collect_and_iterate() {
+        let ids = [
+            hex::decode(b"010714238126772e253118df3cd18fa69f90841d7df1f6f0cddab1f0dc0c9a26")
+                .unwrap(),
+            hex::decode(b"01efab9eca8374d3618a16b39c658689fd90d07fe666a846178cb4965c94a8bf")
+                .unwrap(),
+        ];
+        let report: MessageStatusReport = ids
+            .iter()
+            .map(|id| PerMessageStatus {
+                mimi_id: id.clone(),
+                status: MessageStatus::Delivered,
+            })
+            .collect();
+        assert_eq!(report.len(), 2);
+
+        for (status, id) in std::iter::zip(&report, &ids) {
+            assert_eq!(&status.mimi_id, id);
+            assert_eq!(status.status, MessageStatus::Delivered);
+        }
+
+        let statuses: Vec<_> = report.clone().into_iter().collect();
+        assert_eq!(statuses, report.statuses);
+    }
+
+    #[test]
+    fn streamed_serialization() {
+        let report: MessageStatusReport = (0..1000u32)
+            .map(|i| {
+                let mut mimi_id = vec![0x01; 28];
+                mimi_id.extend(i.to_be_bytes());
+                PerMessageStatus {
+                    mimi_id,
+                    status: MessageStatus::from((i % 7) as u8),
+                }
+            })
+            .collect();
+
+        let mut streamed = Vec::new();
+        report.serialize_to(&mut streamed).unwrap();
+        let mut buf = Vec::new();
+        minicbor::encode(&report.statuses, &mut buf).unwrap();
+        assert_eq!(streamed, buf);
+        assert_eq!(MessageStatusReport::deserialize(&streamed).unwrap(), report);
+
+        let mut streamed = Vec::new();
+        MessageStatusReport::new()
+            .serialize_to(&mut streamed)
+            .unwrap();
+        assert_eq!(streamed, [0x80]);
+    }
+
+    #[test]
+    fn all_known_statuses() {
+        assert_eq!(MessageStatus::all_known().len(), 7);
+        for (value, status) in MessageStatus::all_known().iter().enumerate() {
+            assert_eq!(u8::from(*status), value as u8);
+
+            let report = MessageStatusReport::new().with_status(vec![0x01; 32], *status);
+            let decoded =
+                MessageStatusReport::deserialize_strict(&report.serialize().unwrap()).unwrap();
+            assert_eq!(decoded, report);
+        }
+    }
+
+    #[test]
+    fn lifecycle() {
+        use MessageStatus::*;
+        assert!(Deleted.is_terminal());
+        assert!(Expired.is_terminal());
+        assert!(!Unread.is_terminal());
+        assert!(!Custom(7).is_terminal());
+
+        for (current, next) in [
+            (Delivered, Read),
+            (Delivered, Error),
+            (Read, Unread),
+            (Unread, Hidden),
+            (Hidden, Read),
+            (Error, Delivered),
+            (Read, Deleted),
+            (Delivered, Expired),
+            (Read, Custom(7)),
+            (Custom(7), Unread),
+        ] {
+            assert!(current.can_transition_to(next), "{current:?} -> {next:?}");
+        }
+
+        for (current, next) in [
+            (Deleted, Unread),
+            (Expired, Read),
+            (Deleted, Expired),
+            (Deleted, Custom(7)),
+            (Read, Delivered),
+            (Unread, Error),
+            (Error, Read),
+            (Read, Read),
+        ] {
+            assert!(!current.can_transition_to(next), "{current:?} -> {next:?}");
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_report() {
+        let report = MessageStatusReport::new()
+            .with_status(vec![0x01; 32], MessageStatus::Read)
+            .with_status(vec![0x01; 32], MessageStatus::Custom(9));
+
+        let value = ciborium::Value::serialized(&report).unwrap();
+        assert!(value.is_array());
+        let decoded: MessageStatusReport = value.deserialized().unwrap();
+        assert_eq!(decoded, report);
+
+        assert_eq!(
+            minicbor_serde::to_vec(&report).unwrap(),
+            report.serialize().unwrap()
+        );
+
+        // Message IDs are validated as in `MessageStatusReport::deserialize`
+        let malformed = report.with_status(vec![0x01; 3], MessageStatus::Read);
+        let error = ciborium::Value::serialized(&malformed)
+            .unwrap()
+            .deserialized::<MessageStatusReport>()
+            .unwrap_err();
+        assert!(error.to_string().contains("invalid message id at index 2"));
+    }
+
+    #[test]
+    fn per_message_constructors() {
+        let id = MessageId::try_from(
+            hex::decode(b"010714238126772e253118df3cd18fa69f90841d7df1f6f0cddab1f0dc0c9a26")
+                .unwrap(),
+        )
+        .unwrap();
+
+        for (status, expected) in [
+            (PerMessageStatus::unread(id), MessageStatus::Unread),
+            (
+                PerMessageStatus::delivered(id.to_vec()),
+                MessageStatus::Delivered,
+            ),
+            (PerMessageStatus::read(&id[..]), MessageStatus::Read),
+            (
+                PerMessageStatus::new(id, MessageStatus::Hidden),
+                MessageStatus::Hidden,
+            ),
+        ] {
+            let status = status.unwrap();
+            assert_eq!(status.mimi_id, id.to_vec());
+            assert_eq!(status.status, expected);
+        }
+
+        assert!(matches!(
+            PerMessageStatus::read(&[0x01; 31][..]),
+            Err(Error::MalformedMessageId)
+        ));
+        assert!(matches!(
+            PerMessageStatus::read(vec![0x02; 32]),
+            Err(Error::MalformedMessageId)
+        ));
+
+        let report = [id, id]
+            .into_iter()
+            .map(PerMessageStatus::read)
+            .collect::<Result<MessageStatusReport>>()
+            .unwrap();
+        assert_eq!(report.len(), 2);
+        assert!(report.validate().is_ok());
+    }
 }
@@ -0,0 +1,118 @@
+// SPDX-FileCopyrightText: 2026 Phoenix R&D GmbH <hello@phnx.im>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use std::collections::HashSet;
+
+use sha2::{Digest, Sha256};
+
+use crate::{content_container::EncryptionAlgorithm, Error, MimiContent, NestedPart, Result};
+
+/// Detects the reuse of a key and nonce pair across external parts.
+///
+/// This crate does not encrypt attachments itself, so the tracker is meant
+/// to be consulted by the caller for every external part it encrypts, e.g.
+/// before sending. Reusing a nonce with the same key breaks the
+/// confidentiality and integrity of AEADs such as AES-GCM.
+///
+/// Only a hash of each key is retained.
+#[derive(Debug, Clone, Default)]
+pub struct NonceTracker {
+    seen: HashSet<([u8; 32], Vec<u8>)>,
+}
+
+impl NonceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the key and nonce of `part`.
+    ///
+    /// Returns [`Error::NonceReuse`] if the pair was recorded before. Parts
+    /// other than external parts, and external parts without encryption,
+    /// are ignored.
+    pub fn record(&mut self, part: &NestedPart) -> Result<()> {
+        let NestedPart::ExternalPart {
+            enc_alg,
+            key,
+            nonce,
+            ..
+        } = part
+        else {
+            return Ok(());
+        };
+        if *enc_alg == EncryptionAlgorithm::None {
+            return Ok(());
+        }
+        if !self
+            .seen
+            .insert((Sha256::digest(key).into(), nonce.clone()))
+        {
+            return Err(Error::NonceReuse);
+        }
+        Ok(())
+    }
+
+    /// Records the key and nonce of every external part of `content`.
+    ///
+    /// Stops at the first reused pair, which includes pairs repeated within
+    /// `content`.
+    pub fn record_content(&mut self, content: &MimiContent) -> Result<()> {
+        let mut result = Ok(());
+        content.nested_part.walk(|_, part| {
+            if result.is_ok() {
+                result = self.record(part);
+            }
+        });
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{content_container::HashAlgorithm, Disposition};
+
+    use super::*;
+
+    fn attachment(key: &[u8], nonce: &[u8]) -> NestedPart {
+        NestedPart::ExternalPart {
+            disposition: Disposition::Attachment,
+            language: "".to_owned(),
+            content_type: "image/png".to_owned(),
+            url: "https://example.com/storage/abc.png".to_owned(),
+            expires: 0,
+            size: 0,
+            enc_alg: EncryptionAlgorithm::Aes128Gcm,
+            key: key.to_vec(),
+            nonce: nonce.to_vec(),
+            aad: vec![],
+            hash_alg: HashAlgorithm::Sha256,
+            content_hash: vec![],
+            description: "".to_owned(),
+            filename: "abc.png".to_owned(),
+        }
+    }
+
+    #[test]
+    fn nonce_reuse() {
+        let mut tracker = NonceTracker::new();
+        tracker.record(&attachment(&[1; 16], &[1; 12])).unwrap();
+        tracker.record(&attachment(&[1; 16], &[2; 12])).unwrap();
+        tracker.record(&attachment(&[2; 16], &[1; 12])).unwrap();
+        assert!(matches!(
+            tracker.record(&attachment(&[1; 16], &[1; 12])),
+            Err(Error::NonceReuse)
+        ));
+
+        let content = MimiContent::from_draft_vector("attachments").unwrap();
+        tracker.record_content(&content).unwrap();
+        assert!(matches!(
+            tracker.record_content(&content),
+            Err(Error::NonceReuse)
+        ));
+
+        let conference = MimiContent::from_draft_vector("conferencing").unwrap();
+        tracker.record_content(&conference).unwrap();
+        tracker.record_content(&conference).unwrap();
+    }
+}
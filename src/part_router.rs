@@ -0,0 +1,109 @@
+// SPDX-FileCopyrightText: 2026 Phoenix R&D GmbH <hello@phnx.im>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::{MediaType, NestedPart};
+
+type Handler<'a> = Box<dyn Fn(&[usize], &NestedPart) + 'a>;
+
+/// Dispatches single and external parts to handlers by content type.
+///
+/// Patterns are media type essences such as `text/markdown`, or wildcards
+/// such as `text/*` and `*/*`. Parameters of the content type are ignored
+/// when matching. The first matching route wins.
+///
+/// See [`MimiContent::route_parts`](crate::MimiContent::route_parts).
+#[derive(Default)]
+pub struct PartRouter<'a> {
+    routes: Vec<(String, Handler<'a>)>,
+}
+
+impl<'a> PartRouter<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a route for `pattern` and returns the router.
+    ///
+    /// The handler is called with the path of the part, as in
+    /// [`NestedPart::walk`], and the part itself.
+    pub fn route(mut self, pattern: &str, handler: impl Fn(&[usize], &NestedPart) + 'a) -> Self {
+        self.routes.push((pattern.to_owned(), Box::new(handler)));
+        self
+    }
+
+    /// Calls the handler of the first route matching `content_type`, if any.
+    ///
+    /// Returns whether a handler was called.
+    pub fn dispatch(&self, content_type: &str, path: &[usize], part: &NestedPart) -> bool {
+        let media_type = MediaType::parse(content_type);
+        let Some((_, handler)) = self
+            .routes
+            .iter()
+            .find(|(pattern, _)| matches(&media_type, pattern))
+        else {
+            return false;
+        };
+        handler(path, part);
+        true
+    }
+}
+
+fn matches(media_type: &MediaType, pattern: &str) -> bool {
+    if pattern == "*" || pattern == "*/*" {
+        return true;
+    }
+    match pattern.split_once('/') {
+        Some((type_, "*")) => media_type.type_().eq_ignore_ascii_case(type_),
+        _ => media_type.is(pattern),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        let media_type = MediaType::parse("text/markdown;variant=GFM-MIMI");
+        assert!(matches(&media_type, "text/markdown"));
+        assert!(matches(&media_type, "Text/*"));
+        assert!(matches(&media_type, "*/*"));
+        assert!(matches(&media_type, "*"));
+        assert!(!matches(&media_type, "text/plain"));
+        assert!(!matches(&media_type, "image/*"));
+    }
+
+    #[test]
+    fn dispatch_markdown() {
+        use std::cell::Cell;
+
+        let markdown = NestedPart::from("**Hi**");
+        let plain = NestedPart::SinglePart {
+            disposition: crate::Disposition::Render,
+            language: "".to_owned(),
+            content_type: "text/plain".to_owned(),
+            content: b"Hi".to_vec(),
+        };
+
+        let text = Cell::new(0);
+        let router = PartRouter::new().route("text/*", |_, _| text.set(text.get() + 1));
+        assert!(router.dispatch("text/markdown;variant=GFM-MIMI", &[], &markdown));
+        assert!(router.dispatch("text/plain", &[], &plain));
+        assert_eq!(text.get(), 2);
+
+        // A markdown route ahead of the wildcard takes markdown parts only
+        let markdown_parts = Cell::new(0);
+        let text = Cell::new(0);
+        let router = PartRouter::new()
+            .route("text/markdown", |_, part| {
+                assert_eq!(part, &markdown);
+                markdown_parts.set(markdown_parts.get() + 1)
+            })
+            .route("text/*", |_, _| text.set(text.get() + 1));
+        assert!(router.dispatch("text/markdown;variant=GFM-MIMI", &[], &markdown));
+        assert!(router.dispatch("text/plain", &[], &plain));
+        assert!(!router.dispatch("image/png", &[], &plain));
+        assert_eq!((markdown_parts.get(), text.get()), (1, 1));
+    }
+}
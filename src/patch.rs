@@ -0,0 +1,401 @@
+// SPDX-FileCopyrightText: 2026 Phoenix R&D GmbH <hello@phnx.im>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use std::collections::BTreeMap;
+
+use minicbor::bytes::ByteSlice;
+
+use crate::{
+    cbor,
+    content_container::{Expiration, ExtensionName},
+    Error, MimiContent, NestedPart, Result,
+};
+
+/// The changes turning one message into another, e.g. an edit into the
+/// message it replaces.
+///
+/// Created with [`MimiContent::make_patch`] and applied with
+/// [`MimiContent::apply_patch`]. Only changed fields are included. A change
+/// to the content of a single part that keeps its disposition, language and
+/// content type is encoded as a splice of the bytes between the common prefix
+/// and suffix, so a small edit to a large message results in a small patch.
+/// A splice includes the [`MimiContent::body_fingerprint`] of the message it
+/// was made from and only applies to a message with the same body.
+///
+/// All other changes carry the new value of what they change: a replaced
+/// body, a set or removed extension, or a new header field. They result in
+/// the same value whatever the message they are applied to, so they are not
+/// bound to a fingerprint. Fields and extensions the patch does not mention
+/// keep the values of the message it is applied to.
+///
+/// The encoding is a CBOR map from field number to new value:
+///
+/// | Key | Value                                                       |
+/// |-----|-------------------------------------------------------------|
+/// | 0   | salt                                                        |
+/// | 1   | replaces, or null                                           |
+/// | 2   | topic ID                                                    |
+/// | 3   | expiration, or null                                         |
+/// | 4   | in reply to, or null                                        |
+/// | 5   | map of added and changed extensions                         |
+/// | 6   | array of removed extension keys                             |
+/// | 7   | `[0, nested part]` or                                       |
+/// |     | `[1, prefix len, suffix len, bytes, base body fingerprint]` |
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ContentPatch {
+    salt: Option<Vec<u8>>,
+    replaces: Option<Option<Vec<u8>>>,
+    topic_id: Option<Vec<u8>>,
+    expires: Option<Option<Expiration>>,
+    in_reply_to: Option<Option<Vec<u8>>>,
+    extensions_set: BTreeMap<ExtensionName, cbor::Value>,
+    extensions_removed: Vec<ExtensionName>,
+    body: Option<BodyPatch>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum BodyPatch {
+    Replace(NestedPart),
+    Splice {
+        prefix: u64,
+        suffix: u64,
+        bytes: Vec<u8>,
+        base_fingerprint: [u8; 32],
+    },
+}
+
+impl ContentPatch {
+    /// Returns whether applying the patch leaves a message unchanged.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        minicbor::encode(self, &mut buf).map_err(Error::Encode)?;
+        Ok(buf)
+    }
+
+    pub fn deserialize(input: &[u8]) -> Result<Self> {
+        minicbor::decode(input).map_err(Error::Decode)
+    }
+}
+
+impl MimiContent {
+    /// Computes the patch turning this message into `edited`.
+    pub fn make_patch(&self, edited: &MimiContent) -> ContentPatch {
+        let changed = |old: &Vec<u8>, new: &Vec<u8>| (old != new).then(|| new.clone());
+        let changed_option =
+            |old: &Option<Vec<u8>>, new: &Option<Vec<u8>>| (old != new).then(|| new.clone());
+
+        let mut extensions_set = BTreeMap::new();
+        for (key, value) in &edited.extensions {
+            if self.extensions.get(key) != Some(value) {
+                extensions_set.insert(key.clone(), value.clone());
+            }
+        }
+        let extensions_removed = self
+            .extensions
+            .keys()
+            .filter(|key| !edited.extensions.contains_key(*key))
+            .cloned()
+            .collect();
+
+        ContentPatch {
+            salt: changed(&self.salt, &edited.salt),
+            replaces: changed_option(&self.replaces, &edited.replaces),
+            topic_id: changed(&self.topic_id, &edited.topic_id),
+            expires: (self.expires != edited.expires).then(|| edited.expires.clone()),
+            in_reply_to: changed_option(&self.in_reply_to, &edited.in_reply_to),
+            extensions_set,
+            extensions_removed,
+            body: body_patch(self, &edited.nested_part),
+        }
+    }
+
+    /// Applies `patch` as created by [`MimiContent::make_patch`] on this
+    /// message.
+    ///
+    /// Returns [`Error::PatchMismatch`] if the patch splices the body of a
+    /// different message.
+    pub fn apply_patch(&self, patch: &ContentPatch) -> Result<MimiContent> {
+        let mut patched = self.clone();
+        if let Some(salt) = &patch.salt {
+            patched.salt = salt.clone();
+        }
+        if let Some(replaces) = &patch.replaces {
+            patched.replaces = replaces.clone();
+        }
+        if let Some(topic_id) = &patch.topic_id {
+            patched.topic_id = topic_id.clone();
+        }
+        if let Some(expires) = &patch.expires {
+            patched.expires = expires.clone();
+        }
+        if let Some(in_reply_to) = &patch.in_reply_to {
+            patched.in_reply_to = in_reply_to.clone();
+        }
+        for key in &patch.extensions_removed {
+            patched.extensions.remove(key);
+        }
+        for (key, value) in &patch.extensions_set {
+            patched.extensions.insert(key.clone(), value.clone());
+        }
+        match &patch.body {
+            None => {}
+            Some(BodyPatch::Replace(part)) => patched.nested_part = part.clone(),
+            Some(BodyPatch::Splice {
+                prefix,
+                suffix,
+                bytes,
+                base_fingerprint,
+            }) => {
+                if self.body_fingerprint() != *base_fingerprint {
+                    return Err(Error::PatchMismatch);
+                }
+                let NestedPart::SinglePart { content, .. } = &mut patched.nested_part else {
+                    return Err(Error::PatchMismatch);
+                };
+                let prefix = usize::try_from(*prefix).map_err(|_| Error::PatchMismatch)?;
+                let suffix = usize::try_from(*suffix).map_err(|_| Error::PatchMismatch)?;
+                let end = content
+                    .len()
+                    .checked_sub(suffix)
+                    .filter(|end| *end >= prefix)
+                    .ok_or(Error::PatchMismatch)?;
+                content.splice(prefix..end, bytes.iter().copied());
+            }
+        }
+        Ok(patched)
+    }
+}
+
+fn body_patch(base: &MimiContent, new: &NestedPart) -> Option<BodyPatch> {
+    let old = &base.nested_part;
+    if old == new {
+        return None;
+    }
+    match (old, new) {
+        (
+            NestedPart::SinglePart {
+                disposition,
+                language,
+                content_type,
+                content: old_content,
+            },
+            NestedPart::SinglePart {
+                disposition: new_disposition,
+                language: new_language,
+                content_type: new_content_type,
+                content: new_content,
+            },
+        ) if disposition == new_disposition
+            && language == new_language
+            && content_type == new_content_type =>
+        {
+            let prefix = std::iter::zip(old_content, new_content)
+                .take_while(|(old, new)| old == new)
+                .count();
+            let suffix = std::iter::zip(
+                old_content[prefix..].iter().rev(),
+                new_content[prefix..].iter().rev(),
+            )
+            .take_while(|(old, new)| old == new)
+            .count();
+            Some(BodyPatch::Splice {
+                prefix: prefix as u64,
+                suffix: suffix as u64,
+                bytes: new_content[prefix..new_content.len() - suffix].to_vec(),
+                base_fingerprint: base.body_fingerprint(),
+            })
+        }
+        _ => Some(BodyPatch::Replace(new.clone())),
+    }
+}
+
+impl<C> minicbor::Encode<C> for ContentPatch {
+    fn encode<W: minicbor::encode::Write>(
+        &self,
+        e: &mut minicbor::Encoder<W>,
+        _ctx: &mut C,
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        let len = [
+            self.salt.is_some(),
+            self.replaces.is_some(),
+            self.topic_id.is_some(),
+            self.expires.is_some(),
+            self.in_reply_to.is_some(),
+            !self.extensions_set.is_empty(),
+            !self.extensions_removed.is_empty(),
+            self.body.is_some(),
+        ]
+        .into_iter()
+        .filter(|present| *present)
+        .count();
+        e.map(len as u64)?;
+        if let Some(salt) = &self.salt {
+            e.u8(0)?.bytes(salt)?;
+        }
+        if let Some(replaces) = &self.replaces {
+            e.u8(1)?
+                .encode(replaces.as_deref().map(<&ByteSlice>::from))?;
+        }
+        if let Some(topic_id) = &self.topic_id {
+            e.u8(2)?.bytes(topic_id)?;
+        }
+        if let Some(expires) = &self.expires {
+            e.u8(3)?.encode(expires)?;
+        }
+        if let Some(in_reply_to) = &self.in_reply_to {
+            e.u8(4)?
+                .encode(in_reply_to.as_deref().map(<&ByteSlice>::from))?;
+        }
+        if !self.extensions_set.is_empty() {
+            e.u8(5)?.encode(&self.extensions_set)?;
+        }
+        if !self.extensions_removed.is_empty() {
+            e.u8(6)?.encode(&self.extensions_removed)?;
+        }
+        match &self.body {
+            None => {}
+            Some(BodyPatch::Replace(part)) => {
+                e.u8(7)?.array(2)?.u8(0)?.encode(part)?;
+            }
+            Some(BodyPatch::Splice {
+                prefix,
+                suffix,
+                bytes,
+                base_fingerprint,
+            }) => {
+                e.u8(7)?
+                    .array(5)?
+                    .u8(1)?
+                    .u64(*prefix)?
+                    .u64(*suffix)?
+                    .bytes(bytes)?
+                    .bytes(base_fingerprint)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<C> minicbor::Decode<'_, C> for ContentPatch {
+    fn decode(
+        d: &mut minicbor::Decoder<'_>,
+        _ctx: &mut C,
+    ) -> Result<Self, minicbor::decode::Error> {
+        let len = d.map()?.ok_or(minicbor::decode::Error::message(
+            "invalid map length for ContentPatch",
+        ))?;
+        let mut patch = Self::default();
+        for _ in 0..len {
+            match d.u8()? {
+                0 => patch.salt = Some(d.bytes()?.to_vec()),
+                1 => patch.replaces = Some(decode_optional_bytes(d)?),
+                2 => patch.topic_id = Some(d.bytes()?.to_vec()),
+                3 => patch.expires = Some(d.decode()?),
+                4 => patch.in_reply_to = Some(decode_optional_bytes(d)?),
+                5 => patch.extensions_set = d.decode()?,
+                6 => patch.extensions_removed = d.decode()?,
+                7 => {
+                    let array = d.array()?;
+                    patch.body = Some(match (array, d.u8()?) {
+                        (Some(2), 0) => BodyPatch::Replace(d.decode()?),
+                        (Some(5), 1) => BodyPatch::Splice {
+                            prefix: d.u64()?,
+                            suffix: d.u64()?,
+                            bytes: d.bytes()?.to_vec(),
+                            base_fingerprint: d.bytes()?.try_into().map_err(|_| {
+                                minicbor::decode::Error::message("invalid body fingerprint")
+                            })?,
+                        },
+                        _ => return Err(minicbor::decode::Error::message("invalid body patch")),
+                    });
+                }
+                _ => {
+                    return Err(minicbor::decode::Error::message(
+                        "unknown ContentPatch field",
+                    ))
+                }
+            }
+        }
+        Ok(patch)
+    }
+}
+
+fn decode_optional_bytes(
+    d: &mut minicbor::Decoder<'_>,
+) -> Result<Option<Vec<u8>>, minicbor::decode::Error> {
+    Ok(d.decode::<Option<&ByteSlice>>()?
+        .map(|bytes| bytes.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::content_container::Salt;
+
+    use super::*;
+
+    #[test]
+    fn one_word_edit() {
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(40);
+        let original = MimiContent::from_markdown(&text, Salt::from([1; 16]));
+        let edited = MimiContent {
+            replaces: Some(vec![0x01; 32]),
+            ..MimiContent::from_markdown(&text.replacen("lazy", "sleepy", 1), Salt::from([2; 16]))
+        };
+
+        let patch = original.make_patch(&edited);
+        let encoded = patch.serialize().unwrap();
+        assert!(encoded.len() < 150, "{}", encoded.len());
+        assert!(edited.serialize().unwrap().len() > 1700);
+
+        let decoded = ContentPatch::deserialize(&encoded).unwrap();
+        assert_eq!(decoded, patch);
+        assert_eq!(original.apply_patch(&decoded).unwrap(), edited);
+        assert!(original.make_patch(&original).is_empty());
+
+        // A splice does not apply to a different body of the same length
+        let other =
+            MimiContent::from_markdown(&text.replacen("quick", "slick", 1), Salt::from([1; 16]));
+        assert!(matches!(
+            other.apply_patch(&decoded),
+            Err(Error::PatchMismatch)
+        ));
+    }
+
+    #[test]
+    fn structural_edit() {
+        let original = MimiContent::from_draft_vector("original_message").unwrap();
+        let mut edited = MimiContent::from_draft_vector("multipart").unwrap();
+        edited.extensions.remove(&ExtensionName::Number(2));
+        edited.set_extension_text("x-edited", true);
+        edited.set_ttl(3600);
+
+        let patch =
+            ContentPatch::deserialize(&original.make_patch(&edited).serialize().unwrap()).unwrap();
+        assert_eq!(original.apply_patch(&patch).unwrap(), edited);
+
+        // Replacements apply to any base and only touch what they change
+        let mut other = MimiContent::from_draft_vector("reply").unwrap();
+        other.set_extension_text("x-other", 1);
+        let patched = other.apply_patch(&patch).unwrap();
+        assert_eq!(patched.nested_part, edited.nested_part);
+        assert_eq!(patched.expires, edited.expires);
+        let text = |key: &str| ExtensionName::Text(key.to_owned());
+        assert_eq!(patched.get_extension(&text("x-edited")), Some(&true.into()));
+        assert_eq!(patched.get_extension(&text("x-other")), Some(&1.into()));
+        assert_eq!(patched.in_reply_to, other.in_reply_to);
+
+        // A splice does not apply to a message without a single part
+        let reply = MimiContent::from_draft_vector("reply").unwrap();
+        assert!(matches!(
+            MimiContent::from_draft_vector("attachments")
+                .unwrap()
+                .apply_patch(&original.make_patch(&reply)),
+            Err(Error::PatchMismatch)
+        ));
+    }
+}
@@ -0,0 +1,196 @@
+// SPDX-FileCopyrightText: 2026 Phoenix R&D GmbH <hello@phnx.im>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Compact storage format based on postcard.
+//!
+//! Postcard is not self-describing, so values whose type the CBOR wire format
+//! tells apart by major type, i.e. extension keys and values, are stored as
+//! explicitly tagged enums. Everything else uses the typed serde
+//! implementations of the content types.
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+use ::serde::{Deserialize, Serialize};
+
+use crate::{
+    cbor,
+    content_container::{Expiration, ExtensionName},
+    Error, MimiContent, NestedPart, Result,
+};
+
+#[derive(Serialize, Deserialize)]
+struct StoredContent<'a> {
+    salt: Cow<'a, [u8]>,
+    replaces: Option<Cow<'a, [u8]>>,
+    topic_id: Cow<'a, [u8]>,
+    expires: Option<Expiration>,
+    in_reply_to: Option<Cow<'a, [u8]>>,
+    extensions: Vec<(StoredExtensionName<'a>, StoredValue<'a>)>,
+    nested_part: Cow<'a, NestedPart>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum StoredExtensionName<'a> {
+    Text(Cow<'a, str>),
+    Number(u64),
+}
+
+#[derive(Serialize, Deserialize)]
+enum StoredValue<'a> {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(Cow<'a, str>),
+    Bytes(Cow<'a, [u8]>),
+    Array(Vec<StoredValue<'a>>),
+    Map(Vec<(Cow<'a, str>, StoredValue<'a>)>),
+    Null,
+}
+
+impl<'a> From<&'a ExtensionName> for StoredExtensionName<'a> {
+    fn from(name: &'a ExtensionName) -> Self {
+        match name {
+            ExtensionName::Text(text) => Self::Text(Cow::Borrowed(text)),
+            ExtensionName::Number(number) => Self::Number(*number),
+        }
+    }
+}
+
+impl From<StoredExtensionName<'_>> for ExtensionName {
+    fn from(name: StoredExtensionName<'_>) -> Self {
+        match name {
+            StoredExtensionName::Text(text) => Self::Text(text.into_owned()),
+            StoredExtensionName::Number(number) => Self::Number(number),
+        }
+    }
+}
+
+impl<'a> From<&'a cbor::Value> for StoredValue<'a> {
+    fn from(value: &'a cbor::Value) -> Self {
+        match value {
+            cbor::Value::Bool(value) => Self::Bool(*value),
+            cbor::Value::Int(value) => Self::Int(*value),
+            cbor::Value::Float(value) => Self::Float(*value),
+            cbor::Value::Text(text) => Self::Text(Cow::Borrowed(text)),
+            cbor::Value::Bytes(bytes) => Self::Bytes(Cow::Borrowed(bytes)),
+            cbor::Value::Array(values) => Self::Array(values.iter().map(Self::from).collect()),
+            cbor::Value::Map(entries) => Self::Map(
+                entries
+                    .iter()
+                    .map(|(key, value)| (Cow::Borrowed(key.as_str()), value.into()))
+                    .collect(),
+            ),
+            cbor::Value::Null => Self::Null,
+        }
+    }
+}
+
+impl From<StoredValue<'_>> for cbor::Value {
+    fn from(value: StoredValue<'_>) -> Self {
+        match value {
+            StoredValue::Bool(value) => Self::Bool(value),
+            StoredValue::Int(value) => Self::Int(value),
+            StoredValue::Float(value) => Self::Float(value),
+            StoredValue::Text(text) => Self::Text(text.into_owned().into()),
+            StoredValue::Bytes(bytes) => Self::Bytes(bytes.into_owned()),
+            StoredValue::Array(values) => Self::Array(values.into_iter().map(Self::from).collect()),
+            StoredValue::Map(entries) => Self::Map(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key.into_owned(), value.into()))
+                    .collect::<BTreeMap<_, _>>(),
+            ),
+            StoredValue::Null => Self::Null,
+        }
+    }
+}
+
+impl MimiContent {
+    /// Encodes the message in a compact format for local storage, e.g. in a
+    /// database.
+    ///
+    /// The format is not the MIMI wire format and is not interoperable with
+    /// other implementations; use [`MimiContent::serialize`] for messages
+    /// that leave the device or server. [`MimiContent::from_postcard`]
+    /// restores the message losslessly.
+    pub fn to_postcard(&self) -> Result<Vec<u8>> {
+        let stored = StoredContent {
+            salt: Cow::Borrowed(&self.salt),
+            replaces: self.replaces.as_deref().map(Cow::Borrowed),
+            topic_id: Cow::Borrowed(&self.topic_id),
+            expires: self.expires.clone(),
+            in_reply_to: self.in_reply_to.as_deref().map(Cow::Borrowed),
+            extensions: self
+                .extensions
+                .iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect(),
+            nested_part: Cow::Borrowed(&self.nested_part),
+        };
+        ::postcard::to_allocvec(&stored).map_err(Error::Storage)
+    }
+
+    /// Decodes a message encoded with [`MimiContent::to_postcard`].
+    pub fn from_postcard(input: &[u8]) -> Result<Self> {
+        let stored: StoredContent = ::postcard::from_bytes(input).map_err(Error::Storage)?;
+        Ok(MimiContent {
+            salt: stored.salt.into_owned(),
+            replaces: stored.replaces.map(Cow::into_owned),
+            topic_id: stored.topic_id.into_owned(),
+            expires: stored.expires,
+            in_reply_to: stored.in_reply_to.map(Cow::into_owned),
+            extensions: stored
+                .extensions
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect(),
+            nested_part: stored.nested_part.into_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::content_container::Salt;
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for vector in crate::draft_vectors::draft_vectors() {
+            let value = MimiContent::from_draft_vector(vector.name).unwrap();
+            let stored = value.to_postcard().unwrap();
+            assert_eq!(
+                MimiContent::from_postcard(&stored).unwrap(),
+                value,
+                "{}",
+                vector.name
+            );
+        }
+
+        let value = MimiContent::from_markdown("Hi", Salt::from([0; 16]))
+            .with_extension_number(1, cbor::Value::Text("1".into()))
+            .with_extension_text("1", cbor::Value::Int(-1))
+            .with_extension_text("float", cbor::Value::Float(0.5))
+            .with_extension_text("bytes", cbor::Value::Bytes(vec![0, 1, 2]))
+            .with_extension_text("null", cbor::Value::Null)
+            .with_extension_text(
+                "nested",
+                cbor::Value::Array(vec![
+                    cbor::Value::Bool(true),
+                    cbor::Value::Map(BTreeMap::from([(
+                        "key".to_owned(),
+                        cbor::Value::Text("value".into()),
+                    )])),
+                ]),
+            );
+        let stored = value.to_postcard().unwrap();
+        assert_eq!(MimiContent::from_postcard(&stored).unwrap(), value);
+
+        assert!(matches!(
+            MimiContent::from_postcard(&stored[..stored.len() - 1]),
+            Err(Error::Storage(_))
+        ));
+    }
+}
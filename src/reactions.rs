@@ -0,0 +1,335 @@
+// SPDX-FileCopyrightText: 2026 Phoenix R&D GmbH <hello@phnx.im>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use std::collections::BTreeMap;
+
+use crate::{content_container::MessageId, MimiContent};
+
+/// Counts the reactions to messages by emoji.
+///
+/// Reactions are recognized with [`MimiContent::reaction_emoji`] and
+/// attributed to the message in their `in_reply_to`. A message replacing a
+/// reaction, e.g. with a null part, withdraws that reaction; if it is a
+/// reaction itself, it counts in place of the replaced one. Only the sender
+/// of a reaction can replace it.
+///
+/// Messages may be added in any order. A replacement added before the
+/// reaction it replaces is kept until that reaction arrives, so the counts
+/// do not depend on the order of delivery. Withdrawn reactions, and
+/// replacements of messages that never arrive or are not reactions, are kept
+/// for the lifetime of the aggregator, so it should not outlive the room
+/// history it aggregates.
+#[derive(Debug, Clone, Default)]
+pub struct ReactionAggregator {
+    /// Reactions, by ID of the reaction. Withdrawn reactions are kept so
+    /// that replacements by other senders are still ignored.
+    reactions: BTreeMap<MessageId, Reaction>,
+    /// Replacements whose replaced reaction has not been added yet, by ID of
+    /// the replaced reaction and sender of the replacement.
+    tombstones: BTreeMap<(MessageId, Vec<u8>), Tombstone>,
+}
+
+#[derive(Debug, Clone)]
+struct Reaction {
+    sender: Vec<u8>,
+    target: MessageId,
+    emoji: String,
+    withdrawn: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Tombstone {
+    /// ID of the replacement
+    id: MessageId,
+    /// Emoji of the replacement if it is a reaction that takes its target
+    /// from the replaced one.
+    emoji: Option<String>,
+}
+
+impl ReactionAggregator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the message `content` with the ID `id`, sent by `sender`.
+    ///
+    /// Returns whether the counts changed. Messages other than reactions and
+    /// replacements of reactions are ignored, as are reactions whose emoji is
+    /// not UTF-8 or whose target is not a valid message ID, and replacements
+    /// by anyone but the sender of the reaction.
+    pub fn add(&mut self, id: MessageId, sender: &[u8], content: &MimiContent) -> bool {
+        let emoji = content.reaction_emoji().and_then(Result::ok);
+        let mut target = content
+            .in_reply_to
+            .as_deref()
+            .and_then(|in_reply_to| MessageId::try_from(in_reply_to).ok());
+
+        let mut changed = false;
+        if let Some(replaces) = content
+            .replaces
+            .as_deref()
+            .and_then(|replaces| MessageId::try_from(replaces).ok())
+        {
+            match self.reactions.get_mut(&replaces) {
+                Some(reaction) if reaction.sender == sender => {
+                    target = target.or(Some(reaction.target));
+                    changed = !reaction.withdrawn;
+                    reaction.withdrawn = true;
+                }
+                Some(_) => return false,
+                None if content.is_tombstone() || emoji.is_some() => {
+                    let tombstone = Tombstone {
+                        id,
+                        emoji: emoji.clone().filter(|_| target.is_none()),
+                    };
+                    self.tombstones
+                        .insert((replaces, sender.to_vec()), tombstone);
+                }
+                None => {}
+            }
+        }
+
+        // Replacements of this message that arrived before it
+        let tombstones: Vec<_> = self
+            .tombstones
+            .range((id, Vec::new())..)
+            .take_while(|((replaced, _), _)| *replaced == id)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut withdrawn = None;
+        for key in tombstones {
+            let tombstone = self.tombstones.remove(&key).expect("key from range");
+            if key.1 == sender {
+                withdrawn = Some(tombstone);
+            } else if target.is_some() && emoji.is_some() {
+                // Added in order, the replacement would have been ignored
+                changed |= self.reactions.remove(&tombstone.id).is_some();
+            }
+        }
+
+        let (Some(target), Some(emoji)) = (target, emoji) else {
+            return changed;
+        };
+        self.insert(id, sender, target, emoji, withdrawn.is_some());
+        match withdrawn {
+            Some(Tombstone {
+                id,
+                emoji: Some(emoji),
+            }) => {
+                self.insert(id, sender, target, emoji, false);
+                true
+            }
+            Some(_) => changed,
+            None => true,
+        }
+    }
+
+    fn insert(
+        &mut self,
+        id: MessageId,
+        sender: &[u8],
+        target: MessageId,
+        emoji: String,
+        withdrawn: bool,
+    ) {
+        self.reactions.insert(
+            id,
+            Reaction {
+                sender: sender.to_vec(),
+                target,
+                emoji,
+                withdrawn,
+            },
+        );
+    }
+
+    /// Returns the number of reactions by target message and emoji.
+    pub fn counts(&self) -> BTreeMap<(MessageId, String), usize> {
+        let mut counts = BTreeMap::new();
+        for Reaction { target, emoji, .. } in self.counted() {
+            *counts.entry((*target, emoji.clone())).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Returns the number of reactions to `target` by emoji.
+    pub fn counts_for(&self, target: &MessageId) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for Reaction { emoji, .. } in self.counted().filter(|reaction| reaction.target == *target) {
+            *counts.entry(emoji.clone()).or_default() += 1;
+        }
+        counts
+    }
+
+    fn counted(&self) -> impl Iterator<Item = &Reaction> {
+        self.reactions
+            .values()
+            .filter(|reaction| !reaction.withdrawn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        content_container::{Salt, MESSAGE_ID_LEN, MESSAGE_ID_VERSION},
+        Disposition, NestedPart,
+    };
+
+    use super::*;
+
+    const ALICE: &[u8] = b"mimi://example.com/u/alice-smith";
+    const BOB: &[u8] = b"mimi://example.com/u/bob-jones";
+
+    fn message_id(byte: u8) -> MessageId {
+        let mut id = vec![byte; MESSAGE_ID_LEN];
+        id[0] = MESSAGE_ID_VERSION;
+        MessageId::try_from(id).unwrap()
+    }
+
+    fn reaction(target: MessageId, emoji: &str) -> MimiContent {
+        MimiContent {
+            in_reply_to: Some(target.into()),
+            nested_part: NestedPart::SinglePart {
+                disposition: Disposition::Reaction,
+                language: "".to_owned(),
+                content_type: "text/plain;charset=utf-8".to_owned(),
+                content: emoji.as_bytes().to_vec(),
+            },
+            ..MimiContent::from_markdown("", Salt::from([0; 16]))
+        }
+    }
+
+    #[test]
+    fn aggregate() {
+        let target = message_id(0xaa);
+        let other_target = message_id(0xbb);
+        let mut aggregator = ReactionAggregator::new();
+
+        assert!(aggregator.add(message_id(1), ALICE, &reaction(target, "❤")));
+        assert!(aggregator.add(message_id(2), ALICE, &reaction(target, "❤")));
+        assert!(aggregator.add(message_id(3), ALICE, &reaction(target, "👍")));
+        assert!(aggregator.add(message_id(4), ALICE, &reaction(other_target, "👍")));
+        assert!(!aggregator.add(
+            message_id(5),
+            ALICE,
+            &MimiContent::from_markdown("Hi", Salt::from([0; 16]))
+        ));
+        assert_eq!(
+            aggregator.counts_for(&target),
+            BTreeMap::from([("❤".to_owned(), 2), ("👍".to_owned(), 1)])
+        );
+
+        // Withdraw a heart and change the thumbs up into a heart
+        let removal = MimiContent {
+            replaces: Some(message_id(2).into()),
+            ..Default::default()
+        };
+        assert!(aggregator.add(message_id(6), ALICE, &removal));
+        let edit = MimiContent {
+            replaces: Some(message_id(3).into()),
+            in_reply_to: None,
+            ..reaction(target, "❤")
+        };
+        assert!(aggregator.add(message_id(7), ALICE, &edit));
+        assert!(!aggregator.add(message_id(8), ALICE, &removal));
+
+        assert_eq!(
+            aggregator.counts(),
+            BTreeMap::from([
+                ((target, "❤".to_owned()), 2),
+                ((other_target, "👍".to_owned()), 1),
+            ])
+        );
+    }
+
+    #[test]
+    fn replacements_before_reactions() {
+        let target = message_id(0xaa);
+        let removal = MimiContent::reaction_removal(message_id(1), Salt::from([0; 16]));
+        let edit = MimiContent {
+            replaces: Some(message_id(2).into()),
+            in_reply_to: None,
+            ..reaction(target, "👍")
+        };
+
+        let mut in_order = ReactionAggregator::new();
+        in_order.add(message_id(1), ALICE, &reaction(target, "❤"));
+        in_order.add(message_id(2), ALICE, &reaction(target, "❤"));
+        in_order.add(message_id(3), ALICE, &removal);
+        in_order.add(message_id(4), ALICE, &edit);
+
+        let mut reversed = ReactionAggregator::new();
+        assert!(!reversed.add(message_id(4), ALICE, &edit));
+        assert!(!reversed.add(message_id(3), ALICE, &removal));
+        assert!(reversed.add(message_id(2), ALICE, &reaction(target, "❤")));
+        assert!(!reversed.add(message_id(1), ALICE, &reaction(target, "❤")));
+
+        let expected = BTreeMap::from([((target, "👍".to_owned()), 1)]);
+        assert_eq!(in_order.counts(), expected);
+        assert_eq!(reversed.counts(), expected);
+    }
+
+    #[test]
+    fn replacements_by_other_senders() {
+        let target = message_id(0xaa);
+        let removal = MimiContent::reaction_removal(message_id(1), Salt::from([0; 16]));
+        let expected = BTreeMap::from([("❤".to_owned(), 1)]);
+
+        let mut aggregator = ReactionAggregator::new();
+        aggregator.add(message_id(1), ALICE, &reaction(target, "❤"));
+        assert!(!aggregator.add(message_id(2), BOB, &removal));
+        assert_eq!(aggregator.counts_for(&target), expected);
+
+        let mut aggregator = ReactionAggregator::new();
+        aggregator.add(message_id(2), BOB, &removal);
+        assert!(aggregator.add(message_id(1), ALICE, &reaction(target, "❤")));
+        assert_eq!(aggregator.counts_for(&target), expected);
+    }
+
+    #[test]
+    fn early_replacements_by_several_senders() {
+        let target = message_id(0xaa);
+        let removal = MimiContent::reaction_removal(message_id(1), Salt::from([0; 16]));
+        let bob_edit = MimiContent {
+            replaces: Some(message_id(1).into()),
+            ..reaction(target, "👍")
+        };
+        let messages = [
+            (message_id(1), ALICE, reaction(target, "❤")),
+            (message_id(2), ALICE, removal),
+            (message_id(3), BOB, bob_edit),
+        ];
+
+        // Every order gives the counts of the delivery order
+        let mut in_order = ReactionAggregator::new();
+        for (id, sender, content) in &messages {
+            in_order.add(*id, sender, content);
+        }
+        assert!(in_order.counts().is_empty());
+        for order in [[1, 2, 0], [2, 1, 0], [1, 0, 2], [2, 0, 1], [0, 2, 1]] {
+            let mut aggregator = ReactionAggregator::new();
+            for index in order {
+                let (id, sender, content) = &messages[index];
+                aggregator.add(*id, sender, content);
+            }
+            assert_eq!(aggregator.counts(), in_order.counts(), "{order:?}");
+        }
+    }
+
+    #[test]
+    fn withdrawn_reaction_still_replaces() {
+        let target = message_id(0xaa);
+        let edit = MimiContent {
+            replaces: Some(message_id(1).into()),
+            ..reaction(target, "👍")
+        };
+        let removal = MimiContent::reaction_removal(message_id(2), Salt::from([0; 16]));
+
+        let mut aggregator = ReactionAggregator::new();
+        aggregator.add(message_id(1), ALICE, &reaction(target, "❤"));
+        aggregator.add(message_id(3), ALICE, &removal);
+        assert!(aggregator.add(message_id(2), ALICE, &edit));
+        assert!(aggregator.counts().is_empty());
+    }
+}
@@ -14,8 +14,9 @@ use crate::{
     cbor::Value,
     content_container::{
         Disposition, EncryptionAlgorithm, Expiration, ExtensionName, HashAlgorithm, MimiContent,
-        NestedPart, PartSemantics,
+        NestedPart, NestedPartRef, PartSemantics,
     },
+    MessageStatus, MessageStatusReport, PerMessageStatus,
 };
 
 impl Serialize for Value {
@@ -264,9 +265,134 @@ impl<'de> Deserialize<'de> for Expiration {
 }
 
 impl Serialize for NestedPart {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.as_part_ref().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for NestedPart {
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NestedPartVisitor;
+
+        impl<'de> Visitor<'de> for NestedPartVisitor {
+            type Value = NestedPart;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a MIMI NestedPart array")
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<NestedPart, A::Error> {
+                let disposition: Disposition = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let language: String = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                let discriminant: u8 = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+                match discriminant {
+                    0 => Ok(NestedPart::NullPart {
+                        disposition,
+                        language,
+                    }),
+                    1 => {
+                        let content_type: String = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(3, &self))?;
+                        let content: serde_bytes::ByteBuf = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(4, &self))?;
+                        Ok(NestedPart::SinglePart {
+                            disposition,
+                            language,
+                            content_type,
+                            content: content.into_vec(),
+                        })
+                    }
+                    2 => {
+                        let content_type: String = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(3, &self))?;
+                        let url: String = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(4, &self))?;
+                        let expires: u32 = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(5, &self))?;
+                        let size: u64 = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(6, &self))?;
+                        let enc_alg: EncryptionAlgorithm = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(7, &self))?;
+                        let key: serde_bytes::ByteBuf = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(8, &self))?;
+                        let nonce: serde_bytes::ByteBuf = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(9, &self))?;
+                        let aad: serde_bytes::ByteBuf = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(10, &self))?;
+                        let hash_alg: HashAlgorithm = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(11, &self))?;
+                        let content_hash: serde_bytes::ByteBuf = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(12, &self))?;
+                        let description: String = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(13, &self))?;
+                        let filename: String = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(14, &self))?;
+                        Ok(NestedPart::ExternalPart {
+                            disposition,
+                            language,
+                            content_type,
+                            url,
+                            expires,
+                            size,
+                            enc_alg,
+                            key: key.into_vec(),
+                            nonce: nonce.into_vec(),
+                            aad: aad.into_vec(),
+                            hash_alg,
+                            content_hash: content_hash.into_vec(),
+                            description,
+                            filename,
+                        })
+                    }
+                    3 => {
+                        let part_semantics: PartSemantics = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(3, &self))?;
+                        let parts: Vec<NestedPart> = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(4, &self))?;
+                        Ok(NestedPart::MultiPart {
+                            disposition,
+                            language,
+                            part_semantics,
+                            parts,
+                        })
+                    }
+                    _ => Err(de::Error::custom(format!(
+                        "invalid discriminant {discriminant} for NestedPart"
+                    ))),
+                }
+            }
+        }
+
+        deserializer.deserialize_seq(NestedPartVisitor)
+    }
+}
+
+impl Serialize for NestedPartRef<'_> {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         match self {
-            NestedPart::NullPart {
+            NestedPartRef::NullPart {
                 disposition,
                 language,
             } => {
@@ -276,7 +402,7 @@ impl Serialize for NestedPart {
                 seq.serialize_element(&0u8)?;
                 seq.end()
             }
-            NestedPart::SinglePart {
+            NestedPartRef::SinglePart {
                 disposition,
                 language,
                 content_type,
@@ -290,7 +416,7 @@ impl Serialize for NestedPart {
                 seq.serialize_element(serde_bytes::Bytes::new(content))?;
                 seq.end()
             }
-            NestedPart::ExternalPart {
+            NestedPartRef::ExternalPart {
                 disposition,
                 language,
                 content_type,
@@ -324,7 +450,7 @@ impl Serialize for NestedPart {
                 seq.serialize_element(filename)?;
                 seq.end()
             }
-            NestedPart::MultiPart {
+            NestedPartRef::MultiPart {
                 disposition,
                 language,
                 part_semantics,
@@ -342,51 +468,54 @@ impl Serialize for NestedPart {
     }
 }
 
-impl<'de> Deserialize<'de> for NestedPart {
+impl<'de> Deserialize<'de> for NestedPartRef<'de> {
     fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        struct NestedPartVisitor;
+        struct NestedPartRefVisitor;
 
-        impl<'de> Visitor<'de> for NestedPartVisitor {
-            type Value = NestedPart;
+        impl<'de> Visitor<'de> for NestedPartRefVisitor {
+            type Value = NestedPartRef<'de>;
 
             fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
                 f.write_str("a MIMI NestedPart array")
             }
 
-            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<NestedPart, A::Error> {
+            fn visit_seq<A: de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<NestedPartRef<'de>, A::Error> {
                 let disposition: Disposition = seq
                     .next_element()?
                     .ok_or_else(|| de::Error::invalid_length(0, &self))?;
-                let language: String = seq
+                let language: &'de str = seq
                     .next_element()?
                     .ok_or_else(|| de::Error::invalid_length(1, &self))?;
                 let discriminant: u8 = seq
                     .next_element()?
                     .ok_or_else(|| de::Error::invalid_length(2, &self))?;
                 match discriminant {
-                    0 => Ok(NestedPart::NullPart {
+                    0 => Ok(NestedPartRef::NullPart {
                         disposition,
                         language,
                     }),
                     1 => {
-                        let content_type: String = seq
+                        let content_type: &'de str = seq
                             .next_element()?
                             .ok_or_else(|| de::Error::invalid_length(3, &self))?;
-                        let content: serde_bytes::ByteBuf = seq
+                        let content: &'de serde_bytes::Bytes = seq
                             .next_element()?
                             .ok_or_else(|| de::Error::invalid_length(4, &self))?;
-                        Ok(NestedPart::SinglePart {
+                        Ok(NestedPartRef::SinglePart {
                             disposition,
                             language,
                             content_type,
-                            content: content.into_vec(),
+                            content,
                         })
                     }
                     2 => {
-                        let content_type: String = seq
+                        let content_type: &'de str = seq
                             .next_element()?
                             .ok_or_else(|| de::Error::invalid_length(3, &self))?;
-                        let url: String = seq
+                        let url: &'de str = seq
                             .next_element()?
                             .ok_or_else(|| de::Error::invalid_length(4, &self))?;
                         let expires: u32 = seq
@@ -398,28 +527,28 @@ impl<'de> Deserialize<'de> for NestedPart {
                         let enc_alg: EncryptionAlgorithm = seq
                             .next_element()?
                             .ok_or_else(|| de::Error::invalid_length(7, &self))?;
-                        let key: serde_bytes::ByteBuf = seq
+                        let key: &'de serde_bytes::Bytes = seq
                             .next_element()?
                             .ok_or_else(|| de::Error::invalid_length(8, &self))?;
-                        let nonce: serde_bytes::ByteBuf = seq
+                        let nonce: &'de serde_bytes::Bytes = seq
                             .next_element()?
                             .ok_or_else(|| de::Error::invalid_length(9, &self))?;
-                        let aad: serde_bytes::ByteBuf = seq
+                        let aad: &'de serde_bytes::Bytes = seq
                             .next_element()?
                             .ok_or_else(|| de::Error::invalid_length(10, &self))?;
                         let hash_alg: HashAlgorithm = seq
                             .next_element()?
                             .ok_or_else(|| de::Error::invalid_length(11, &self))?;
-                        let content_hash: serde_bytes::ByteBuf = seq
+                        let content_hash: &'de serde_bytes::Bytes = seq
                             .next_element()?
                             .ok_or_else(|| de::Error::invalid_length(12, &self))?;
-                        let description: String = seq
+                        let description: &'de str = seq
                             .next_element()?
                             .ok_or_else(|| de::Error::invalid_length(13, &self))?;
-                        let filename: String = seq
+                        let filename: &'de str = seq
                             .next_element()?
                             .ok_or_else(|| de::Error::invalid_length(14, &self))?;
-                        Ok(NestedPart::ExternalPart {
+                        Ok(NestedPartRef::ExternalPart {
                             disposition,
                             language,
                             content_type,
@@ -427,11 +556,11 @@ impl<'de> Deserialize<'de> for NestedPart {
                             expires,
                             size,
                             enc_alg,
-                            key: key.into_vec(),
-                            nonce: nonce.into_vec(),
-                            aad: aad.into_vec(),
+                            key,
+                            nonce,
+                            aad,
                             hash_alg,
-                            content_hash: content_hash.into_vec(),
+                            content_hash,
                             description,
                             filename,
                         })
@@ -440,10 +569,10 @@ impl<'de> Deserialize<'de> for NestedPart {
                         let part_semantics: PartSemantics = seq
                             .next_element()?
                             .ok_or_else(|| de::Error::invalid_length(3, &self))?;
-                        let parts: Vec<NestedPart> = seq
+                        let parts: Vec<NestedPartRef<'de>> = seq
                             .next_element()?
                             .ok_or_else(|| de::Error::invalid_length(4, &self))?;
-                        Ok(NestedPart::MultiPart {
+                        Ok(NestedPartRef::MultiPart {
                             disposition,
                             language,
                             part_semantics,
@@ -457,7 +586,64 @@ impl<'de> Deserialize<'de> for NestedPart {
             }
         }
 
-        deserializer.deserialize_seq(NestedPartVisitor)
+        deserializer.deserialize_seq(NestedPartRefVisitor)
+    }
+}
+
+impl Serialize for MessageStatusReport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.statuses.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for MessageStatusReport {
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let report = MessageStatusReport {
+            statuses: Deserialize::deserialize(deserializer)?,
+        };
+        report.validate().map_err(de::Error::custom)?;
+        Ok(report)
+    }
+}
+
+impl Serialize for PerMessageStatus {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(2))?;
+        seq.serialize_element(serde_bytes::Bytes::new(&self.mimi_id))?;
+        seq.serialize_element(&self.status)?;
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for PerMessageStatus {
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PerMessageStatusVisitor;
+
+        impl<'de> Visitor<'de> for PerMessageStatusVisitor {
+            type Value = PerMessageStatus;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a 2-element array [bytes, u8]")
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<PerMessageStatus, A::Error> {
+                let mimi_id: serde_bytes::ByteBuf = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let status = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                Ok(PerMessageStatus {
+                    mimi_id: mimi_id.into_vec(),
+                    status,
+                })
+            }
+        }
+
+        deserializer.deserialize_seq(PerMessageStatusVisitor)
     }
 }
 
@@ -489,3 +675,4 @@ impl_serde_num_enum!(HashAlgorithm, u8);
 impl_serde_num_enum!(EncryptionAlgorithm, u16);
 impl_serde_num_enum!(Disposition, u8);
 impl_serde_num_enum!(PartSemantics, u8);
+impl_serde_num_enum!(MessageStatus, u8);